@@ -0,0 +1,30 @@
+//! Compare building the index of the complex fixture with parsing it fully. Run with
+//! `cargo bench -p maestro_ngs_application`.
+
+use maestro_ngs_application::Loader;
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 20;
+
+fn time(f: impl Fn()) -> Duration {
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        f();
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    d.push("resources/test/Application_Complex.eap");
+    let doc = std::fs::read_to_string(d).unwrap();
+    let loader = Loader::new(&doc).unwrap();
+
+    let index = time(|| {
+        loader.build_index().unwrap();
+    });
+    let full = time(|| {
+        loader.build_application().unwrap();
+    });
+    println!("index {:?}, full parse {:?}", index, full);
+}
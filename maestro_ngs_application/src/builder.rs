@@ -0,0 +1,267 @@
+use crate::{
+    assign_stable_ids, number_instructions, Instruction, Layout, Method, SavedApplication,
+    Variable, VariablesPool,
+};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Assemble a saved application without an exported XML document, for example to generate a
+/// protocol in code and run it through the emulator.
+///
+/// # Example
+///
+/// ```
+/// use maestro_ngs_application::{Instruction, Command, SavedApplicationBuilder};
+///
+/// let main = uuid::Uuid::from_u128(1);
+/// let layout = uuid::Uuid::from_u128(2);
+/// let rem = Instruction::new(Command::REM {
+///     comment: "Hello".to_string(),
+/// });
+/// let app = SavedApplicationBuilder::new()
+///     .add_layout(maestro_ngs_application::Layout::new(layout, "MainLayout"))
+///     .add_method(main, "Main", layout, vec![rem])
+///     .set_start_method(main)
+///     .build()
+///     .unwrap();
+/// assert_eq!(app.start_method_name(), Some("Main"));
+/// ```
+#[derive(Default)]
+pub struct SavedApplicationBuilder {
+    start_method: Option<Uuid>,
+    global_variables: HashMap<Uuid, Variable>,
+    layouts: Vec<Layout>,
+    methods: Vec<Method>,
+    local_variables: Vec<(Uuid, Variable)>,
+}
+
+impl SavedApplicationBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_global(mut self, variable: Variable) -> Self {
+        self.global_variables.insert(variable.id, variable);
+        self
+    }
+
+    pub fn add_layout(mut self, layout: Layout) -> Self {
+        self.layouts.push(layout);
+        self
+    }
+
+    /// Add a method without local variables or parameters
+    pub fn add_method(
+        self,
+        id: Uuid,
+        designation: &str,
+        layout_id: Uuid,
+        instructions: Vec<Instruction>,
+    ) -> Self {
+        self.add_method_with_parameters(id, designation, layout_id, Vec::new(), instructions)
+    }
+
+    /// Add a method without local variables. Each parameter holds the value the method sees if
+    /// a Run Method call does not pass one.
+    pub fn add_method_with_parameters(
+        mut self,
+        id: Uuid,
+        designation: &str,
+        layout_id: Uuid,
+        parameters: Vec<Variable>,
+        mut instructions: Vec<Instruction>,
+    ) -> Self {
+        assign_stable_ids(&mut instructions);
+        number_instructions(&mut instructions);
+        let mut parameter_pool = empty_pool(format!("{}:Parameters", designation));
+        for parameter in parameters {
+            parameter_pool.variables.insert(parameter.id, parameter);
+        }
+        self.methods.push(Method {
+            designation: designation.to_string(),
+            id,
+            layout_id,
+            local_variables_pool: empty_pool(format!("{}:LOCAL Variables", designation)),
+            parameters: parameter_pool,
+            declared_instructions: instructions.len(),
+            instructions,
+        });
+        self
+    }
+
+    /// Add a local variable to a method, which may be added before or after
+    pub fn add_local(mut self, method_id: Uuid, variable: Variable) -> Self {
+        self.local_variables.push((method_id, variable));
+        self
+    }
+
+    pub fn set_start_method(mut self, id: Uuid) -> Self {
+        self.start_method = Some(id);
+        self
+    }
+
+    /// The application, if the start method, the layout of every method and the method of every
+    /// local variable have been added
+    pub fn build(mut self) -> Result<SavedApplication, BuilderError> {
+        let start_method = self.start_method.ok_or(BuilderError::NoStartMethod)?;
+        let mut app = SavedApplication {
+            start_method,
+            global_variables: self.global_variables,
+            layouts: HashMap::new(),
+            methods: HashMap::new(),
+            method_order: Vec::new(),
+        };
+        for layout in self.layouts {
+            app.add_layout(layout);
+        }
+        for (method_id, variable) in self.local_variables {
+            let method = self
+                .methods
+                .iter_mut()
+                .find(|m| m.id == method_id)
+                .ok_or(BuilderError::UnknownMethod(method_id))?;
+            method
+                .local_variables_pool
+                .variables
+                .insert(variable.id, variable);
+        }
+        for method in self.methods {
+            if !app.layouts.contains_key(&method.layout_id) {
+                return Err(BuilderError::UnknownLayout(method.layout_id));
+            }
+            app.add_method(method);
+        }
+        if !app.has_method(start_method) {
+            return Err(BuilderError::UnknownStartMethod(start_method));
+        }
+        Ok(app)
+    }
+}
+
+fn empty_pool(designation: String) -> VariablesPool {
+    VariablesPool {
+        designation,
+        id: Uuid::nil(),
+        variables: HashMap::new(),
+    }
+}
+
+#[derive(Debug)]
+pub enum BuilderError {
+    NoStartMethod,
+    UnknownLayout(Uuid),
+    UnknownMethod(Uuid),
+    UnknownStartMethod(Uuid),
+}
+
+impl std::fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoStartMethod => write!(f, "no start method was set"),
+            Self::UnknownLayout(uuid) => write!(f, "unknown layout ({})", uuid),
+            Self::UnknownMethod(uuid) => write!(f, "unknown method ({})", uuid),
+            Self::UnknownStartMethod(uuid) => write!(f, "unknown start method ({})", uuid),
+        }
+    }
+}
+
+impl std::error::Error for BuilderError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Command, VariableValue};
+
+    fn rem(comment: &str) -> Instruction {
+        Instruction::new(Command::REM {
+            comment: comment.to_string(),
+        })
+    }
+
+    #[test]
+    fn build_two_instruction_method() {
+        let main = Uuid::from_u128(1);
+        let layout = Uuid::from_u128(2);
+        let count = Uuid::from_u128(3);
+        let app = SavedApplicationBuilder::new()
+            .add_global(Variable::new(count, "g_Count", VariableValue::Float(0.0)))
+            .add_layout(Layout::new(layout, "MainLayout"))
+            .add_method(main, "Main", layout, vec![rem("first"), rem("second")])
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        assert_eq!(app.start_method(), main);
+        assert_eq!(app.instruction_count(main), Some(2));
+        assert_eq!(app.layout_of_method(main), Some(layout));
+        assert_eq!(
+            app.global_variables().get(&count).unwrap().value(),
+            &VariableValue::Float(0.0)
+        );
+        assert_ne!(
+            app.instruction(main, 0).unwrap().stable_id(),
+            app.instruction(main, 1).unwrap().stable_id()
+        );
+        assert_eq!(app.instruction(main, 1).unwrap().source_index(), 2);
+    }
+
+    #[test]
+    fn add_local_before_method() {
+        let main = Uuid::from_u128(9);
+        let other = Uuid::from_u128(10);
+        let layout = Uuid::from_u128(11);
+        let index = Uuid::from_u128(12);
+        let builder = SavedApplicationBuilder::new()
+            .add_local(main, Variable::new(index, "l_Index", VariableValue::Int(1)))
+            .add_layout(Layout::new(layout, "MainLayout"))
+            .add_method(main, "Main", layout, vec![rem("only")])
+            .set_start_method(main);
+        let app = builder.build().unwrap();
+        assert!(app
+            .local_variables_of_method(main)
+            .unwrap()
+            .contains_key(&index));
+
+        let result = SavedApplicationBuilder::new()
+            .add_local(
+                other,
+                Variable::new(index, "l_Index", VariableValue::Int(1)),
+            )
+            .add_layout(Layout::new(layout, "MainLayout"))
+            .add_method(main, "Main", layout, vec![rem("only")])
+            .set_start_method(main)
+            .build();
+        assert!(matches!(result, Err(BuilderError::UnknownMethod(uuid)) if uuid == other));
+    }
+
+    #[test]
+    fn reject_unknown_start_method() {
+        let main = Uuid::from_u128(4);
+        let other = Uuid::from_u128(5);
+        let layout = Uuid::from_u128(6);
+        let builder = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout, "MainLayout"))
+            .add_method(main, "Main", layout, vec![rem("only")]);
+
+        assert!(matches!(
+            builder.set_start_method(other).build(),
+            Err(BuilderError::UnknownStartMethod(uuid)) if uuid == other
+        ));
+        assert!(matches!(
+            SavedApplicationBuilder::new().build(),
+            Err(BuilderError::NoStartMethod)
+        ));
+    }
+
+    #[test]
+    fn reject_unknown_layout() {
+        let main = Uuid::from_u128(7);
+        let layout = Uuid::from_u128(8);
+        let result = SavedApplicationBuilder::new()
+            .add_method(main, "Main", layout, vec![rem("only")])
+            .set_start_method(main)
+            .build();
+
+        assert!(matches!(result, Err(BuilderError::UnknownLayout(uuid)) if uuid == layout));
+    }
+}
@@ -1,10 +1,17 @@
+mod builder;
+mod workspace;
+
+pub use builder::{BuilderError, SavedApplicationBuilder};
 use roxmltree::{Document, Node};
-use std::{collections::HashMap};
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
+pub use workspace::Workspace;
 
 const APP: &str = "Application";
 const APP_BUILD: &str = "ExportedApplicationBuild";
 const APP_VERSION: &str = "ExportedApplicationVersion";
+const CONSUMABLE: &str = "Consumable";
+const CONSUMABLES: &str = "Consumables";
 const GLOBAL_VAR_POOL: &str = "GlobalVariablesPool";
 const INSTR_COMPARATOR: &str = "Comparator";
 const INSTR_COUNT: &str = "InstructionsCount";
@@ -28,7 +35,9 @@ const VAR_COUNT: &str = "VariablesCount";
 const VAR_DESIG: &str = "VariableDesignation";
 const VAR_ID: &str = "VariableID";
 const VAR_NUMBER_STACKED: &str = "NumberOfStackedConsumables";
+const VAR_PERMISSIBLE: &str = "PermissibleValues";
 const VAR_POOL_DESIG: &str = "VariablesPoolDesignation";
+const VAR_POOL: &str = "VariablesPool";
 const VAR_POOL_ID: &str = "VariablesPoolID";
 const VAR_THIS_DESIG: &str = "ThisDesignation";
 const VAR_VALUE: &str = "Value";
@@ -38,18 +47,30 @@ pub struct Loader<'a> {
     raw: Document<'a>,
     version: f64,
     build: u32,
+    keep_source: bool,
 }
 
 impl<'a> Loader<'a> {
-    pub fn new(instruction_text: &'a str) -> Self {
-        let raw = Document::parse(instruction_text).unwrap();
-        let version = get_float_text(&raw.root(), APP_VERSION);
-        let build = get_int_text(&raw.root(), APP_BUILD);
-        Loader {
+    /// Parse an exported application. Fails if the text is not XML or does not have the version
+    /// and build of Maestro it was exported from.
+    pub fn new(instruction_text: &'a str) -> Result<Self, LoaderError> {
+        let raw = Document::parse(instruction_text).map_err(LoaderError::XmlParse)?;
+        let version = get_float_text(&raw.root(), APP_VERSION)
+            .ok_or(LoaderError::MissingField(APP_VERSION))?;
+        let build =
+            get_int_text(&raw.root(), APP_BUILD).ok_or(LoaderError::MissingField(APP_BUILD))?;
+        Ok(Loader {
             raw,
             version,
             build,
-        }
+            keep_source: false,
+        })
+    }
+
+    /// Keep the XML of every instruction that is parsed, for showing where it came from. This is
+    /// off by default, as it copies much of the export.
+    pub fn keep_instruction_source(&mut self, keep: bool) {
+        self.keep_source = keep;
     }
 
     pub fn input_text(&self) -> &str {
@@ -64,31 +85,47 @@ impl<'a> Loader<'a> {
         self.build
     }
 
-    pub fn build_application(&self) -> SavedApplication {
-        let app = self
-            .raw
-            .descendants()
-            .find(|n| n.has_tag_name(APP))
-            .unwrap();
+    /// Fail unless the export is from a Maestro version from min to max inclusive, so an export from
+    /// a version the parser has not been tested with is rejected before it is parsed
+    pub fn require_version(&self, min: f64, max: f64) -> Result<(), LoaderError> {
+        if min <= self.version && self.version <= max {
+            Ok(())
+        } else {
+            Err(LoaderError::UnsupportedVersion {
+                found: self.version,
+                supported: (min, max),
+            })
+        }
+    }
+
+    pub fn build_application(&self) -> Result<SavedApplication, LoaderError> {
+        let app = descendant(&self.raw.root(), APP)?;
         let flat_fields = text_only_children(&app);
 
         let mut result = SavedApplication {
-            start_method: flat_fields.get(START_METHOD).unwrap().parse().unwrap(),
+            start_method: parse_field(&flat_fields, START_METHOD)?,
             global_variables: HashMap::new(),
             layouts: HashMap::new(),
             methods: HashMap::new(),
+            method_order: Vec::new(),
         };
 
+        let tip_sizes = self.build_tip_sizes()?;
+        let well_capacities = self.build_well_capacities()?;
         for c in app.children() {
             if c.has_tag_name(GLOBAL_VAR_POOL) {
-                let global_var = Self::build_variables_pool(&c.first_element_child().unwrap());
+                let global_var = Self::build_variables_pool(&first_element(&c, VAR_POOL)?)?;
                 result.set_global_variables(global_var);
             } else if c.has_tag_name(LAYOUTS) {
                 for layouts in c
                     .children()
                     .filter(|n| n.is_element() && !n.has_tag_name(LAYOUTS_COUNT))
                 {
-                    let layout_var = Self::build_layout(&layouts.first_element_child().unwrap());
+                    let mut layout_var = Self::build_layout(&first_element(&layouts, VAR_POOL)?)?;
+                    for location in layout_var.positions.values_mut() {
+                        location.tip_size = tip_sizes.get(&location.consumable).cloned();
+                        location.well_capacity = well_capacities.get(&location.consumable).cloned();
+                    }
                     result.add_layout(layout_var);
                 }
             } else if c.has_tag_name(METHODS) {
@@ -96,114 +133,267 @@ impl<'a> Loader<'a> {
                     .children()
                     .filter(|n| n.is_element() && !n.has_tag_name(METHODS_COUNT))
                 {
-                    let method = Self::build_method(&method_nodes);
+                    let method = Self::build_method(&method_nodes, self.keep_source)?;
                     result.add_method(method);
                 }
             }
         }
-        result
+        Ok(result)
+    }
+
+    /// Like `build_application`, but the export is first checked for inconsistencies, such as a
+    /// method whose instruction count does not match its instructions after a crash during save.
+    pub fn try_build_application(&self) -> Result<SavedApplication, LoaderError> {
+        let app = self.build_application()?;
+        for id in &app.method_order {
+            check_instruction_count(&app.methods[id])?;
+        }
+        Ok(app)
+    }
+
+    // The tip size of each tip box consumable that states a single size in its name
+    fn build_tip_sizes(&self) -> Result<HashMap<Uuid, f64>, LoaderError> {
+        let mut tip_sizes = HashMap::new();
+        let consumables = match self.raw.descendants().find(|n| n.has_tag_name(CONSUMABLES)) {
+            Some(node) => node,
+            None => return Ok(tip_sizes),
+        };
+        for c in consumables
+            .descendants()
+            .filter(|n| n.has_tag_name(CONSUMABLE))
+        {
+            let fields = text_only_children(&c);
+            if fields.get("Category") != Some(&"Tip Box") {
+                continue;
+            }
+            if let Some(size) = parse_tip_size(field(&fields, "ConsumableName")?) {
+                tip_sizes.insert(parse_field(&fields, "ConsumableID")?, size);
+            }
+        }
+        Ok(tip_sizes)
+    }
+
+    // The volume of a well of each consumable, other than tip boxes, that gives its well sizes
+    fn build_well_capacities(&self) -> Result<HashMap<Uuid, f64>, LoaderError> {
+        let mut capacities = HashMap::new();
+        let consumables = match self.raw.descendants().find(|n| n.has_tag_name(CONSUMABLES)) {
+            Some(node) => node,
+            None => return Ok(capacities),
+        };
+        for c in consumables
+            .descendants()
+            .filter(|n| n.has_tag_name(CONSUMABLE))
+        {
+            let fields = text_only_children(&c);
+            if fields.get("Category") == Some(&"Tip Box") {
+                continue;
+            }
+            if let Some(capacity) = well_capacity(&fields) {
+                capacities.insert(parse_field(&fields, "ConsumableID")?, capacity);
+            }
+        }
+        Ok(capacities)
+    }
+
+    // The method element with the ProgramID
+    fn find_method_node(&self, method_id: Uuid) -> Option<Node<'_, '_>> {
+        let app = self.raw.descendants().find(|n| n.has_tag_name(APP))?;
+        let methods = app.children().find(|n| n.has_tag_name(METHODS))?;
+        methods
+            .children()
+            .filter(|n| n.is_element() && !n.has_tag_name(METHODS_COUNT))
+            .find(|n| {
+                text_only_children(n)
+                    .get(PROGRAM_ID)
+                    .and_then(|id| id.parse::<Uuid>().ok())
+                    == Some(method_id)
+            })
+    }
+
+    /// The start method and method signatures of the application, without parsing any
+    /// instructions. This is much faster than `build_application` for listing methods.
+    pub fn build_index(&self) -> Result<AppIndex, LoaderError> {
+        let app = descendant(&self.raw.root(), APP)?;
+        let flat_fields = text_only_children(&app);
+        let mut methods = Vec::new();
+        if let Some(c) = app.children().find(|n| n.has_tag_name(METHODS)) {
+            for method_node in c
+                .children()
+                .filter(|n| n.is_element() && !n.has_tag_name(METHODS_COUNT))
+            {
+                methods.push(Self::build_method_signature(&method_node)?);
+            }
+        }
+        Ok(AppIndex {
+            start_method: parse_field(&flat_fields, START_METHOD)?,
+            methods,
+        })
+    }
+
+    /// Give the global variables, methods, local variables and instructions to the visitor in a
+    /// single pass, in the order they appear in the exported application. Nothing is kept after
+    /// it is visited, so this uses much less memory than `build_application` for large exports.
+    /// The instructions do not have stable ids, as those need the whole method.
+    pub fn visit(&self, visitor: &mut dyn AppVisitor) -> Result<(), LoaderError> {
+        let app = descendant(&self.raw.root(), APP)?;
+        for c in app.children() {
+            if c.has_tag_name(GLOBAL_VAR_POOL) {
+                Self::visit_variables(&c, None, visitor)?;
+            } else if c.has_tag_name(METHODS) {
+                for method_node in c
+                    .children()
+                    .filter(|n| n.is_element() && !n.has_tag_name(METHODS_COUNT))
+                {
+                    let signature = Self::build_method_signature(&method_node)?;
+                    visitor.on_method(&signature);
+                    let mut reached_instructions = false;
+                    for m in method_node.children() {
+                        if reached_instructions && m.is_element() {
+                            let instruction = Self::build_instruction(&m, self.keep_source)?;
+                            visitor.on_instruction(signature.id, &instruction);
+                        } else if m.has_tag_name(LOCAL_VAR_POOL) {
+                            Self::visit_variables(&m, Some(signature.id), visitor)?;
+                        } else if m.has_tag_name(INSTR_COUNT) {
+                            reached_instructions = true;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_variables(
+        node: &Node,
+        method: Option<Uuid>,
+        visitor: &mut dyn AppVisitor,
+    ) -> Result<(), LoaderError> {
+        let var_count = descendant(node, VAR_COUNT)?;
+        // The sibling element iterator includes itself, so skip it
+        for n in var_count.next_siblings().skip(1).filter(|n| n.is_element()) {
+            visitor.on_variable(method, &Self::build_variable(&n)?);
+        }
+        Ok(())
+    }
+
+    fn build_method_signature(node: &Node) -> Result<MethodSignature, LoaderError> {
+        let method_fields = text_only_children(node);
+        let params = node
+            .children()
+            .find(|n| n.has_tag_name(PARAMS))
+            .ok_or(LoaderError::MissingField(PARAMS))?;
+        let params_pool = first_element(&params, VAR_POOL)?;
+        let var_count = params_pool
+            .children()
+            .find(|n| n.has_tag_name(VAR_COUNT))
+            .ok_or(LoaderError::MissingField(VAR_COUNT))?;
+        // The sibling element iterator includes itself, so skip it
+        let parameters = var_count
+            .next_siblings()
+            .skip(1)
+            .filter(|n| n.is_element())
+            .map(|n| Self::build_variable(&n))
+            .collect::<Result<_, _>>()?;
+        Ok(MethodSignature {
+            designation: field(&method_fields, METHOD_DESIG)?.to_string(),
+            id: parse_field(&method_fields, PROGRAM_ID)?,
+            layout_id: parse_field(&method_fields, LAYOUT_ID)?,
+            parameters,
+        })
     }
 
-    fn build_variable(node: &Node) -> Variable {
+    fn build_variable(node: &Node) -> Result<Variable, LoaderError> {
         let variable_fields = text_only_children(node);
-        let val_str = variable_fields.get(VAR_VALUE).unwrap();
-        let value = match *variable_fields.get(VAR_TYPE).unwrap() {
-            "2" => Some(VariableValue::Float(val_str.parse().unwrap())),
-            "3" => Some(VariableValue::String(val_str.to_string())),
+        let val_str = field(&variable_fields, VAR_VALUE)?;
+        // Integers are not in the exports at hand, 1 is the code Maestro is reported to use for them
+        let value = match field(&variable_fields, VAR_TYPE)?.trim() {
+            "1" => VariableValue::Int(parse_text(val_str, VAR_VALUE)?),
+            "2" => VariableValue::Float(parse_text(val_str, VAR_VALUE)?),
+            "3" => VariableValue::String(val_str.to_string()),
             "4" => {
-                let b = Self::build_bool(&val_str);
-                Some(VariableValue::Bool(b))
+                let b = Self::build_bool(val_str);
+                VariableValue::Bool(b)
             }
-            "7" => Some(VariableValue::Seconds(val_str.parse().unwrap())),
-            _ => None,
+            "7" => VariableValue::Seconds(parse_text(val_str, VAR_VALUE)?),
+            other => return Err(unknown_value(VAR_TYPE, other)),
         };
-        Variable {
-            designation: variable_fields.get(VAR_DESIG).unwrap().to_string(),
-            id: variable_fields.get(VAR_ID).unwrap().parse().unwrap(),
-            value: value.unwrap(),
-        }
+        Ok(Variable {
+            designation: field(&variable_fields, VAR_DESIG)?.to_string(),
+            id: parse_field(&variable_fields, VAR_ID)?,
+            value,
+            value_labels: variable_fields
+                .get(VAR_PERMISSIBLE)
+                .and_then(|p| parse_value_labels(p)),
+        })
     }
 
-    fn build_parameter(node: &Node) -> Parameter {
+    fn build_parameter(node: &Node) -> Result<Parameter, LoaderError> {
         let variable_fields = text_only_children(node);
-        let uuid_str = variable_fields.get("ForParameter").unwrap();
-        let val_type_str = variable_fields.get("ParameterType").unwrap();
-        let val_type = match *val_type_str {
+        let val_type = match field(&variable_fields, "ParameterType")?.trim() {
+            "1" => VariableType::Int,
             "2" => VariableType::Float,
             "3" => VariableType::String,
             "4" => VariableType::Bool,
             "7" => VariableType::Seconds,
-            _ => panic!("Unknown parameter type {}", val_type_str),
+            other => return Err(unknown_value("ParameterType", other)),
         };
-        let val = Self::build_instruction_value(&node, val_type);
-        Parameter {
-            id: uuid_str.parse().unwrap(),
+        let val = Self::build_instruction_value(node, val_type)?;
+        Ok(Parameter {
+            id: parse_field(&variable_fields, "ForParameter")?,
             value: val,
-        }
+        })
     }
 
-    fn build_variables_pool(node: &Node) -> VariablesPool {
+    fn build_variables_pool(node: &Node) -> Result<VariablesPool, LoaderError> {
         let global_fields = text_only_children(node);
-        let var_count = node
-            .descendants()
-            .find(|n| n.has_tag_name(VAR_COUNT))
-            .unwrap();
+        let var_count = descendant(node, VAR_COUNT)?;
         let mut var_map = HashMap::new();
 
         // The sibling element iterator includes itself, so skip it
         for n in var_count.next_siblings().skip(1).filter(|n| n.is_element()) {
-            let var = Self::build_variable(&n);
+            let var = Self::build_variable(&n)?;
             var_map.insert(var.id, var);
         }
 
-        VariablesPool {
-            designation: global_fields.get(VAR_POOL_DESIG).unwrap().parse().unwrap(),
-            id: global_fields.get(VAR_POOL_ID).unwrap().parse().unwrap(),
+        Ok(VariablesPool {
+            designation: field(&global_fields, VAR_POOL_DESIG)?.to_string(),
+            id: parse_field(&global_fields, VAR_POOL_ID)?,
             variables: var_map,
-        }
+        })
     }
 
-    fn build_location(node: &Node) -> Location {
+    fn build_location(node: &Node) -> Result<Location, LoaderError> {
         let variable_fields = text_only_children(node);
-        Location {
-            id: variable_fields.get(VAR_ID).unwrap().parse().unwrap(),
-            position: variable_fields.get(VAR_DESIG).unwrap().to_string(),
-            number_stacked: variable_fields
-                .get(VAR_NUMBER_STACKED)
-                .unwrap()
-                .parse()
-                .unwrap(),
-            designation: variable_fields.get(VAR_THIS_DESIG).unwrap().to_string(),
-            consumable: variable_fields
-                .get(VAR_CONSUMABLE)
-                .unwrap()
-                .parse()
-                .unwrap(),
-        }
+        Ok(Location {
+            id: parse_field(&variable_fields, VAR_ID)?,
+            position: field(&variable_fields, VAR_DESIG)?.to_string(),
+            number_stacked: parse_field(&variable_fields, VAR_NUMBER_STACKED)?,
+            designation: field(&variable_fields, VAR_THIS_DESIG)?.to_string(),
+            consumable: parse_field(&variable_fields, VAR_CONSUMABLE)?,
+            tip_size: None,
+            well_capacity: None,
+        })
     }
 
-    fn build_layout(node: &Node) -> Layout {
+    fn build_layout(node: &Node) -> Result<Layout, LoaderError> {
         let global_fields = text_only_children(node);
-        let var_count = node
-            .descendants()
-            .find(|n| n.has_tag_name(VAR_COUNT))
-            .unwrap();
+        let var_count = descendant(node, VAR_COUNT)?;
         let mut var_map = HashMap::new();
 
         // The sibling element iterator includes itself, so skip it
         for n in var_count.next_siblings().skip(1).filter(|n| n.is_element()) {
-            let var = Self::build_location(&n);
+            let var = Self::build_location(&n)?;
             var_map.insert(var.id, var);
         }
 
-        Layout {
-            designation: global_fields.get(VAR_POOL_DESIG).unwrap().parse().unwrap(),
-            id: global_fields.get(VAR_POOL_ID).unwrap().parse().unwrap(),
+        Ok(Layout {
+            designation: field(&global_fields, VAR_POOL_DESIG)?.to_string(),
+            id: parse_field(&global_fields, VAR_POOL_ID)?,
             positions: var_map,
-        }
+        })
     }
 
-    fn build_method(node: &Node) -> Method {
+    fn build_method(node: &Node, keep_source: bool) -> Result<Method, LoaderError> {
         let method_fields = text_only_children(node);
         let mut local_var: Option<VariablesPool> = None;
         let mut params: Option<VariablesPool> = None;
@@ -211,352 +401,345 @@ impl<'a> Loader<'a> {
         let mut reached_instructions = false;
         for c in node.children() {
             if reached_instructions && c.is_element() {
-                instructions.push(Self::build_instruction(&c));
+                instructions.push(Self::build_instruction(&c, keep_source)?);
             } else if c.has_tag_name(LOCAL_VAR_POOL) {
-                local_var = Some(Self::build_variables_pool(
-                    &c.first_element_child().unwrap(),
-                ));
+                local_var = Some(Self::build_variables_pool(&first_element(&c, VAR_POOL)?)?);
             } else if c.has_tag_name(PARAMS) {
-                params = Some(Self::build_variables_pool(
-                    &c.first_element_child().unwrap(),
-                ));
+                params = Some(Self::build_variables_pool(&first_element(&c, VAR_POOL)?)?);
             } else if c.has_tag_name(INSTR_COUNT) {
                 reached_instructions = true;
             }
         }
-        Method {
-            designation: method_fields.get(METHOD_DESIG).unwrap().parse().unwrap(),
-            id: method_fields.get(PROGRAM_ID).unwrap().parse().unwrap(),
-            layout_id: method_fields.get(LAYOUT_ID).unwrap().parse().unwrap(),
-            local_variables_pool: local_var.unwrap(),
-            parameters: params.unwrap(),
+        assign_stable_ids(&mut instructions);
+        number_instructions(&mut instructions);
+        Ok(Method {
+            designation: field(&method_fields, METHOD_DESIG)?.to_string(),
+            id: parse_field(&method_fields, PROGRAM_ID)?,
+            layout_id: parse_field(&method_fields, LAYOUT_ID)?,
+            local_variables_pool: local_var.ok_or(LoaderError::MissingField(LOCAL_VAR_POOL))?,
+            parameters: params.ok_or(LoaderError::MissingField(PARAMS))?,
+            declared_instructions: parse_field(&method_fields, INSTR_COUNT)?,
             instructions,
-        }
+        })
     }
 
-    fn build_instruction(node: &Node) -> Instruction {
+    fn build_instruction(node: &Node, keep_source: bool) -> Result<Instruction, LoaderError> {
         let instr_fields = text_only_children(node);
-        let instr = instr_fields.get(INSTR_DESIG).unwrap();
-        let is_comment_str = instr_fields.get(INSTR_IS_COMMENT).unwrap();
-        let is_comment = Self::build_bool(*is_comment_str);
-        let command = match *instr {
-            "Absolute Move" => Command::AbsoluteMove,
+        let instr = field(&instr_fields, INSTR_DESIG)?;
+        let is_comment_str = field(&instr_fields, INSTR_IS_COMMENT)?;
+        let is_comment = Self::build_bool(is_comment_str);
+        let command = match instr {
+            "Absolute Move" => Self::build_instruction_absolute_move(node)?,
             "Application Exit" => Command::ApplicationExit,
-            "Aspirate" => Self::build_instruction_aspirate(&node),
-            "Begin Loop" => Self::build_instruction_begin_loop(&node),
+            "Aspirate" => Self::build_instruction_aspirate(node)?,
+            "Begin Loop" => Self::build_instruction_begin_loop(node)?,
             "CloseWorkbook" => Command::CloseWorkbook,
-            "Dispense" => Self::build_instruction_dispense(&node),
+            "Dispense" => Self::build_instruction_dispense(node)?,
             "End If" => Command::EndIf,
             "End Loop" => Command::EndLoop,
             "End While" => Command::EndWhile,
-            "Eject Tips" => Self::build_instruction_eject_tips(&node),
-            "Execute VSTA Macro" => Self::build_instruction_execute_vsta_macro(&node),
+            "Eject Tips" => Self::build_instruction_eject_tips(node)?,
+            "Execute VSTA Macro" => Self::build_instruction_execute_vsta_macro(node)?,
             "Get Current Position Relative to Reference" => {
                 Command::GetCurrentPositionRelativeToReference
             }
-            "Head Position" => Self::build_instruction_head_position(&node),
-            "Home" => Self::build_instruction_home(&node),
+            "Head Position" => Self::build_instruction_head_position(node)?,
+            "Home" => Self::build_instruction_home(node)?,
             "Home P Axis" => Command::HomePAxis,
-            "If..Then" => Self::build_instruction_if_then(&node),
+            "If..Then" => Self::build_instruction_if_then(node)?,
             "Initialize" => Command::Initialize,
             "Initialize System" => Command::InitializeSystem,
-            "Load Tips" => Self::build_instruction_load_tips(&node),
-            "Math Operation" => Self::build_instruction_math_operation(&node),
-            "Mix" => Self::build_instruction_mix(&node),
-            "Move Material" => Self::build_instruction_move_material(&node),
+            "Load Tips" => Self::build_instruction_load_tips(node)?,
+            "Math Operation" => Self::build_instruction_math_operation(node)?,
+            "Mix" => Self::build_instruction_mix(node)?,
+            "Move Material" => Self::build_instruction_move_material(node)?,
             "OpenWorkbook" => Command::OpenWorkbook,
-            "P Axis Set Position" => Command::PAxisSetPosition,
-            "Pick" => Self::build_instruction_pick(&node),
-            "Place" => Self::build_instruction_place(&node),
-            "Relative Move" => Command::RelativeMove,
-            "REM" => Self::build_instruction_rem(&node),
+            "P Axis Set Position" => Self::build_instruction_p_axis_set_position(node)?,
+            "Pick" => Self::build_instruction_pick(node)?,
+            "Place" => Self::build_instruction_place(node)?,
+            "Relative Move" => Self::build_instruction_relative_move(node)?,
+            "REM" => Self::build_instruction_rem(node)?,
             "RunMacro" => Command::RunMacro,
-            "Run Method" => Self::build_instruction_run_method(&node),
-            "Run Shaker For Time" => Self::build_instruction_run_shaker_for_time(&node),
-            "Set Leg Light Intensity" => Self::build_instruction_set_light_intensity(&node),
-            "Set Speed" => Self::build_instruction_set_speed(&node),
-            "Set Temperature" => Self::build_instruction_set_temperature(&node),
-            "Set Travel Height" => Command::SetTravelHeight,
+            "Run Method" => Self::build_instruction_run_method(node)?,
+            "Run Shaker For Time" => Self::build_instruction_run_shaker_for_time(node)?,
+            "Set Leg Light Intensity" => Self::build_instruction_set_light_intensity(node)?,
+            "Set Speed" => Self::build_instruction_set_speed(node)?,
+            "Set Temperature" => Self::build_instruction_set_temperature(node)?,
+            "Set Travel Height" => Self::build_instruction_set_travel_height(node)?,
             "SetWorkingDirectory" => Command::SetWorkingDirectory,
-            "Shaker On/Off" => Self::build_instruction_temperature_on_off(&node),
-            "Show Dialog" => Self::build_show_dialog(&node),
+            "Shaker On/Off" => Self::build_instruction_shaker_on_off(node)?,
+            "Show Dialog" => Self::build_show_dialog(node)?,
             "Start Timer" => Command::StartTime,
             "Stop Timer" => Command::StopTimer,
-            "String Operation" => Command::StringOperation,
-            "Temperature On/Off" => Self::build_instruction_shaker_on_off(&node),
+            "String Operation" => Self::build_instruction_string_operation(node)?,
+            "Temperature On/Off" => Self::build_instruction_temperature_on_off(node)?,
             "UnGrip" => Command::Ungrip,
             "Vertical Position" => Command::VerticalPosition,
-            "While Loop" => Self::build_instruction_while_loop(&node),
-            _ => panic!("Unknown command {}", instr),
+            "While Loop" => Self::build_instruction_while_loop(node)?,
+            // Newer Maestro versions add instructions, which should not stop the rest loading
+            _ => Command::Unknown {
+                designation: instr.to_string(),
+            },
         };
-        Instruction {
+        // Instructions are saved in elements numbered from 1 like <Instruction13>, as the editor
+        // numbers them
+        let source_index = node
+            .tag_name()
+            .name()
+            .strip_prefix("Instruction")
+            .and_then(|n| n.parse().ok())
+            .unwrap_or(0);
+        let source = if keep_source {
+            Some(node.document().input_text()[node.range()].to_string())
+        } else {
+            None
+        };
+        Ok(Instruction {
             is_comment,
             command,
-        }
+            stable_id: 0,
+            source_index,
+            source,
+        })
     }
 
-    fn build_operator(op: &str) -> Operator {
+    fn build_operator(op: &str) -> Result<Operator, LoaderError> {
         match op {
-            "(Assignment)" => Operator::Assign,
-            "-" => Operator::Minus,
-            "+" => Operator::Plus,
-            _ => panic!("Unknown math operator {}", op),
+            "(Assignment)" => Ok(Operator::Assign),
+            "-" => Ok(Operator::Minus),
+            "+" => Ok(Operator::Plus),
+            _ => Err(unknown_value("Operator", op)),
         }
     }
 
-    fn build_test_variable_type(var: &str) -> VariableType {
+    fn build_string_operator(op: &str) -> Result<StringOperator, LoaderError> {
+        match parse_text(op, "Operation")? {
+            8 => Ok(StringOperator::Assign),
+            code => Ok(StringOperator::Other(code)),
+        }
+    }
+
+    fn build_test_variable_type(var: &str, tag: &'static str) -> Result<VariableType, LoaderError> {
         match var {
-            "0" => VariableType::String,
-            "1" => VariableType::Float,
-            "2" => VariableType::Bool,
-            _ => panic!("Unknown test variable type {}", var),
+            "0" => Ok(VariableType::String),
+            "1" => Ok(VariableType::Float),
+            "2" => Ok(VariableType::Bool),
+            _ => Err(unknown_value(tag, var)),
         }
     }
 
-    fn build_comparator(comp: &str) -> Comparator {
+    fn build_comparator(comp: &str) -> Result<Comparator, LoaderError> {
         match comp {
-            "Equals" => Comparator::Equals,
-            "Greater than" => Comparator::GreaterThan,
-            "Greater than or equal to" => Comparator::GreaterThanOrEqual,
-            "Less than" => Comparator::LessThan,
-            "Less than or equal to" => Comparator::LessThanOrEqual,
-            _ => panic!("Unknown comparator {}", comp),
+            "Equals" => Ok(Comparator::Equals),
+            "Greater than" => Ok(Comparator::GreaterThan),
+            "Greater than or equal to" => Ok(Comparator::GreaterThanOrEqual),
+            "Less than" => Ok(Comparator::LessThan),
+            "Less than or equal to" => Ok(Comparator::LessThanOrEqual),
+            _ => Err(unknown_value(INSTR_COMPARATOR, comp)),
         }
     }
 
-    fn build_position_head(node: &Node) -> PositionHead {
-        let uuid_str = node
-            .descendants()
-            .find(|n| n.has_tag_name("DeckVariableID"))
-            .unwrap()
-            .text()
-            .unwrap();
-        let mut deck_parameter = None;
-        if uuid_str != "[[[[---NONE---]]]]" {
-            deck_parameter = Some(uuid_str.parse().unwrap());
+    // The id of the deck parameter, if the position is given by a parameter of the method
+    fn build_deck_parameter(node: &Node) -> Result<Option<Uuid>, LoaderError> {
+        let uuid_str = element_text(&descendant(node, "DeckVariableID")?).trim();
+        if uuid_str == "[[[[---NONE---]]]]" {
+            Ok(None)
+        } else {
+            parse_text(uuid_str, "DeckVariableID").map(Some)
         }
-        let var_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("DeckLocation"))
-            .unwrap();
-        let deck_location = Self::build_instruction_value(&var_node, VariableType::String);
+    }
 
-        let z_offset_node = var_node
-            .next_siblings()
-            .find(|n| n.has_tag_name("ZPosOffset"))
-            .unwrap();
-        let z_offset = Self::build_instruction_value(&z_offset_node, VariableType::Float);
-        PositionHead {
+    fn build_position_head(node: &Node) -> Result<PositionHead, LoaderError> {
+        let deck_parameter = Self::build_deck_parameter(node)?;
+        let var_node = descendant(node, "DeckLocation")?;
+        let deck_location = Self::build_instruction_value(&var_node, VariableType::String)?;
+
+        let z_offset_node = next_sibling(&var_node, "ZPosOffset")?;
+        let z_offset = Self::build_instruction_value(&z_offset_node, VariableType::Float)?;
+        Ok(PositionHead {
             deck_parameter,
             deck_location,
             z_offset,
-        }
+        })
     }
 
-    fn build_load_eject_tips_head(node: &Node) -> LoadEjectTipsHead {
-        let uuid_str = node
-            .descendants()
-            .find(|n| n.has_tag_name("DeckVariableID"))
-            .unwrap()
-            .text()
-            .unwrap();
-        let mut deck_parameter = None;
-        if uuid_str != "[[[[---NONE---]]]]" {
-            deck_parameter = Some(uuid_str.parse().unwrap());
-        }
-        let var_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("DeckLocation"))
-            .unwrap();
-        let deck_location = Self::build_instruction_value(&var_node, VariableType::String);
-        LoadEjectTipsHead {
+    fn build_load_eject_tips_head(node: &Node) -> Result<LoadEjectTipsHead, LoaderError> {
+        let deck_parameter = Self::build_deck_parameter(node)?;
+        let var_node = descendant(node, "DeckLocation")?;
+        let deck_location = Self::build_instruction_value(&var_node, VariableType::String)?;
+        Ok(LoadEjectTipsHead {
             deck_parameter,
             deck_location,
-        }
+        })
     }
 
     fn build_bool(s: &str) -> bool {
-        if s == "0" {
-            false
-        } else {
-            true
-        }
+        s.trim() != "0"
     }
 
-    fn build_instruction_aspirate(node: &Node) -> Command {
-        let position_node = node
+    fn build_instruction_aspirate(node: &Node) -> Result<Command, LoaderError> {
+        let position_node = descendant(node, "HeadPosInstr")?;
+        let position = Self::build_position_head(&position_node)?;
+        let vol_node = next_sibling(&position_node, "VarVolume")?;
+        let vol = Self::build_instruction_value(&vol_node, VariableType::Float)?;
+        let ignore_air_gap = node
             .descendants()
-            .find(|n| n.has_tag_name("HeadPosInstr"))
-            .unwrap();
-        let position = Self::build_position_head(&position_node);
-        let vol_node = position_node
-            .next_siblings()
-            .find(|n| n.has_tag_name("VarVolume"))
-            .unwrap();
-        let vol = Self::build_instruction_value(&vol_node, VariableType::Float);
-        Command::Aspirate {
+            .find(|n| n.has_tag_name("IgnoreAirGap"))
+            .map(|n| Self::build_bool(n.text().unwrap_or("0")))
+            .unwrap_or(false);
+        let air_gap = |tag| {
+            vol_node
+                .next_siblings()
+                .find(|n| n.has_tag_name(tag))
+                .filter(|_| !ignore_air_gap)
+                .map(|n| Self::build_instruction_value(&n, VariableType::Float))
+                .transpose()
+        };
+        Ok(Command::Aspirate {
             position_head: position,
             volume: vol,
-        }
+            leading_air_gap: air_gap("VarLeadingAirGap")?,
+            trailing_air_gap: air_gap("VarTrailingAirGap")?,
+        })
     }
 
-    fn build_instruction_begin_loop(node: &Node) -> Command {
-        let index_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("LoopIndexParam"))
-            .unwrap();
-        let index = Self::build_instruction_value(&index_node, VariableType::Int);
-        let from_node = index_node
-            .next_siblings()
-            .find(|n| n.has_tag_name("LoopFromParam"))
-            .unwrap();
-        let from = Self::build_instruction_value(&from_node, VariableType::Int);
-        let to_node = from_node
-            .next_siblings()
-            .find(|n| n.has_tag_name("LoopToParam"))
-            .unwrap();
-        let to = Self::build_instruction_value(&to_node, VariableType::Int);
-        let steps_node = to_node
-            .next_siblings()
-            .find(|n| n.has_tag_name("LoopStepParam"))
-            .unwrap();
-        let steps = Self::build_instruction_value(&steps_node, VariableType::Int);
-        Command::BeginLoop {
+    fn build_instruction_begin_loop(node: &Node) -> Result<Command, LoaderError> {
+        let index_node = descendant(node, "LoopIndexParam")?;
+        let index = Self::build_instruction_value(&index_node, VariableType::Int)?;
+        let from_node = next_sibling(&index_node, "LoopFromParam")?;
+        let from = Self::build_instruction_value(&from_node, VariableType::Int)?;
+        let to_node = next_sibling(&from_node, "LoopToParam")?;
+        let to = Self::build_instruction_value(&to_node, VariableType::Int)?;
+        let steps_node = next_sibling(&to_node, "LoopStepParam")?;
+        let steps = Self::build_instruction_value(&steps_node, VariableType::Int)?;
+        Ok(Command::BeginLoop {
             index,
             from,
             to,
             steps,
-        }
+        })
     }
 
-    fn build_instruction_dispense(node: &Node) -> Command {
-        let dcc_control_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("DCCControl"))
-            .unwrap();
-        if dcc_control_node.text().unwrap() == "Sciclone" {
-            let all_node = node
-                .descendants()
-                .find(|n| n.has_tag_name("DispenseAll"))
-                .unwrap();
-            let dispense_all = Self::build_bool(all_node.text().unwrap());
-            let head_node = all_node
-                .next_siblings()
-                .find(|n| n.has_tag_name("HeadPosInstr"))
-                .unwrap();
-            let position_head = Self::build_position_head(&head_node);
-            let volume_node = head_node
-                .next_siblings()
-                .find(|n| n.has_tag_name("VarVolume"))
-                .unwrap();
-            let volume = Self::build_instruction_value(&volume_node, VariableType::Float);
-            Command::Dispense {
+    fn build_instruction_dispense(node: &Node) -> Result<Command, LoaderError> {
+        let dcc_control_node = descendant(node, "DCCControl")?;
+        if element_text(&dcc_control_node) == "Sciclone" {
+            let all_node = descendant(node, "DispenseAll")?;
+            let dispense_all = Self::build_bool(element_text(&all_node));
+            let head_node = next_sibling(&all_node, "HeadPosInstr")?;
+            let position_head = Self::build_position_head(&head_node)?;
+            let volume_node = next_sibling(&head_node, "VarVolume")?;
+            let volume = Self::build_instruction_value(&volume_node, VariableType::Float)?;
+            Ok(Command::Dispense {
                 position_head,
                 dispense_all,
                 volume,
-            }
+                destinations: Self::build_array_destinations(node)?,
+            })
         } else {
-            let volume_node = node
-                .descendants()
-                .find(|n| n.has_tag_name("Volume"))
-                .unwrap();
-            let volume = Self::build_instruction_value(&volume_node, VariableType::Float);
-            let dispense_all_node = volume_node
-                .next_siblings()
-                .find(|n| n.has_tag_name("DsAll"))
-                .unwrap();
-            let dispense_all = Self::build_bool(dispense_all_node.text().unwrap());
-            Command::DispenseMainArray {
+            let volume_node = descendant(node, "Volume")?;
+            let volume = Self::build_instruction_value(&volume_node, VariableType::Float)?;
+            let dispense_all_node = next_sibling(&volume_node, "DsAll")?;
+            let dispense_all = Self::build_bool(element_text(&dispense_all_node));
+            Ok(Command::DispenseMainArray {
                 volume,
                 dispense_all,
-            }
+            })
         }
     }
 
-    fn build_instruction_eject_tips(node: &Node) -> Command {
-        let pos_node = node
+    // The further wells of an array dispense, each with its volume. A dispense to one well has
+    // no ArrayDestinations element.
+    fn build_array_destinations(node: &Node) -> Result<Vec<(PositionHead, f64)>, LoaderError> {
+        let array_node = match node
             .descendants()
-            .find(|n| n.has_tag_name("LoadEjectTipsInstr"))
-            .unwrap();
-        let l = Self::build_load_eject_tips_head(&pos_node);
-        Command::EjectTips {
+            .find(|n| n.has_tag_name("ArrayDestinations"))
+        {
+            Some(n) => n,
+            None => return Ok(Vec::new()),
+        };
+        array_node
+            .children()
+            .filter(|n| n.has_tag_name("Destination"))
+            .map(|dest_node| {
+                let head_node = descendant(&dest_node, "HeadPosInstr")?;
+                let position_head = Self::build_position_head(&head_node)?;
+                let volume_node = next_sibling(&head_node, "Volume")?;
+                let volume = parse_text(element_text(&volume_node), "Volume")?;
+                Ok((position_head, volume))
+            })
+            .collect()
+    }
+
+    fn build_instruction_eject_tips(node: &Node) -> Result<Command, LoaderError> {
+        let pos_node = descendant(node, "LoadEjectTipsInstr")?;
+        let l = Self::build_load_eject_tips_head(&pos_node)?;
+        Ok(Command::EjectTips {
             load_eject_tips_head: l,
-        }
+        })
     }
 
-    fn build_instruction_execute_vsta_macro(node: &Node) -> Command {
-        let name = node
-            .descendants()
-            .find(|n| n.has_tag_name("MacroName"))
-            .unwrap()
-            .text()
-            .unwrap()
-            .to_string();
-        Command::ExecuteVSTAMacro { name }
+    fn build_instruction_execute_vsta_macro(node: &Node) -> Result<Command, LoaderError> {
+        let name = element_text(&descendant(node, "MacroName")?).to_string();
+        Ok(Command::ExecuteVSTAMacro { name })
     }
 
-    fn build_instruction_head_position(node: &Node) -> Command {
-        let pos_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("PositionHeadInstr"))
-            .unwrap();
-        let position_head = Self::build_position_head(&pos_node);
-        Command::HeadPosition { position_head }
+    fn build_instruction_head_position(node: &Node) -> Result<Command, LoaderError> {
+        let pos_node = descendant(node, "PositionHeadInstr")?;
+        let position_head = Self::build_position_head(&pos_node)?;
+        Ok(Command::HeadPosition { position_head })
     }
 
-    fn build_instruction_home(node: &Node) -> Command {
-        let x_node = node.descendants().find(|n| n.has_tag_name("X")).unwrap();
-        let y_node = x_node
-            .next_siblings()
-            .find(|n| n.has_tag_name("Y"))
-            .unwrap();
-        let z_node = y_node
-            .next_siblings()
-            .find(|n| n.has_tag_name("Z"))
-            .unwrap();
-        let x = Self::build_bool(x_node.text().unwrap());
-        let y = Self::build_bool(y_node.text().unwrap());
-        let z = Self::build_bool(z_node.text().unwrap());
-        Command::Home { x, y, z }
+    fn build_instruction_home(node: &Node) -> Result<Command, LoaderError> {
+        let x_node = descendant(node, "X")?;
+        let y_node = next_sibling(&x_node, "Y")?;
+        let z_node = next_sibling(&y_node, "Z")?;
+        let x = Self::build_bool(element_text(&x_node));
+        let y = Self::build_bool(element_text(&y_node));
+        let z = Self::build_bool(element_text(&z_node));
+        Ok(Command::Home { x, y, z })
     }
 
-    fn build_instruction_if_then(node: &Node) -> Command {
-        let if_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("ControlInstr_IfThen"))
-            .unwrap();
-        let fields = text_only_children(&if_node);
-        let comparator = Self::build_comparator(fields.get(INSTR_COMPARATOR).unwrap());
-        let var_type = Self::build_test_variable_type(fields.get(INSTR_TEST_TYPE).unwrap());
+    // The comparator and the two values compared by an If..Then or While Loop
+    fn build_test(
+        node: &Node,
+        type_tag: &'static str,
+    ) -> Result<(Comparator, InstructionValue, InstructionValue), LoaderError> {
+        let fields = text_only_children(node);
+        let comparator = Self::build_comparator(field(&fields, INSTR_COMPARATOR)?)?;
+        let var_type = Self::build_test_variable_type(field(&fields, type_tag)?, type_tag)?;
         let mut instr_val = Vec::new();
-        for c in if_node.children().filter(|n| n.is_element()).skip(2) {
-            instr_val.push(Self::build_instruction_value(&c, var_type));
+        for c in node.children().filter(|n| n.is_element()).skip(2) {
+            instr_val.push(Self::build_instruction_value(&c, var_type)?);
         }
-        let rhs = instr_val.pop().unwrap();
-        let lhs = instr_val.pop().unwrap();
-        Command::IfThen {
+        let rhs = instr_val
+            .pop()
+            .ok_or(LoaderError::MissingField("CompareValue"))?;
+        let lhs = instr_val
+            .pop()
+            .ok_or(LoaderError::MissingField("TestVariable"))?;
+        Ok((comparator, lhs, rhs))
+    }
+
+    fn build_instruction_if_then(node: &Node) -> Result<Command, LoaderError> {
+        let if_node = descendant(node, "ControlInstr_IfThen")?;
+        let (comparator, lhs, rhs) = Self::build_test(&if_node, INSTR_TEST_TYPE)?;
+        Ok(Command::IfThen {
             comparator,
             lhs,
             rhs,
-        }
+        })
     }
 
-    fn build_instruction_load_tips(node: &Node) -> Command {
-        let pos_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("LoadEjectTipsInstr"))
-            .unwrap();
-        let l = Self::build_load_eject_tips_head(&pos_node);
-        Command::LoadTips {
+    fn build_instruction_load_tips(node: &Node) -> Result<Command, LoaderError> {
+        let pos_node = descendant(node, "LoadEjectTipsInstr")?;
+        let l = Self::build_load_eject_tips_head(&pos_node)?;
+        Ok(Command::LoadTips {
             load_eject_tips_head: l,
-        }
+        })
     }
 
-    fn build_instruction_math_operation(node: &Node) -> Command {
-        let math_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("ControlInstr_MathOps"))
-            .unwrap();
+    fn build_instruction_math_operation(node: &Node) -> Result<Command, LoaderError> {
+        let math_node = descendant(node, "ControlInstr_MathOps")?;
         let instr_type = VariableType::Float;
         let mut operator = None;
         let mut vars = Vec::new();
@@ -564,270 +747,474 @@ impl<'a> Loader<'a> {
             if c.has_tag_name("DataType") {
                 continue;
             } else if c.has_tag_name("Operator") {
-                operator = Some(Self::build_operator(c.text().unwrap()));
+                operator = Some(Self::build_operator(element_text(&c))?);
             } else {
-                vars.push(Self::build_instruction_value(&c, instr_type));
+                vars.push(Self::build_instruction_value(&c, instr_type)?);
             }
         }
-        let rhs_op2 = vars.pop().unwrap();
-        let rhs_op1 = vars.pop().unwrap();
-        let lhs = vars.pop().unwrap();
-        Command::MathOperation {
-            operator: operator.unwrap(),
+        let mut operand = || vars.pop().ok_or(LoaderError::MissingField("Operand"));
+        let rhs_op2 = operand()?;
+        let rhs_op1 = operand()?;
+        let lhs = operand()?;
+        Ok(Command::MathOperation {
+            operator: operator.ok_or(LoaderError::MissingField("Operator"))?,
             lhs,
             rhs_op1,
             rhs_op2,
-        }
+        })
     }
 
-    fn build_instruction_mix(node: &Node) -> Command {
-        let head_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("PositionHeadInstr"))
-            .unwrap();
-        let position_head = Self::build_position_head(&head_node);
-        Command::Mix { position_head }
+    fn build_instruction_mix(node: &Node) -> Result<Command, LoaderError> {
+        let head_node = descendant(node, "PositionHeadInstr")?;
+        let position_head = Self::build_position_head(&head_node)?;
+        Ok(Command::Mix { position_head })
     }
 
-    fn build_instruction_move_material(node: &Node) -> Command {
-        let from_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("MoveMatPickInstr"))
-            .unwrap();
-        let from_head_node = from_node
-            .descendants()
-            .find(|n| n.has_tag_name("PositionHeadInstr"))
-            .unwrap();
-        let from = Self::build_position_head(&from_head_node);
-        let to_node = from_node
-            .next_siblings()
-            .find(|n| n.has_tag_name("MoveMatPlaceInstr"))
-            .unwrap();
-        let to_head_node = to_node
-            .descendants()
-            .find(|n| n.has_tag_name("PositionHeadInstr"))
-            .unwrap();
-        let to = Self::build_position_head(&to_head_node);
-        Command::MoveMaterial { from, to }
+    fn build_instruction_move_material(node: &Node) -> Result<Command, LoaderError> {
+        let from_node = descendant(node, "MoveMatPickInstr")?;
+        let from_head_node = descendant(&from_node, "PositionHeadInstr")?;
+        let from = Self::build_position_head(&from_head_node)?;
+        let to_node = next_sibling(&from_node, "MoveMatPlaceInstr")?;
+        let to_head_node = descendant(&to_node, "PositionHeadInstr")?;
+        let to = Self::build_position_head(&to_head_node)?;
+        Ok(Command::MoveMaterial { from, to })
     }
 
-    fn build_instruction_pick(node: &Node) -> Command {
-        let pos_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("HeadPosInstr"))
-            .unwrap();
-        let position_head = Self::build_position_head(&pos_node);
-        Command::Pick { position_head }
+    fn build_instruction_p_axis_set_position(node: &Node) -> Result<Command, LoaderError> {
+        let position_node = descendant(node, "SetPosInstructionSpecification")?;
+        let position = Self::build_instruction_value(&position_node, VariableType::Float)?;
+        Ok(Command::PAxisSetPosition { position })
     }
 
-    fn build_instruction_place(node: &Node) -> Command {
-        let pos_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("HeadPosInstr"))
-            .unwrap();
-        let position_head = Self::build_position_head(&pos_node);
-        Command::Place { position_head }
+    fn build_instruction_pick(node: &Node) -> Result<Command, LoaderError> {
+        let pos_node = descendant(node, "HeadPosInstr")?;
+        let position_head = Self::build_position_head(&pos_node)?;
+        Ok(Command::Pick { position_head })
     }
 
-    fn build_instruction_run_method(node: &Node) -> Command {
-        let call_method_uid = node
-            .descendants()
-            .find(|n| n.has_tag_name("CalledMethod"))
-            .unwrap()
-            .text()
-            .unwrap();
+    fn build_instruction_place(node: &Node) -> Result<Command, LoaderError> {
+        let pos_node = descendant(node, "HeadPosInstr")?;
+        let position_head = Self::build_position_head(&pos_node)?;
+        Ok(Command::Place { position_head })
+    }
 
-        let param_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("Parameters"))
-            .unwrap();
+    fn build_instruction_run_method(node: &Node) -> Result<Command, LoaderError> {
+        let call_method_uid = element_text(&descendant(node, "CalledMethod")?);
+
+        let param_node = descendant(node, "Parameters")?;
         let mut parameters = Vec::new();
         for c in param_node.children().filter(|n| n.is_element()).skip(1) {
-            parameters.push(Self::build_parameter(&c));
+            parameters.push(Self::build_parameter(&c)?);
         }
-        Command::RunMethod {
-            method: call_method_uid.parse().unwrap(),
+        Ok(Command::RunMethod {
+            method: parse_text(call_method_uid, "CalledMethod")?,
             parameters,
-        }
+        })
     }
 
-    fn build_instruction_run_shaker_for_time(node: &Node) -> Command {
-        let speed_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("Speed"))
-            .unwrap();
-        let speed = Self::build_instruction_value(&speed_node, VariableType::Float);
-        let timeout_node = speed_node
-            .next_siblings()
-            .find(|n| n.has_tag_name("TimeoutDuration"))
-            .unwrap();
-        let timeout = Self::build_instruction_value(&timeout_node, VariableType::Seconds);
-        Command::RunShakerForTime { speed, timeout }
+    fn build_instruction_run_shaker_for_time(node: &Node) -> Result<Command, LoaderError> {
+        let speed_node = descendant(node, "Speed")?;
+        let speed = Self::build_instruction_value(&speed_node, VariableType::Float)?;
+        let timeout_node = next_sibling(&speed_node, "TimeoutDuration")?;
+        let timeout = Self::build_instruction_value(&timeout_node, VariableType::Seconds)?;
+        Ok(Command::RunShakerForTime { speed, timeout })
     }
 
-    fn build_instruction_rem(node: &Node) -> Command {
-        let msg_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("CommentText"))
-            .unwrap();
-        let comment = match msg_node.text() {
-            Some(s) => s.to_string(),
-            None => "".to_string(),
+    fn build_instruction_rem(node: &Node) -> Result<Command, LoaderError> {
+        let msg_node = descendant(node, "CommentText")?;
+        let comment = element_text(&msg_node).to_string();
+        Ok(Command::REM { comment })
+    }
+
+    fn build_instruction_relative_move(node: &Node) -> Result<Command, LoaderError> {
+        let (x, y, z) = Self::build_move_axes(node)?;
+        Ok(Command::RelativeMove { x, y, z })
+    }
+
+    fn build_instruction_absolute_move(node: &Node) -> Result<Command, LoaderError> {
+        let (x, y, z) = Self::build_move_axes(node)?;
+        Ok(Command::AbsoluteMove { x, y, z })
+    }
+
+    // Relative and absolute moves are both saved as <RelativeMoveInstr>, told apart by their
+    // <MoveType>. An axis that is not used does not move, whatever its value, and neither does one
+    // that is missing.
+    fn build_move_axes(node: &Node) -> Result<MoveAxes, LoaderError> {
+        let move_node = descendant(node, "RelativeMoveInstr")?;
+        let fields = text_only_children(&move_node);
+        let axis = |use_tag, tag| -> Result<Option<InstructionValue>, LoaderError> {
+            let used = field(&fields, use_tag)
+                .map(Self::build_bool)
+                .unwrap_or(false);
+            match move_node.children().find(|n| n.has_tag_name(tag)) {
+                Some(axis_node) if used => Ok(Some(Self::build_instruction_value(
+                    &axis_node,
+                    VariableType::Float,
+                )?)),
+                _ => Ok(None),
+            }
         };
-        Command::REM { comment }
+        Ok((axis("UseX", "X")?, axis("UseY", "Y")?, axis("UseZ", "Z")?))
     }
 
-    fn build_instruction_set_light_intensity(node: &Node) -> Command {
-        let light_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("LegLightPercentage"))
-            .unwrap();
-        let percentage = Self::build_instruction_value(&light_node, VariableType::Float);
-        Command::SetLegLightIntensity { percentage }
+    fn build_instruction_set_light_intensity(node: &Node) -> Result<Command, LoaderError> {
+        let light_node = descendant(node, "LegLightPercentage")?;
+        let percentage = Self::build_instruction_value(&light_node, VariableType::Float)?;
+        Ok(Command::SetLegLightIntensity { percentage })
     }
 
-    fn build_instruction_set_speed(node: &Node) -> Command {
-        let speed_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("Speed"))
-            .unwrap();
-        let speed = Self::build_instruction_value(&speed_node, VariableType::Float);
-        Command::SetSpeed { speed }
+    fn build_instruction_set_speed(node: &Node) -> Result<Command, LoaderError> {
+        let speed_node = descendant(node, "Speed")?;
+        let speed = Self::build_instruction_value(&speed_node, VariableType::Float)?;
+        Ok(Command::SetSpeed { speed })
     }
 
-    fn build_instruction_shaker_on_off(node: &Node) -> Command {
-        let device = node
-            .descendants()
-            .find(|n| n.has_tag_name("DCCControl"))
-            .unwrap()
-            .text()
-            .unwrap()
-            .to_string();
-        let on_off_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("TurnOn"))
-            .unwrap();
-        let on_off = Self::build_instruction_value(&on_off_node, VariableType::Bool);
-        Command::ShakerOnOff { device, on_off }
+    fn build_instruction_set_travel_height(node: &Node) -> Result<Command, LoaderError> {
+        let height_node = descendant(node, "TravelHeight")?;
+        let height = Self::build_instruction_value(&height_node, VariableType::Float)?;
+        Ok(Command::SetTravelHeight { height })
     }
 
-    fn build_instruction_while_loop(node: &Node) -> Command {
-        let if_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("ControlInstr_WhileLoop"))
-            .unwrap();
-        let fields = text_only_children(&if_node);
-        let comparator = Self::build_comparator(fields.get(INSTR_COMPARATOR).unwrap());
-        let var_type = Self::build_test_variable_type(fields.get("ComparisonType").unwrap());
-        let mut instr_val = Vec::new();
-        for c in if_node.children().filter(|n| n.is_element()).skip(2) {
-            instr_val.push(Self::build_instruction_value(&c, var_type));
-        }
-        let rhs = instr_val.pop().unwrap();
-        let lhs = instr_val.pop().unwrap();
-        Command::IfThen {
+    fn build_instruction_shaker_on_off(node: &Node) -> Result<Command, LoaderError> {
+        let device = element_text(&descendant(node, "DCCControl")?).to_string();
+        let on_off_node = descendant(node, "TurnOn")?;
+        let on_off = Self::build_instruction_value(&on_off_node, VariableType::Bool)?;
+        Ok(Command::ShakerOnOff { device, on_off })
+    }
+
+    fn build_instruction_string_operation(node: &Node) -> Result<Command, LoaderError> {
+        let string_node = descendant(node, "ControlInstr_StringOps")?;
+        let fields = text_only_children(&string_node);
+        let operation = field(&fields, "Operation")?;
+        let result_node = descendant(&string_node, "Result")?;
+        // Operands are numbered from 1 like <Operand2>, and only as many as the operation takes
+        // are saved
+        let operands = string_node
+            .children()
+            .filter(|n| n.is_element() && n.tag_name().name().starts_with("Operand"))
+            .map(|n| Self::build_instruction_value(&n, VariableType::String))
+            .collect::<Result<_, _>>()?;
+        Ok(Command::StringOperation {
+            operator: Self::build_string_operator(operation)?,
+            operands,
+            result: Self::build_instruction_value(&result_node, VariableType::String)?,
+        })
+    }
+
+    fn build_instruction_while_loop(node: &Node) -> Result<Command, LoaderError> {
+        let while_node = descendant(node, "ControlInstr_WhileLoop")?;
+        let (comparator, lhs, rhs) = Self::build_test(&while_node, "ComparisonType")?;
+        Ok(Command::WhileLoop {
             comparator,
             lhs,
             rhs,
-        }
+        })
     }
 
-    fn build_show_dialog(node: &Node) -> Command {
-        let msg_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("DisplayText"))
-            .unwrap();
-        Command::ShowDialog {
-            text: msg_node.text().unwrap().to_string(),
-        }
+    fn build_show_dialog(node: &Node) -> Result<Command, LoaderError> {
+        let msg_node = descendant(node, "DisplayText")?;
+        Ok(Command::ShowDialog {
+            text: element_text(&msg_node).to_string(),
+        })
     }
 
-    fn build_instruction_temperature_on_off(node: &Node) -> Command {
-        let fields = text_only_children(&node);
-        let device = fields.get("DCCControl").unwrap().to_string();
-        let temp_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("TurnOn"))
-            .unwrap();
-        let on_off = Self::build_instruction_value(&temp_node, VariableType::Bool);
-        Command::TemperatureOnOff { device, on_off }
+    fn build_instruction_temperature_on_off(node: &Node) -> Result<Command, LoaderError> {
+        let fields = text_only_children(node);
+        let device = field(&fields, "DCCControl")?.to_string();
+        let temp_node = descendant(node, "TurnOn")?;
+        let on_off = Self::build_instruction_value(&temp_node, VariableType::Bool)?;
+        Ok(Command::TemperatureOnOff { device, on_off })
     }
 
-    fn build_instruction_set_temperature(node: &Node) -> Command {
-        let device_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("DCCControl"))
-            .unwrap();
-        let device = device_node.text().unwrap().to_string();
-        let temp_node = node
-            .descendants()
-            .find(|n| n.has_tag_name("Temperature"))
-            .unwrap();
-        let temperature = Self::build_instruction_value(&temp_node, VariableType::Float);
-        Command::SetTemperature {
+    fn build_instruction_set_temperature(node: &Node) -> Result<Command, LoaderError> {
+        let device = element_text(&descendant(node, "DCCControl")?).to_string();
+        let temp_node = descendant(node, "Temperature")?;
+        let temperature = Self::build_instruction_value(&temp_node, VariableType::Float)?;
+        Ok(Command::SetTemperature {
             device,
             temperature,
-        }
+        })
     }
 
-    fn build_instruction_value(node: &Node, value_type: VariableType) -> InstructionValue {
+    fn build_instruction_value(
+        node: &Node,
+        value_type: VariableType,
+    ) -> Result<InstructionValue, LoaderError> {
         let fields = text_only_children(node);
-        let value_str = fields.get(INSTR_DIRECT_VALUE).unwrap();
-        let var_str = fields.get(INSTR_VARIABLE).unwrap();
-        let var: Option<Uuid> = if *var_str == "[[[[---NONE---]]]]" {
+        let value_str = field(&fields, INSTR_DIRECT_VALUE)?;
+        let var_str = field(&fields, INSTR_VARIABLE)?.trim();
+        let var: Option<Uuid> = if var_str == "[[[[---NONE---]]]]" {
             None
         } else {
-            Some(var_str.parse().unwrap())
+            Some(parse_text(var_str, INSTR_VARIABLE)?)
         };
         let value = match value_type {
             VariableType::Bool => {
-                let b = Self::build_bool(&value_str);
+                let b = Self::build_bool(value_str);
                 VariableValue::Bool(b)
             }
-            VariableType::Float => VariableValue::Float(value_str.parse().unwrap()),
-            VariableType::Int => VariableValue::Int(value_str.parse().unwrap()),
+            VariableType::Float => VariableValue::Float(parse_text(value_str, INSTR_DIRECT_VALUE)?),
+            VariableType::Int => VariableValue::Int(parse_text(value_str, INSTR_DIRECT_VALUE)?),
+            // Free text keeps its whitespace, only numbers and ids are trimmed
             VariableType::String => VariableValue::String(value_str.to_string()),
-            VariableType::Seconds => VariableValue::Seconds(value_str.parse().unwrap()),
+            VariableType::Seconds => {
+                VariableValue::Seconds(parse_text(value_str, INSTR_DIRECT_VALUE)?)
+            }
         };
-        InstructionValue {
+        Ok(InstructionValue {
             variable: var,
             direct: value,
-        }
+        })
     }
 }
 
-/// The state of the Maestro application when it was saved. The Maestro export format may change, but
-/// this class will strive to provide a constant access API.
-///
-/// # Example
-///
-/// ```
-/// // Read the XML string of an empty application
-/// let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-/// d.push("resources/test/Application_Empty.eap");
-/// let empty_app = std::fs::read_to_string(d).unwrap();
-///
-///let app = maestro_ngs_application::Loader::new(&empty_app).build_application();
-/// ```
-///
-pub struct SavedApplication {
-    start_method: Uuid,
-    global_variables: HashMap<Uuid, Variable>,
-    layouts: HashMap<Uuid, Layout>,
-    methods: HashMap<Uuid, Method>,
+#[derive(Debug)]
+pub enum LoaderError {
+    InstructionCountMismatch {
+        method: Uuid,
+        declared: usize,
+        found: usize,
+    },
+    XmlParse(roxmltree::Error),
+    /// The element is missing
+    MissingField(&'static str),
+    /// The element has a value that cannot be parsed or is not known
+    InvalidField {
+        tag: &'static str,
+        value: String,
+        error: String,
+    },
+    UnknownMethod(Uuid),
+    UnsupportedVersion {
+        found: f64,
+        supported: (f64, f64),
+    },
 }
 
-impl SavedApplication {
-    fn set_global_variables(&mut self, pool: VariablesPool) {
-        self.global_variables = pool.variables;
+impl std::fmt::Display for LoaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InstructionCountMismatch {
+                method,
+                declared,
+                found,
+            } => write!(
+                f,
+                "method {} declares {} instructions but has {}",
+                method, declared, found
+            ),
+            Self::XmlParse(e) => write!(f, "not a valid XML document: {}", e),
+            Self::MissingField(tag) => write!(f, "missing the {} element", tag),
+            Self::InvalidField { tag, value, error } => {
+                write!(f, "invalid {} element \"{}\": {}", tag, value, error)
+            }
+            Self::UnknownMethod(uuid) => write!(f, "unknown method ({})", uuid),
+            Self::UnsupportedVersion {
+                found,
+                supported: (min, max),
+            } => write!(
+                f,
+                "exported from Maestro {}, but only {} to {} are supported",
+                found, min, max
+            ),
+        }
     }
+}
 
-    fn add_layout(&mut self, layout: Layout) {
-        self.layouts.insert(layout.id, layout);
+impl std::error::Error for LoaderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::XmlParse(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+fn check_instruction_count(method: &Method) -> Result<(), LoaderError> {
+    if method.declared_instructions != method.instructions.len() {
+        Err(LoaderError::InstructionCountMismatch {
+            method: method.id,
+            declared: method.declared_instructions,
+            found: method.instructions.len(),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+/// A problem with a change to a saved application
+#[derive(Debug, PartialEq)]
+pub enum EditError {
+    UnknownMethod(Uuid),
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownMethod(uuid) => write!(f, "unknown method ({})", uuid),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// A pipetting instruction that may run without tips, as found by `SavedApplication::lint_tip_usage`
+#[derive(Debug, PartialEq)]
+pub struct TipLintIssue {
+    pub method: Uuid,
+    pub line: usize,
+}
+
+/// A problem with the arguments of a method call, as found by
+/// `SavedApplication::validate_arguments`
+#[derive(Debug, PartialEq)]
+pub enum ArgError {
+    MissingArgument(Uuid),
+    MistypedArgument(Uuid),
+    UnknownMethod(Uuid),
+    UnknownParameter(Uuid),
+}
+
+impl std::fmt::Display for ArgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingArgument(uuid) => write!(f, "no argument for parameter {}", uuid),
+            Self::MistypedArgument(uuid) => {
+                write!(f, "argument for parameter {} has the wrong type", uuid)
+            }
+            Self::UnknownMethod(uuid) => write!(f, "unknown method ({})", uuid),
+            Self::UnknownParameter(uuid) => write!(f, "unknown parameter ({})", uuid),
+        }
+    }
+}
+
+impl std::error::Error for ArgError {}
+
+/// The state of the Maestro application when it was saved. The Maestro export format may change, but
+/// this class will strive to provide a constant access API.
+///
+/// # Example
+///
+/// ```
+/// // Read the XML string of an empty application
+/// let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+/// d.push("resources/test/Application_Empty.eap");
+/// let empty_app = std::fs::read_to_string(d).unwrap();
+///
+///let app = maestro_ngs_application::Loader::new(&empty_app).unwrap().build_application().unwrap();
+/// ```
+///
+#[derive(PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct SavedApplication {
+    start_method: Uuid,
+    global_variables: HashMap<Uuid, Variable>,
+    layouts: HashMap<Uuid, Layout>,
+    methods: HashMap<Uuid, Method>,
+    method_order: Vec<Uuid>,
+}
+
+impl SavedApplication {
+    fn set_global_variables(&mut self, pool: VariablesPool) {
+        self.global_variables = pool.variables;
+    }
+
+    fn add_layout(&mut self, layout: Layout) {
+        self.layouts.insert(layout.id, layout);
     }
 
     fn add_method(&mut self, method: Method) {
-        self.methods.insert(method.id, method);
+        let id = method.id;
+        if self.methods.insert(id, method).is_none() {
+            self.method_order.push(id);
+        }
+    }
+
+    /// Replace a method with its parse from the loader, such as an export saved after the method
+    /// was edited, leaving the rest of the application as it is. The method is checked like
+    /// `Loader::try_build_application` does.
+    pub fn reparse_method(&mut self, loader: &Loader, method_id: Uuid) -> Result<(), LoaderError> {
+        let node = loader
+            .find_method_node(method_id)
+            .ok_or(LoaderError::UnknownMethod(method_id))?;
+        let method = Loader::build_method(&node, loader.keep_source)?;
+        check_instruction_count(&method)?;
+        self.add_method(method);
+        Ok(())
+    }
+
+    /// Add a copy of a method under a new id and designation, to be edited without changing the
+    /// original. Its variable pools get new ids too. Its variables keep their ids, as variables are
+    /// looked up within their method, so the instructions of the copy need no rewiring.
+    pub fn duplicate_method(
+        &mut self,
+        src: Uuid,
+        new_designation: &str,
+    ) -> Result<Uuid, EditError> {
+        let mut method = self
+            .methods
+            .get(&src)
+            .cloned()
+            .ok_or(EditError::UnknownMethod(src))?;
+        method.id = Uuid::new_v4();
+        method.designation = new_designation.to_string();
+        method.local_variables_pool.id = Uuid::new_v4();
+        method.local_variables_pool.designation = format!("{}:LOCAL Variables", new_designation);
+        method.parameters.id = Uuid::new_v4();
+        method.parameters.designation = format!("{}:Parameters", new_designation);
+        let id = method.id;
+        self.add_method(method);
+        Ok(id)
+    }
+
+    /// Bring the application to a canonical form, so two exports that differ only cosmetically
+    /// compare equal: designations are trimmed and methods are ordered by id. Ids are parsed rather
+    /// than kept as text, so their casing already makes no difference. The order of instructions
+    /// is left as it is, as it is what the methods do.
+    pub fn canonicalize(&mut self) {
+        self.method_order.sort();
+        for variable in self.global_variables.values_mut() {
+            trim_designation(&mut variable.designation);
+        }
+        for layout in self.layouts.values_mut() {
+            trim_designation(&mut layout.designation);
+            for location in layout.positions.values_mut() {
+                trim_designation(&mut location.position);
+                trim_designation(&mut location.designation);
+            }
+        }
+        for method in self.methods.values_mut() {
+            trim_designation(&mut method.designation);
+            for pool in [&mut method.local_variables_pool, &mut method.parameters] {
+                trim_designation(&mut pool.designation);
+                for variable in pool.variables.values_mut() {
+                    trim_designation(&mut variable.designation);
+                }
+            }
+        }
+    }
+
+    /// Every id the application refers to: the start method, the layout of each method, consumables
+    /// of layout locations, and the methods, parameters, variables and deck positions instructions
+    /// refer to. Ids are not included just for being defined.
+    pub fn all_referenced_uuids(&self) -> HashSet<Uuid> {
+        let mut uuids = HashSet::new();
+        uuids.insert(self.start_method);
+        for layout in self.layouts.values() {
+            uuids.extend(
+                layout
+                    .positions
+                    .values()
+                    .map(|l| l.consumable)
+                    .filter(|c| !c.is_nil()),
+            );
+        }
+        for method in self.methods.values() {
+            uuids.insert(method.layout_id);
+            for instr in &method.instructions {
+                instr.command.add_referenced_uuids(&mut uuids);
+            }
+        }
+        uuids
     }
 
     /// Global variables of saved application
@@ -855,22 +1242,63 @@ impl SavedApplication {
         self.methods.keys().collect()
     }
 
+    /// The method ids in the order they appear in the exported application
+    pub fn methods_in_order(&self) -> Vec<Uuid> {
+        self.method_order.clone()
+    }
+
     /// Instruction from method
     pub fn instruction(&self, method_id: Uuid, line: usize) -> Option<&Instruction> {
-        self.methods.get(&method_id).and_then(|m| m.instructions.get(line))
+        self.methods
+            .get(&method_id)
+            .and_then(|m| m.instructions.get(line))
     }
 
     /// How many instructions in the method
     pub fn instruction_count(&self, method_id: Uuid) -> Option<usize> {
-        self.methods.get(&method_id).and_then(|m| Some(m.instructions.len()))
+        self.methods.get(&method_id).map(|m| m.instructions.len())
     }
 
     /// The layout associated with the specified method
     pub fn layout_of_method(&self, method_id: Uuid) -> Option<Uuid> {
-        match self.methods.get(&method_id) {
-            Some(method) => Some(method.layout_id),
-            None => None,
-        }
+        self.methods.get(&method_id).map(|m| m.layout_id)
+    }
+
+    /// The layouts not used by any method, in order of their id
+    pub fn unused_layouts(&self) -> Vec<Uuid> {
+        let used: std::collections::HashSet<Uuid> =
+            self.methods.values().map(|m| m.layout_id).collect();
+        let mut unused: Vec<Uuid> = self
+            .layouts
+            .keys()
+            .filter(|id| !used.contains(id))
+            .cloned()
+            .collect();
+        unused.sort();
+        unused
+    }
+
+    /// How many deck positions the layout defines
+    pub fn layout_slot_count(&self, layout_id: Uuid) -> Option<usize> {
+        self.layouts.get(&layout_id).map(|l| l.positions.len())
+    }
+
+    /// The deck positions the layout defines, such as "C3", in alphabetical order
+    pub fn layout_slots(&self, layout_id: Uuid) -> Option<Vec<&str>> {
+        self.layouts.get(&layout_id).map(|l| {
+            let mut slots: Vec<&str> = l.positions.values().map(|p| p.position.as_str()).collect();
+            slots.sort_unstable();
+            slots
+        })
+    }
+
+    /// Every location of the layout, in alphabetical order of their deck position
+    pub fn positions_of_layout(&self, layout_id: Uuid) -> Option<Vec<&Location>> {
+        self.layouts.get(&layout_id).map(|l| {
+            let mut locations: Vec<&Location> = l.positions.values().collect();
+            locations.sort_by(|a, b| (&a.position, a.id).cmp(&(&b.position, b.id)));
+            locations
+        })
     }
 
     /// Get all layouts of saved method
@@ -882,7 +1310,24 @@ impl SavedApplication {
     pub fn local_variables_of_method(&self, method_id: Uuid) -> Option<&HashMap<Uuid, Variable>> {
         self.methods
             .get(&method_id)
-            .and_then(|m| Some(&m.local_variables_pool.variables))
+            .map(|m| &m.local_variables_pool.variables)
+    }
+
+    /// The global variable with the name. Names are not unique, so if several match, the one with
+    /// the lowest id is returned, so the same variable is found on every call.
+    pub fn global_var_by_designation(&self, name: &str) -> Option<&Variable> {
+        self.global_variables
+            .values()
+            .filter(|v| v.designation == name)
+            .min_by_key(|v| v.id)
+    }
+
+    /// The local variable of the method with the name, picked like `global_var_by_designation`
+    pub fn local_var_by_designation(&self, method_id: Uuid, name: &str) -> Option<&Variable> {
+        self.local_variables_of_method(method_id)?
+            .values()
+            .filter(|v| v.designation == name)
+            .min_by_key(|v| v.id)
     }
 
     /// The name of the global variable
@@ -913,16 +1358,214 @@ impl SavedApplication {
     pub fn parameters_of_method(&self, method_id: Uuid) -> Option<&HashMap<Uuid, Variable>> {
         self.methods
             .get(&method_id)
-            .and_then(|m| Some(&m.parameters.variables))
+            .map(|m| &m.parameters.variables)
+    }
+
+    /// A hash of the content of the application, for caching results of an application that has not
+    /// changed. Applications with the same methods, instructions, variables and layouts have the
+    /// same fingerprint, whatever order they were loaded in.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = fnv1a(FNV_OFFSET, self.start_method.as_bytes());
+        hash = hash_variables(hash, &self.global_variables);
+
+        let mut layouts: Vec<&Layout> = self.layouts.values().collect();
+        layouts.sort_by_key(|l| l.id);
+        for layout in layouts {
+            hash = fnv1a(hash, layout.id.as_bytes());
+            hash = hash_str(hash, &layout.designation);
+            let mut positions: Vec<&Location> = layout.positions.values().collect();
+            positions.sort_by_key(|l| l.id);
+            for location in positions {
+                hash = fnv1a(hash, location.id.as_bytes());
+                hash = hash_str(hash, &location.position);
+                hash = fnv1a(hash, &location.number_stacked.to_le_bytes());
+                hash = hash_str(hash, &location.designation);
+                hash = fnv1a(hash, location.consumable.as_bytes());
+                hash = hash_str(hash, &format!("{:?}", location.tip_size));
+                hash = hash_str(hash, &format!("{:?}", location.well_capacity));
+            }
+        }
+
+        let mut methods: Vec<&Method> = self.methods.values().collect();
+        methods.sort_by_key(|m| m.id);
+        for method in methods {
+            hash = fnv1a(hash, method.id.as_bytes());
+            hash = hash_str(hash, &method.designation);
+            hash = fnv1a(hash, method.layout_id.as_bytes());
+            hash = hash_variables(hash, &method.local_variables_pool.variables);
+            hash = hash_variables(hash, &method.parameters.variables);
+            for instr in &method.instructions {
+                hash = fnv1a(hash, &[instr.is_comment as u8]);
+                hash = hash_str(hash, &format!("{:?}", instr.command));
+            }
+        }
+        hash
+    }
+
+    /// Pipetting instructions of the method that have no Load Tips since the start of the method or
+    /// the last Eject Tips. This is a heuristic over the order the instructions are written in: it
+    /// does not follow loops, branches or calls, so it may flag instructions that are fine, e.g. in a
+    /// method called with tips already loaded. Comments are ignored.
+    pub fn lint_tip_usage(&self, method_id: Uuid) -> Vec<TipLintIssue> {
+        let method = match self.methods.get(&method_id) {
+            Some(m) => m,
+            None => return Vec::new(),
+        };
+        let mut tips_loaded = false;
+        let mut issues = Vec::new();
+        for (line, instr) in method.instructions.iter().enumerate() {
+            if instr.is_comment {
+                continue;
+            }
+            match instr.command {
+                Command::LoadTips { .. } => tips_loaded = true,
+                Command::EjectTips { .. } => tips_loaded = false,
+                Command::Aspirate { .. } | Command::Dispense { .. } | Command::Mix { .. }
+                    if !tips_loaded =>
+                {
+                    issues.push(TipLintIssue {
+                        method: method_id,
+                        line,
+                    })
+                }
+                _ => {}
+            }
+        }
+        issues
+    }
+
+    /// Lines of the method with a Load Tips that follows another Load Tips with no Eject Tips in
+    /// between, which fails on the machine with tips already loaded. Like `lint_tip_usage`, this
+    /// goes by the order the instructions are written in, so a load in each branch of a condition
+    /// is flagged although only one runs. Comments are ignored.
+    pub fn redundant_tip_loads(&self, method_id: Uuid) -> Vec<usize> {
+        let method = match self.methods.get(&method_id) {
+            Some(m) => m,
+            None => return Vec::new(),
+        };
+        let mut tips_loaded = false;
+        let mut lines = Vec::new();
+        for (line, instr) in method.instructions.iter().enumerate() {
+            if instr.is_comment {
+                continue;
+            }
+            match instr.command {
+                Command::LoadTips { .. } if tips_loaded => lines.push(line),
+                Command::LoadTips { .. } => tips_loaded = true,
+                Command::EjectTips { .. } => tips_loaded = false,
+                _ => {}
+            }
+        }
+        lines
+    }
+
+    /// The method and line of every instruction that writes to the variable: math and string
+    /// operations storing into it, loops counting with it as their index, and method calls passing
+    /// an argument for it as a parameter. Methods are in the order of the export, and comments are
+    /// ignored.
+    pub fn writers_of(&self, var: Uuid) -> Vec<(Uuid, usize)> {
+        let mut writers = Vec::new();
+        for method_id in &self.method_order {
+            let method = &self.methods[method_id];
+            for (line, instr) in method.instructions.iter().enumerate() {
+                if instr.is_comment {
+                    continue;
+                }
+                let writes = match &instr.command {
+                    Command::MathOperation { lhs, .. } => lhs.variable == Some(var),
+                    Command::StringOperation { result, .. } => result.variable == Some(var),
+                    Command::BeginLoop { index, .. } => index.variable == Some(var),
+                    Command::RunMethod { parameters, .. } => parameters.iter().any(|p| p.id == var),
+                    _ => false,
+                };
+                if writes {
+                    writers.push((*method_id, line));
+                }
+            }
+        }
+        writers
+    }
+
+    /// Every method with an instruction that sets or turns on or off the device, named by its DCC
+    /// control like "ThermalLocator4", in the order of the export. Comments are ignored.
+    pub fn methods_using_device(&self, device: &str) -> Vec<Uuid> {
+        self.method_order
+            .iter()
+            .filter(|&method_id| {
+                self.methods[method_id]
+                    .instructions
+                    .iter()
+                    .filter(|instr| !instr.is_comment)
+                    .any(|instr| match &instr.command {
+                        Command::SetTemperature { device: d, .. }
+                        | Command::ShakerOnOff { device: d, .. }
+                        | Command::TemperatureOnOff { device: d, .. } => d == device,
+                        _ => false,
+                    })
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// The methods each method calls with Run Method, in the order of its instructions and each
+    /// only once. Every method has an entry, so those that call nothing map to no callees.
+    /// Comments are ignored.
+    pub fn call_graph(&self) -> HashMap<Uuid, Vec<Uuid>> {
+        self.methods
+            .iter()
+            .map(|(&method_id, method)| {
+                let mut callees: Vec<Uuid> = Vec::new();
+                for instr in method.instructions.iter().filter(|i| !i.is_comment) {
+                    if let Command::RunMethod { method, .. } = instr.command {
+                        if !callees.contains(&method) {
+                            callees.push(method);
+                        }
+                    }
+                }
+                (method_id, callees)
+            })
+            .collect()
+    }
+
+    /// Check arguments for a call to the method: every parameter needs an argument with the type
+    /// of the parameter, and every argument must be a parameter. Parameters are checked in order of
+    /// their id, and the first problem found is returned.
+    pub fn validate_arguments(
+        &self,
+        method_id: Uuid,
+        args: &HashMap<Uuid, VariableValue>,
+    ) -> Result<(), ArgError> {
+        let params = self
+            .parameters_of_method(method_id)
+            .ok_or(ArgError::UnknownMethod(method_id))?;
+        let mut ids: Vec<&Uuid> = params.keys().collect();
+        ids.sort();
+        for id in ids {
+            let arg = args.get(id).ok_or(ArgError::MissingArgument(*id))?;
+            if std::mem::discriminant(arg) != std::mem::discriminant(params[id].value()) {
+                return Err(ArgError::MistypedArgument(*id));
+            }
+        }
+        let mut unknown: Vec<&Uuid> = args.keys().filter(|id| !params.contains_key(id)).collect();
+        unknown.sort();
+        match unknown.first() {
+            Some(&&id) => Err(ArgError::UnknownParameter(id)),
+            None => Ok(()),
+        }
     }
 
     /// The method that called at the start of the application
     pub fn start_method(&self) -> Uuid {
         self.start_method
     }
+
+    /// The designation of the method called at the start of the application
+    pub fn start_method_name(&self) -> Option<&str> {
+        self.name_method(self.start_method)
+    }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
 pub enum VariableValue {
     Bool(bool),
     Float(f64),
@@ -931,6 +1574,34 @@ pub enum VariableValue {
     Seconds(u32),
 }
 
+impl VariableValue {
+    pub fn variable_type(&self) -> VariableType {
+        match self {
+            VariableValue::Bool(_) => VariableType::Bool,
+            VariableValue::Float(_) => VariableType::Float,
+            VariableValue::Int(_) => VariableType::Int,
+            VariableValue::String(_) => VariableType::String,
+            VariableValue::Seconds(_) => VariableType::Seconds,
+        }
+    }
+}
+
+/// The value in its natural JSON type, without the variant tag of the `Serialize` implementation.
+/// A float that is not finite becomes null.
+impl From<&VariableValue> for serde_json::Value {
+    fn from(value: &VariableValue) -> Self {
+        match value {
+            VariableValue::Bool(b) => serde_json::Value::Bool(*b),
+            VariableValue::Float(f) => serde_json::Number::from_f64(*f)
+                .map(serde_json::Value::Number)
+                .unwrap_or(serde_json::Value::Null),
+            VariableValue::Int(i) => serde_json::Value::from(*i),
+            VariableValue::String(s) => serde_json::Value::String(s.clone()),
+            VariableValue::Seconds(s) => serde_json::Value::from(*s),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum VariableType {
     Bool,
@@ -940,18 +1611,105 @@ pub enum VariableType {
     Seconds,
 }
 
+/// The methods of an application without their instructions, as built by `Loader::build_index`
+#[derive(Debug)]
+pub struct AppIndex {
+    pub start_method: Uuid,
+    /// In the order they appear in the exported application
+    pub methods: Vec<MethodSignature>,
+}
+
+#[derive(Debug)]
+pub struct MethodSignature {
+    pub designation: String,
+    pub id: Uuid,
+    pub layout_id: Uuid,
+    /// In the order they appear in the exported application
+    pub parameters: Vec<Variable>,
+}
+
+/// Callbacks for `Loader::visit`. They do nothing by default, so a visitor only implements the
+/// ones it needs.
+pub trait AppVisitor {
+    /// A method, with its parameters, before its local variables and instructions
+    fn on_method(&mut self, _method: &MethodSignature) {}
+
+    /// A local variable of the method, or a global variable if there is no method
+    fn on_variable(&mut self, _method: Option<Uuid>, _variable: &Variable) {}
+
+    fn on_instruction(&mut self, _method: Uuid, _instruction: &Instruction) {}
+}
+
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 struct VariablesPool {
     designation: String,
     id: Uuid,
     variables: HashMap<Uuid, Variable>,
 }
-#[derive(Debug, Clone)]
+/// A variable of the application, with the value it was saved with
+///
+/// # Example
+///
+/// ```
+/// use maestro_ngs_application::{Loader, VariableValue};
+///
+/// let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+/// d.push("resources/test/Application_Complex.eap");
+/// let text = std::fs::read_to_string(d).unwrap();
+/// let app = Loader::new(&text).unwrap().build_application().unwrap();
+///
+/// let offset = app
+///     .global_variables()
+///     .values()
+///     .find(|v| v.designation() == "g_ReservedTipBoxZOffset")
+///     .unwrap();
+/// assert_eq!(offset.value(), &VariableValue::Float(-10.0));
+/// assert!(app.global_variables().contains_key(&offset.id()));
+/// ```
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Variable {
     designation: String,
     id: Uuid,
     value: VariableValue,
+    value_labels: Option<Vec<(i64, String)>>,
+}
+
+impl Variable {
+    pub fn new(id: Uuid, designation: &str, value: VariableValue) -> Self {
+        Variable {
+            designation: designation.to_string(),
+            id,
+            value,
+            value_labels: None,
+        }
+    }
+
+    pub fn designation(&self) -> &str {
+        &self.designation
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// The current value of the variable
+    pub fn value(&self) -> &VariableValue {
+        &self.value
+    }
+
+    /// Overwrite the value of the variable
+    pub fn set_value(&mut self, value: VariableValue) {
+        self.value = value;
+    }
+
+    /// The names of the values the variable may take, if its permissible values are a list of
+    /// options rather than a range
+    pub fn value_labels(&self) -> Option<Vec<(i64, String)>> {
+        self.value_labels.clone()
+    }
 }
 
+#[derive(PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Layout {
     designation: String,
     id: Uuid,
@@ -959,40 +1717,201 @@ pub struct Layout {
 }
 
 impl Layout {
+    pub fn new(id: Uuid, designation: &str) -> Self {
+        Layout {
+            designation: designation.to_string(),
+            id,
+            positions: HashMap::new(),
+        }
+    }
+
+    /// Add a deck position, such as "C3", referenced by instructions through the variable id
+    pub fn add_position(&mut self, id: Uuid, position: &str) {
+        let location = Location {
+            id,
+            position: position.to_string(),
+            number_stacked: 1,
+            designation: position.to_string(),
+            consumable: Uuid::nil(),
+            tip_size: None,
+            well_capacity: None,
+        };
+        self.positions.insert(id, location);
+    }
+
+    /// Set the size of the tips, in uL, of a tip box at a deck position
+    pub fn set_tip_size(&mut self, id: Uuid, tip_size: f64) {
+        if let Some(location) = self.positions.get_mut(&id) {
+            location.tip_size = Some(tip_size);
+        }
+    }
+
+    /// Set the volume, in uL, a well at a deck position holds
+    pub fn set_well_capacity(&mut self, id: Uuid, capacity: f64) {
+        if let Some(location) = self.positions.get_mut(&id) {
+            location.well_capacity = Some(capacity);
+        }
+    }
+
     pub fn position(&self, uuid: Uuid) -> Option<&String> {
-        self.positions.get(&uuid).and_then(|l| Some(&l.position))
+        self.positions.get(&uuid).map(|l| &l.position)
+    }
+
+    /// The size of the tips, in uL, if the position holds a tip box of a known size
+    pub fn tip_size(&self, uuid: Uuid) -> Option<f64> {
+        self.positions.get(&uuid).and_then(|l| l.tip_size)
+    }
+
+    /// The volume, in uL, of a well at the position, if its consumable gives the well sizes
+    pub fn well_capacity(&self, uuid: Uuid) -> Option<f64> {
+        self.positions.get(&uuid).and_then(|l| l.well_capacity)
+    }
+
+    /// The location with the deck position, such as "D1", or the designation. If several match,
+    /// the one with the lowest id is returned, so the same location is found on every call.
+    pub fn position_by_designation(&self, name: &str) -> Option<&Location> {
+        self.positions
+            .values()
+            .filter(|l| l.position == name || l.designation == name)
+            .min_by_key(|l| l.id)
     }
 }
 
-struct Location {
+/// A deck position of a layout
+#[derive(PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Location {
     id: Uuid,
     position: String,
     number_stacked: u32,
     designation: String,
     consumable: Uuid,
+    tip_size: Option<f64>,
+    well_capacity: Option<f64>,
+}
+
+impl Location {
+    /// The id of the variable instructions reference the location by
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// The deck position, such as "D1"
+    pub fn position(&self) -> &str {
+        &self.position
+    }
+
+    pub fn designation(&self) -> &str {
+        &self.designation
+    }
+
+    /// How many consumables are stacked at the position
+    pub fn number_stacked(&self) -> u32 {
+        self.number_stacked
+    }
+
+    /// The id of the consumable or accessory at the position
+    pub fn consumable(&self) -> Uuid {
+        self.consumable
+    }
 }
 
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 struct Method {
     designation: String,
     id: Uuid,
     layout_id: Uuid,
     local_variables_pool: VariablesPool,
     parameters: VariablesPool,
+    // The InstructionsCount of the export, which may disagree with the instructions it contains
+    declared_instructions: usize,
     instructions: Vec<Instruction>,
 }
 
+#[derive(Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct Instruction {
     pub is_comment: bool,
     pub command: Command,
+    stable_id: u64,
+    source_index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<String>,
 }
 
-#[derive(Debug)]
+impl Instruction {
+    pub fn new(command: Command) -> Self {
+        Instruction {
+            is_comment: false,
+            command,
+            stable_id: 0,
+            source_index: 0,
+            source: None,
+        }
+    }
+
+    /// The line number the Maestro editor shows for the instruction, counting from 1
+    pub fn source_index(&self) -> usize {
+        self.source_index
+    }
+
+    /// The XML the instruction was parsed from, if the loader was set to keep it
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    /// Whether the instruction runs, is a REM, or is another instruction commented out. Maestro
+    /// flags both REMs and disabled instructions with `is_comment`.
+    pub fn kind(&self) -> InstructionKind {
+        match (&self.command, self.is_comment) {
+            (Command::REM { .. }, _) => InstructionKind::Comment,
+            (_, true) => InstructionKind::DisabledActive,
+            (_, false) => InstructionKind::Active,
+        }
+    }
+
+    /// Identity of the instruction that does not depend on its line number. It is derived from the
+    /// command and the number of identical commands before it in the method, so it survives
+    /// instructions being inserted or edited elsewhere in the method.
+    pub fn stable_id(&self) -> u64 {
+        self.stable_id
+    }
+}
+
+/// What an instruction is in the editor, as given by `Instruction::kind`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionKind {
+    Active,
+    /// A REM, which only holds text
+    Comment,
+    /// An instruction with its settings kept, that the editor has commented out
+    DisabledActive,
+}
+
+// The x, y and z of a move, if the axis is used
+type MoveAxes = (
+    Option<InstructionValue>,
+    Option<InstructionValue>,
+    Option<InstructionValue>,
+);
+
+/// A saved instruction and its settings. More instructions will be supported over time, so matches
+/// outside this crate need a wildcard arm.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[non_exhaustive]
 pub enum Command {
-    AbsoluteMove,
+    // Moves to the coordinates of the axes that are used
+    AbsoluteMove {
+        x: Option<InstructionValue>,
+        y: Option<InstructionValue>,
+        z: Option<InstructionValue>,
+    },
     ApplicationExit,
     Aspirate {
         position_head: PositionHead,
         volume: InstructionValue,
+        // Air drawn into the tips before and after the liquid. None if the instruction has no air
+        // gap or ignores it.
+        leading_air_gap: Option<InstructionValue>,
+        trailing_air_gap: Option<InstructionValue>,
     },
     BeginLoop {
         index: InstructionValue,
@@ -1001,10 +1920,14 @@ pub enum Command {
         steps: InstructionValue,
     },
     CloseWorkbook,
+    /// A dispense of the Sciclone head, to one well or, as an array dispense, to several
     Dispense {
         position_head: PositionHead,
         volume: InstructionValue,
         dispense_all: bool,
+        // Further wells of an array dispense, each with its volume, dispensed in order after the
+        // first. Empty for a dispense to one well. Not used with dispense all.
+        destinations: Vec<(PositionHead, f64)>,
     },
     DispenseMainArray {
         volume: InstructionValue,
@@ -1053,7 +1976,9 @@ pub enum Command {
         to: PositionHead,
     },
     OpenWorkbook,
-    PAxisSetPosition,
+    PAxisSetPosition {
+        position: InstructionValue,
+    },
     Pick {
         position_head: PositionHead,
     },
@@ -1063,7 +1988,12 @@ pub enum Command {
     REM {
         comment: String,
     },
-    RelativeMove,
+    // The distance to move along each axis that is used
+    RelativeMove {
+        x: Option<InstructionValue>,
+        y: Option<InstructionValue>,
+        z: Option<InstructionValue>,
+    },
     RunMethod {
         method: Uuid,
         parameters: Vec<Parameter>,
@@ -1083,7 +2013,9 @@ pub enum Command {
         device: String,
         temperature: InstructionValue,
     },
-    SetTravelHeight,
+    SetTravelHeight {
+        height: InstructionValue,
+    },
     SetWorkingDirectory,
     ShakerOnOff {
         device: String,
@@ -1094,158 +2026,1378 @@ pub enum Command {
     },
     StartTime,
     StopTimer,
-    StringOperation,
+    StringOperation {
+        operator: StringOperator,
+        operands: Vec<InstructionValue>,
+        result: InstructionValue,
+    },
+    // An instruction this crate does not know, with its designation
+    Unknown {
+        designation: String,
+    },
     TemperatureOnOff {
         device: String,
         on_off: InstructionValue,
     },
     Ungrip,
     VerticalPosition,
+    // The loop repeats while the test holds, like the test of If..Then
     WhileLoop {
-        operator: Operator,
+        comparator: Comparator,
         lhs: InstructionValue,
         rhs: InstructionValue,
     },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub enum Operator {
     Assign,
     Minus,
     Plus,
 }
 
-#[derive(Debug)]
-pub enum Comparator {
-    Equals,
-    GreaterThan,
-    GreaterThanOrEqual,
-    LessThan,
-    LessThanOrEqual,
+/// The operation of a String Operation. Only the code of assignment is known from exports, other
+/// operations keep the code Maestro saves them with.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum StringOperator {
+    Assign,
+    Other(u32),
 }
 
-#[derive(Debug)]
-pub struct InstructionValue {
-    pub direct: VariableValue,
-    pub variable: Option<Uuid>,
-}
+impl Command {
+    /// Designation of the instruction in a saved application, such as "Load Tips"
+    pub fn designation(&self) -> &str {
+        match self {
+            Command::AbsoluteMove { .. } => "Absolute Move",
+            Command::ApplicationExit => "Application Exit",
+            Command::Aspirate { .. } => "Aspirate",
+            Command::BeginLoop { .. } => "Begin Loop",
+            Command::CloseWorkbook => "CloseWorkbook",
+            Command::Dispense { .. } | Command::DispenseMainArray { .. } => "Dispense",
+            Command::EjectTips { .. } => "Eject Tips",
+            Command::EndIf => "End If",
+            Command::EndLoop => "End Loop",
+            Command::EndWhile => "End While",
+            Command::ExecuteVSTAMacro { .. } => "Execute VSTA Macro",
+            Command::GetCurrentPositionRelativeToReference => {
+                "Get Current Position Relative to Reference"
+            }
+            Command::HeadPosition { .. } => "Head Position",
+            Command::Home { .. } => "Home",
+            Command::HomePAxis => "Home P Axis",
+            Command::IfThen { .. } => "If..Then",
+            Command::Initialize => "Initialize",
+            Command::InitializeSystem => "Initialize System",
+            Command::LoadTips { .. } => "Load Tips",
+            Command::MathOperation { .. } => "Math Operation",
+            Command::Mix { .. } => "Mix",
+            Command::MoveMaterial { .. } => "Move Material",
+            Command::OpenWorkbook => "OpenWorkbook",
+            Command::PAxisSetPosition { .. } => "P Axis Set Position",
+            Command::Pick { .. } => "Pick",
+            Command::Place { .. } => "Place",
+            Command::REM { .. } => "REM",
+            Command::RelativeMove { .. } => "Relative Move",
+            Command::RunMacro => "RunMacro",
+            Command::RunMethod { .. } => "Run Method",
+            Command::RunShakerForTime { .. } => "Run Shaker For Time",
+            Command::SetLegLightIntensity { .. } => "Set Leg Light Intensity",
+            Command::SetSpeed { .. } => "Set Speed",
+            Command::SetTemperature { .. } => "Set Temperature",
+            Command::SetTravelHeight { .. } => "Set Travel Height",
+            Command::SetWorkingDirectory => "SetWorkingDirectory",
+            Command::ShakerOnOff { .. } => "Shaker On/Off",
+            Command::ShowDialog { .. } => "Show Dialog",
+            Command::StartTime => "Start Timer",
+            Command::StopTimer => "Stop Timer",
+            Command::StringOperation { .. } => "String Operation",
+            Command::TemperatureOnOff { .. } => "Temperature On/Off",
+            Command::Ungrip => "UnGrip",
+            Command::Unknown { designation } => designation,
+            Command::VerticalPosition => "Vertical Position",
+            Command::WhileLoop { .. } => "While Loop",
+        }
+    }
+
+    /// Ids of the variables and parameters the command reads or writes
+    pub fn variables(&self) -> Vec<Uuid> {
+        self.values_and_positions()
+            .0
+            .iter()
+            .filter_map(|v| v.variable)
+            .collect()
+    }
+
+    /// Ids of the layout positions the command is given as deck parameters
+    pub fn deck_positions(&self) -> Vec<Uuid> {
+        self.values_and_positions().1
+    }
+
+    fn add_referenced_uuids(&self, uuids: &mut HashSet<Uuid>) {
+        if let Command::RunMethod { method, parameters } = self {
+            uuids.insert(*method);
+            uuids.extend(parameters.iter().map(|p| p.id));
+        }
+        uuids.extend(self.variables());
+        uuids.extend(self.deck_positions());
+    }
+
+    fn values_and_positions(&self) -> (Vec<&InstructionValue>, Vec<Uuid>) {
+        let mut values: Vec<&InstructionValue> = Vec::new();
+        let mut heads: Vec<&PositionHead> = Vec::new();
+        let mut positions: Vec<Uuid> = Vec::new();
+        match self {
+            Command::Aspirate {
+                position_head,
+                volume,
+                leading_air_gap,
+                trailing_air_gap,
+            } => {
+                heads.push(position_head);
+                values.push(volume);
+                values.extend(leading_air_gap);
+                values.extend(trailing_air_gap);
+            }
+            Command::BeginLoop {
+                index,
+                from,
+                to,
+                steps,
+            } => values.extend([index, from, to, steps]),
+            Command::Dispense {
+                position_head,
+                volume,
+                destinations,
+                ..
+            } => {
+                heads.push(position_head);
+                heads.extend(destinations.iter().map(|(h, _)| h));
+                values.push(volume);
+            }
+            Command::DispenseMainArray { volume, .. } => values.push(volume),
+            Command::EjectTips {
+                load_eject_tips_head,
+            }
+            | Command::LoadTips {
+                load_eject_tips_head,
+            } => {
+                positions.extend(load_eject_tips_head.deck_parameter);
+                values.push(&load_eject_tips_head.deck_location);
+            }
+            Command::HeadPosition { position_head }
+            | Command::Mix { position_head }
+            | Command::Pick { position_head }
+            | Command::Place { position_head } => heads.push(position_head),
+            Command::IfThen { lhs, rhs, .. } | Command::WhileLoop { lhs, rhs, .. } => {
+                values.extend([lhs, rhs])
+            }
+            Command::MathOperation {
+                lhs,
+                rhs_op1,
+                rhs_op2,
+                ..
+            } => values.extend([lhs, rhs_op1, rhs_op2]),
+            Command::MoveMaterial { from, to } => heads.extend([from, to]),
+            Command::PAxisSetPosition { position } => values.push(position),
+            Command::RunMethod { parameters, .. } => {
+                values.extend(parameters.iter().map(|p| &p.value))
+            }
+            Command::AbsoluteMove { x, y, z } | Command::RelativeMove { x, y, z } => {
+                values.extend(x);
+                values.extend(y);
+                values.extend(z);
+            }
+            Command::RunShakerForTime { speed, timeout } => values.extend([speed, timeout]),
+            Command::SetLegLightIntensity { percentage } => values.push(percentage),
+            Command::SetSpeed { speed } => values.push(speed),
+            Command::SetTemperature { temperature, .. } => values.push(temperature),
+            Command::SetTravelHeight { height } => values.push(height),
+            Command::StringOperation {
+                operands, result, ..
+            } => {
+                values.extend(operands);
+                values.push(result);
+            }
+            Command::ShakerOnOff { on_off, .. } | Command::TemperatureOnOff { on_off, .. } => {
+                values.push(on_off)
+            }
+            Command::ApplicationExit
+            | Command::CloseWorkbook
+            | Command::EndIf
+            | Command::EndLoop
+            | Command::EndWhile
+            | Command::ExecuteVSTAMacro { .. }
+            | Command::GetCurrentPositionRelativeToReference
+            | Command::Home { .. }
+            | Command::HomePAxis
+            | Command::Initialize
+            | Command::InitializeSystem
+            | Command::OpenWorkbook
+            | Command::REM { .. }
+            | Command::RunMacro
+            | Command::SetWorkingDirectory
+            | Command::ShowDialog { .. }
+            | Command::StartTime
+            | Command::StopTimer
+            | Command::Unknown { .. }
+            | Command::Ungrip
+            | Command::VerticalPosition => {}
+        }
+        for head in heads {
+            positions.extend(head.deck_parameter);
+            values.extend([&head.deck_location, &head.z_offset]);
+        }
+        (values, positions)
+    }
+}
+
+/// The spelling Maestro saves the operator with
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Assign => write!(f, "(Assignment)"),
+            Self::Minus => write!(f, "-"),
+            Self::Plus => write!(f, "+"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum Comparator {
+    Equals,
+    GreaterThan,
+    GreaterThanOrEqual,
+    LessThan,
+    LessThanOrEqual,
+}
+
+/// The spelling Maestro saves the comparator with
+impl std::fmt::Display for Comparator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Equals => write!(f, "Equals"),
+            Self::GreaterThan => write!(f, "Greater than"),
+            Self::GreaterThanOrEqual => write!(f, "Greater than or equal to"),
+            Self::LessThan => write!(f, "Less than"),
+            Self::LessThanOrEqual => write!(f, "Less than or equal to"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct InstructionValue {
+    pub direct: VariableValue,
+    pub variable: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Parameter {
+    pub id: Uuid,
+    pub value: InstructionValue,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PositionHead {
+    pub deck_parameter: Option<Uuid>,
+    pub deck_location: InstructionValue,
+    pub z_offset: InstructionValue,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct LoadEjectTipsHead {
+    pub deck_parameter: Option<Uuid>,
+    pub deck_location: InstructionValue,
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+// FNV-1a is used instead of the std hasher, as the std hasher output is not guaranteed to be the
+// same across Rust releases and the hashes are persisted
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+// Permissible values are usually a range, such as "0-10" or "0:00:00-24:00:00". A list of options
+// is separated by semicolons or commas, with each option a number optionally followed by "=" and
+// its label, such as "0=Slow;1=Fast". Anything else is not a list.
+fn parse_value_labels(permissible: &str) -> Option<Vec<(i64, String)>> {
+    if !permissible.contains([';', ',']) {
+        return None;
+    }
+    permissible
+        .split([';', ','])
+        .map(|option| {
+            let (value, label) = match option.split_once('=') {
+                Some((value, label)) => (value.trim(), label.trim()),
+                None => (option.trim(), option.trim()),
+            };
+            Some((value.parse().ok()?, label.to_string()))
+        })
+        .collect()
+}
+
+// The length goes first, so that consecutive strings can not run into each other
+fn hash_str(hash: u64, s: &str) -> u64 {
+    fnv1a(fnv1a(hash, &(s.len() as u64).to_le_bytes()), s.as_bytes())
+}
+
+fn hash_variables(mut hash: u64, variables: &HashMap<Uuid, Variable>) -> u64 {
+    let mut sorted: Vec<&Variable> = variables.values().collect();
+    sorted.sort_by_key(|v| v.id);
+    for variable in sorted {
+        hash = fnv1a(hash, variable.id.as_bytes());
+        hash = hash_str(hash, &variable.designation);
+        hash = hash_str(hash, &format!("{:?}", variable.value));
+        hash = hash_str(hash, &format!("{:?}", variable.value_labels));
+    }
+    hash
+}
+
+// Instructions without a number from the export are numbered by their position
+fn number_instructions(instructions: &mut [Instruction]) {
+    for (line, instr) in instructions.iter_mut().enumerate() {
+        if instr.source_index == 0 {
+            instr.source_index = line + 1;
+        }
+    }
+}
+
+fn assign_stable_ids(instructions: &mut [Instruction]) {
+    let mut seen: HashMap<u64, u64> = HashMap::new();
+    for instr in instructions.iter_mut() {
+        let content = fnv1a(FNV_OFFSET, format!("{:?}", instr.command).as_bytes());
+        let ordinal = seen.entry(content).or_insert(0);
+        instr.stable_id = fnv1a(content, &ordinal.to_le_bytes());
+        *ordinal += 1;
+    }
+}
+
+fn get_float_text(xml: &Node, tag: &str) -> Option<f64> {
+    xml.descendants()
+        .find(|n| n.has_tag_name(tag))?
+        .text()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn get_int_text(xml: &Node, tag: &str) -> Option<u32> {
+    xml.descendants()
+        .find(|n| n.has_tag_name(tag))?
+        .text()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+// The tip size of a tip box named like "200ul Tips". A box named for several sizes, like
+// "100ul & 200ul SBS Footprint", is left without a size.
+fn parse_tip_size(name: &str) -> Option<f64> {
+    let lower = name.to_lowercase();
+    let mut sizes = lower
+        .split(|c: char| c.is_whitespace() || c == '&' || c == ',' || c == '/')
+        .filter_map(|word| {
+            word.strip_suffix("ul")
+                .or_else(|| word.strip_suffix("µl"))
+                .and_then(|n| n.parse::<f64>().ok())
+        });
+    let size = sizes.next()?;
+    if sizes.all(|other| other == size) {
+        Some(size)
+    } else {
+        None
+    }
+}
+
+// The inside volume of a well, from its shape and sizes in mm, so in uL. Wells without a depth
+// or of an unknown shape have no known volume.
+fn well_capacity(fields: &HashMap<&str, &str>) -> Option<f64> {
+    let size = |tag| fields.get(tag).and_then(|v| v.parse::<f64>().ok());
+    let depth = size("WellsDepth").filter(|&d| d > 0.0)?;
+    let area = match *fields.get("WellsShape")? {
+        "Circle" => std::f64::consts::PI * (size("WellsDiameter")? / 2.0).powi(2),
+        "Square" => size("InsideLength")? * size("InsideWidth")?,
+        _ => return None,
+    };
+    Some(area * depth).filter(|&v| v > 0.0)
+}
+
+fn trim_designation(designation: &mut String) {
+    if designation.trim().len() != designation.len() {
+        *designation = designation.trim().to_string();
+    }
+}
+
+// A field found by text_only_children
+fn field<'a>(fields: &HashMap<&str, &'a str>, tag: &'static str) -> Result<&'a str, LoaderError> {
+    fields
+        .get(tag)
+        .copied()
+        .ok_or(LoaderError::MissingField(tag))
+}
+
+// A number or id found by text_only_children
+fn parse_field<T>(fields: &HashMap<&str, &str>, tag: &'static str) -> Result<T, LoaderError>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    parse_text(field(fields, tag)?, tag)
+}
+
+// Some exports pad numbers and ids with whitespace, which is never part of the value
+fn parse_text<T>(text: &str, tag: &'static str) -> Result<T, LoaderError>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    text.trim()
+        .parse()
+        .map_err(|e: T::Err| LoaderError::InvalidField {
+            tag,
+            value: text.to_string(),
+            error: e.to_string(),
+        })
+}
+
+fn unknown_value(tag: &'static str, value: &str) -> LoaderError {
+    LoaderError::InvalidField {
+        tag,
+        value: value.to_string(),
+        error: "unknown value".to_string(),
+    }
+}
+
+// The first element with the tag below the node
+fn descendant<'a, 'b>(node: &Node<'a, 'b>, tag: &'static str) -> Result<Node<'a, 'b>, LoaderError> {
+    node.descendants()
+        .find(|n| n.has_tag_name(tag))
+        .ok_or(LoaderError::MissingField(tag))
+}
+
+// The first element with the tag after the node
+fn next_sibling<'a, 'b>(
+    node: &Node<'a, 'b>,
+    tag: &'static str,
+) -> Result<Node<'a, 'b>, LoaderError> {
+    node.next_siblings()
+        .find(|n| n.has_tag_name(tag))
+        .ok_or(LoaderError::MissingField(tag))
+}
+
+// The only child element of the node, expected to have the tag
+fn first_element<'a, 'b>(
+    node: &Node<'a, 'b>,
+    tag: &'static str,
+) -> Result<Node<'a, 'b>, LoaderError> {
+    node.first_element_child()
+        .ok_or(LoaderError::MissingField(tag))
+}
+
+// Maestro uses an element with nothing in it <a></a> as ""
+fn element_text<'a>(node: &Node<'a, '_>) -> &'a str {
+    node.text().unwrap_or("")
+}
+
+fn text_only_element<'a, 'b>(node: &Node<'a, 'b>) -> Option<&'a str> {
+    if !node.is_element() {
+        return None;
+    }
+    if let Some(text) = node.first_child() {
+        if text.is_text() {
+            let len = node.children().collect::<Vec<_>>().len();
+            if len == 1 {
+                text.text()
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    } else {
+        // Maestro uses an element with nothing in it <a></a> as ""
+        Some("")
+    }
+}
+
+fn text_only_children<'a, 'b>(node: &Node<'a, 'b>) -> HashMap<&'a str, &'a str> {
+    let mut result = HashMap::new();
+    for n in node.children() {
+        if let Some(text) = text_only_element(&n) {
+            result.insert(n.tag_name().name(), text);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use roxmltree::Document;
+
+    fn load_empty_app() -> String {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Application_Empty.eap");
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    fn load_complex_app() -> String {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Application_Complex.eap");
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn visit_complex_app() {
+        #[derive(Default)]
+        struct Counter {
+            methods: usize,
+            globals: usize,
+            instructions: usize,
+        }
+        impl AppVisitor for Counter {
+            fn on_method(&mut self, _: &MethodSignature) {
+                self.methods += 1;
+            }
+            fn on_variable(&mut self, method: Option<Uuid>, _: &Variable) {
+                if method.is_none() {
+                    self.globals += 1;
+                }
+            }
+            fn on_instruction(&mut self, _: Uuid, _: &Instruction) {
+                self.instructions += 1;
+            }
+        }
+
+        let doc = load_complex_app();
+        let loader = Loader::new(&doc).unwrap();
+        let mut counter = Counter::default();
+        loader.visit(&mut counter).unwrap();
+
+        let app = loader.build_application().unwrap();
+        let instructions: usize = app
+            .ids_methods()
+            .into_iter()
+            .map(|&id| app.instruction_count(id).unwrap())
+            .sum();
+        assert_eq!(counter.instructions, instructions);
+        assert_eq!(counter.methods, app.ids_methods().len());
+        assert_eq!(counter.globals, app.global_variables().len());
+    }
+
+    #[test]
+    fn build_empty_application() {
+        let doc = load_empty_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        assert_eq!(
+            app.start_method(),
+            "3AC47C04-DCCE-4036-8F9F-6AD7D530E220".parse().unwrap()
+        );
+        assert_eq!(
+            app.name_layout("BB37AAC5-102D-4367-B1BA-98B7D1E47EF0".parse().unwrap()),
+            Some("MainLayout")
+        );
+        assert_eq!(
+            app.name_method("3AC47C04-DCCE-4036-8F9F-6AD7D530E220".parse().unwrap()),
+            Some("Main")
+        );
+        assert_eq!(
+            app.layout_of_method("3AC47C04-DCCE-4036-8F9F-6AD7D530E220".parse().unwrap()),
+            Some("BB37AAC5-102D-4367-B1BA-98B7D1E47EF0".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn complex_app_json_round_trip() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let text = serde_json::to_string(&app).unwrap();
+        let back: SavedApplication = serde_json::from_str(&text).unwrap();
+        assert_eq!(
+            serde_json::to_value(&back).unwrap(),
+            serde_json::to_value(&app).unwrap()
+        );
+    }
+
+    #[test]
+    fn empty_app_to_json() {
+        let doc = load_empty_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let json = serde_json::to_value(&app).unwrap();
+
+        let start = "3ac47c04-dcce-4036-8f9f-6ad7d530e220";
+        assert_eq!(json["start_method"], start);
+        assert_eq!(json["methods"][start]["designation"], "Main");
+
+        let text = serde_json::to_string(&app).unwrap();
+        let back: serde_json::Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(back, json);
+    }
+
+    #[test]
+    fn start_method_name_of_empty_app() {
+        let doc = load_empty_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        assert_eq!(app.start_method_name(), Some("Main"));
+    }
+
+    #[test]
+    fn unused_layouts() {
+        let main = Uuid::from_u128(1);
+        let (used, stale) = (Uuid::from_u128(2), Uuid::from_u128(3));
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(used, "MainLayout"))
+            .add_layout(Layout::new(stale, "MainLayout Copy"))
+            .add_method(main, "Main", used, Vec::new())
+            .set_start_method(main)
+            .build()
+            .unwrap();
+        assert_eq!(app.unused_layouts(), vec![stale]);
+
+        let doc = load_empty_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        assert!(app.unused_layouts().is_empty());
+    }
+
+    #[test]
+    fn index_complex_application() {
+        let doc = load_complex_app();
+        let index = Loader::new(&doc).unwrap().build_index().unwrap();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        assert_eq!(index.start_method, app.start_method());
+        assert_eq!(index.methods.len(), 30);
+        let ids: Vec<Uuid> = index.methods.iter().map(|m| m.id).collect();
+        assert_eq!(ids, app.methods_in_order());
+
+        let load_tips = &index.methods[3];
+        assert_eq!(load_tips.designation, "UTIL_Load Tips");
+        assert_eq!(
+            load_tips.layout_id,
+            "44BAE7C6-789B-4D1B-893B-817ABE10C1E6".parse().unwrap()
+        );
+        let params: Vec<&str> = load_tips
+            .parameters
+            .iter()
+            .map(|p| p.designation.as_str())
+            .collect();
+        assert_eq!(
+            params,
+            vec![
+                "p_SourceDeckLocation",
+                "p_ReserveDeckLocation",
+                "p_IsAutomated",
+                "p_IsInitializeVariables",
+                "p_NumberOfColumnsToLoad",
+                "p_NumberOfRowsToLoad"
+            ]
+        );
+    }
+
+    #[test]
+    fn fingerprint_of_same_content() {
+        let doc = load_complex_app();
+        let first = Loader::new(&doc).unwrap().build_application().unwrap();
+        let second = Loader::new(&doc).unwrap().build_application().unwrap();
+        assert_eq!(first.fingerprint(), second.fingerprint());
+
+        let renamed = doc.replacen("p_IsAutomated", "p_IsAutomatic", 1);
+        let modified = Loader::new(&renamed).unwrap().build_application().unwrap();
+        assert_ne!(first.fingerprint(), modified.fingerprint());
+
+        let labelled = doc.replacen(
+            "<PermissibleValues>0-100</PermissibleValues>",
+            "<PermissibleValues>0=Off;100=On</PermissibleValues>",
+            1,
+        );
+        let modified = Loader::new(&labelled).unwrap().build_application().unwrap();
+        assert_ne!(first.fingerprint(), modified.fingerprint());
+    }
+
+    #[test]
+    fn fingerprint_of_location_sizes() {
+        let (main, layout_id, c3) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        let app = |change: &dyn Fn(&mut Layout)| {
+            let mut layout = Layout::new(layout_id, "MainLayout");
+            layout.add_position(c3, "C3");
+            change(&mut layout);
+            SavedApplicationBuilder::new()
+                .add_layout(layout)
+                .add_method(main, "Main", layout_id, vec![])
+                .set_start_method(main)
+                .build()
+                .unwrap()
+                .fingerprint()
+        };
+
+        let plain = app(&|_| {});
+        let with_tips = app(&|l| l.set_tip_size(c3, 200.0));
+        let with_wells = app(&|l| l.set_well_capacity(c3, 200.0));
+        assert_eq!(plain, app(&|_| {}));
+        assert_ne!(plain, with_tips);
+        assert_ne!(plain, with_wells);
+        assert_ne!(with_tips, with_wells);
+    }
+
+    #[test]
+    fn lint_missing_tip_load() {
+        let main = Uuid::from_u128(9);
+        let layout = Uuid::from_u128(10);
+        let empty = || InstructionValue {
+            direct: VariableValue::String(String::new()),
+            variable: None,
+        };
+        let head = || PositionHead {
+            deck_parameter: None,
+            deck_location: empty(),
+            z_offset: empty(),
+        };
+        let tips_head = || LoadEjectTipsHead {
+            deck_parameter: None,
+            deck_location: empty(),
+        };
+        let instructions = vec![
+            Instruction::new(Command::Mix {
+                position_head: head(),
+            }),
+            Instruction::new(Command::LoadTips {
+                load_eject_tips_head: tips_head(),
+            }),
+            Instruction::new(Command::Aspirate {
+                position_head: head(),
+                volume: empty(),
+                leading_air_gap: None,
+                trailing_air_gap: None,
+            }),
+            Instruction::new(Command::EjectTips {
+                load_eject_tips_head: tips_head(),
+            }),
+            Instruction::new(Command::REM {
+                comment: "tips are ejected".to_string(),
+            }),
+            Instruction::new(Command::Dispense {
+                position_head: head(),
+                volume: empty(),
+                dispense_all: true,
+                destinations: Vec::new(),
+            }),
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout, "MainLayout"))
+            .add_method(main, "Main", layout, instructions)
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let lines: Vec<usize> = app.lint_tip_usage(main).iter().map(|i| i.line).collect();
+        assert_eq!(lines, vec![0, 5]);
+        assert!(app.lint_tip_usage(layout).is_empty());
+    }
+
+    #[test]
+    fn consecutive_tip_loads() {
+        let main = Uuid::from_u128(16);
+        let layout = Uuid::from_u128(17);
+        let tips_head = || LoadEjectTipsHead {
+            deck_parameter: None,
+            deck_location: InstructionValue {
+                direct: VariableValue::String(String::new()),
+                variable: None,
+            },
+        };
+        let load = || {
+            Instruction::new(Command::LoadTips {
+                load_eject_tips_head: tips_head(),
+            })
+        };
+        let instructions = vec![
+            load(),
+            load(),
+            Instruction::new(Command::EjectTips {
+                load_eject_tips_head: tips_head(),
+            }),
+            load(),
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout, "MainLayout"))
+            .add_method(main, "Main", layout, instructions)
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        assert_eq!(app.redundant_tip_loads(main), vec![1]);
+        assert!(app.redundant_tip_loads(layout).is_empty());
+    }
+
+    #[test]
+    fn call_graph_of_complex_app() {
+        let app = Loader::new(&load_complex_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let graph = app.call_graph();
+
+        assert_eq!(graph.len(), app.methods_in_order().len());
+        assert!(!graph[&app.start_method()].is_empty());
+        assert!(graph
+            .values()
+            .flatten()
+            .all(|&callee| app.has_method(callee)));
+    }
+
+    #[test]
+    fn methods_using_thermal_locators() {
+        let app = Loader::new(&load_complex_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let names = |device| -> Vec<&str> {
+            app.methods_using_device(device)
+                .into_iter()
+                .map(|id| app.name_method(id).unwrap())
+                .collect()
+        };
+
+        assert_eq!(
+            names("ThermalLocator4"),
+            vec![
+                "UTIL_HardwareInitialization",
+                "UTIL_SetTemperature",
+                "UTIL_Application Done",
+                "UTIL_Run Shaker"
+            ]
+        );
+        assert_eq!(
+            names("ThermalLocator1"),
+            vec![
+                "UTIL_HardwareInitialization",
+                "UTIL_SetTemperature",
+                "UTIL_Application Done"
+            ]
+        );
+        assert!(names("Sciclone").is_empty());
+    }
+
+    #[test]
+    fn writers_in_two_methods() {
+        let main = Uuid::from_u128(11);
+        let sub = Uuid::from_u128(12);
+        let layout = Uuid::from_u128(13);
+        let count = Uuid::from_u128(14);
+        let step = Uuid::from_u128(15);
+        let int = |i| InstructionValue {
+            direct: VariableValue::Int(i),
+            variable: None,
+        };
+        let count_value = || InstructionValue {
+            direct: VariableValue::Int(0),
+            variable: Some(count),
+        };
+        let add_to_count = || {
+            Instruction::new(Command::MathOperation {
+                operator: Operator::Plus,
+                lhs: count_value(),
+                rhs_op1: count_value(),
+                rhs_op2: int(1),
+            })
+        };
+        let main_instructions = vec![
+            Instruction::new(Command::REM {
+                comment: "count calls".to_string(),
+            }),
+            add_to_count(),
+            Instruction::new(Command::RunMethod {
+                method: sub,
+                parameters: vec![Parameter {
+                    id: step,
+                    value: count_value(),
+                }],
+            }),
+        ];
+        let sub_instructions = vec![
+            Instruction::new(Command::BeginLoop {
+                index: count_value(),
+                from: int(1),
+                to: int(3),
+                steps: int(1),
+            }),
+            Instruction::new(Command::EndLoop),
+            add_to_count(),
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_global(Variable::new(count, "g_Count", VariableValue::Int(0)))
+            .add_layout(Layout::new(layout, "MainLayout"))
+            .add_method(main, "Main", layout, main_instructions)
+            .add_method_with_parameters(
+                sub,
+                "Sub",
+                layout,
+                vec![Variable::new(step, "p_Step", VariableValue::Int(1))],
+                sub_instructions,
+            )
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        assert_eq!(app.writers_of(count), vec![(main, 1), (sub, 0), (sub, 2)]);
+        assert_eq!(app.writers_of(step), vec![(main, 2)]);
+        assert!(app.writers_of(layout).is_empty());
+    }
+
+    #[test]
+    fn parse_travel_height() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let heights: Vec<&InstructionValue> = app
+            .methods
+            .values()
+            .flat_map(|m| &m.instructions)
+            .filter_map(|i| match &i.command {
+                Command::SetTravelHeight { height } => Some(height),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(heights.len(), 3);
+        assert!(heights
+            .iter()
+            .any(|h| h.direct == VariableValue::Float(-60.0) && h.variable.is_none()));
+    }
+
+    #[test]
+    fn keep_source_of_string_operation() {
+        let doc = load_complex_app();
+        let mut loader = Loader::new(&doc).unwrap();
+        let app = loader.build_application().unwrap();
+        assert!(app
+            .methods
+            .values()
+            .flat_map(|m| &m.instructions)
+            .all(|i| i.source().is_none()));
+
+        loader.keep_instruction_source(true);
+        let app = loader.build_application().unwrap();
+        let sources: Vec<&str> = app
+            .methods
+            .values()
+            .flat_map(|m| &m.instructions)
+            .filter(|i| matches!(i.command, Command::StringOperation { .. }))
+            .map(|i| i.source().unwrap())
+            .collect();
+        assert_eq!(sources.len(), 6);
+        for source in sources {
+            assert!(source.starts_with("<Instruction"));
+            assert!(source.contains("<InstructionDesignation>String Operation<"));
+            assert!(Document::parse(source).is_ok());
+        }
+    }
+
+    #[test]
+    fn designation_matches_source() {
+        let doc = load_complex_app();
+        let mut loader = Loader::new(&doc).unwrap();
+        loader.keep_instruction_source(true);
+        let app = loader.build_application().unwrap();
+        for instr in app.methods.values().flat_map(|m| &m.instructions) {
+            let designation = format!("<InstructionDesignation>{}<", instr.command.designation());
+            assert!(instr.source().unwrap().contains(&designation));
+        }
+    }
+
+    #[test]
+    fn parse_relative_move() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let moves: Vec<_> = app
+            .methods
+            .values()
+            .flat_map(|m| &m.instructions)
+            .filter_map(|i| match &i.command {
+                Command::RelativeMove { x, y, z } => Some((x, y, z)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(moves.len(), 10);
+        let z_only: Vec<&InstructionValue> = moves
+            .iter()
+            .filter_map(|(x, y, z)| match (x, y, z) {
+                (None, None, Some(z)) => Some(z),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(z_only.len(), 6);
+        assert!(z_only
+            .iter()
+            .any(|z| z.direct == VariableValue::Float(30.0) && z.variable.is_none()));
+
+        let absolute_moves = app
+            .methods
+            .values()
+            .flat_map(|m| &m.instructions)
+            .filter(|i| matches!(i.command, Command::AbsoluteMove { .. }))
+            .count();
+        assert_eq!(absolute_moves, 2);
+    }
+
+    #[test]
+    fn parse_move_of_x_and_z() {
+        let move_instruction = |designation, axes: &str| {
+            format!(
+                r#"<Instruction3>
+          <IsComment>0</IsComment>
+          <InstructionDesignation>{}</InstructionDesignation>
+          <RelativeMoveInstr>
+            <UseX>-1</UseX>
+            <UseY>0</UseY>
+            <UseZ>-1</UseZ>
+            {}
+          </RelativeMoveInstr>
+        </Instruction3>"#,
+                designation, axes
+            )
+        };
+        let axis = |tag, value| {
+            format!(
+                "<{0}><_DirectValue>{1}</_DirectValue><_Variable>[[[[---NONE---]]]]</_Variable></{0}>",
+                tag, value
+            )
+        };
+        let parse = |xml: String| {
+            let doc = Document::parse(&xml).unwrap();
+            Loader::build_instruction(&doc.root_element(), false)
+                .unwrap()
+                .command
+        };
+        let mm = |d| {
+            Some(InstructionValue {
+                direct: VariableValue::Float(d),
+                variable: None,
+            })
+        };
+
+        let axes = [axis("X", "12.5"), axis("Y", "7"), axis("Z", "-30")].concat();
+        assert_eq!(
+            parse(move_instruction("Relative Move", &axes)),
+            Command::RelativeMove {
+                x: mm(12.5),
+                y: None,
+                z: mm(-30.0)
+            }
+        );
+        // A used axis without a value does not move
+        assert_eq!(
+            parse(move_instruction("Absolute Move", &axis("X", "100"))),
+            Command::AbsoluteMove {
+                x: mm(100.0),
+                y: None,
+                z: None
+            }
+        );
+    }
+
+    #[test]
+    fn value_labels_of_enumeration() {
+        assert_eq!(
+            parse_value_labels("0=Slow;1=Fast; 2 = Careful"),
+            Some(vec![
+                (0, "Slow".to_string()),
+                (1, "Fast".to_string()),
+                (2, "Careful".to_string())
+            ])
+        );
+        assert_eq!(
+            parse_value_labels("1,2"),
+            Some(vec![(1, "1".to_string()), (2, "2".to_string())])
+        );
+        assert_eq!(parse_value_labels("1=One;Two"), None);
+    }
+
+    #[test]
+    fn value_labels_of_range() {
+        for range in ["0-10", "-9999999-9999999", "0:00:00-24:00:00", ""] {
+            assert_eq!(parse_value_labels(range), None);
+        }
+
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let tip_boxes = "12A4FC48-6802-491A-ACE5-871B53197F12".parse().unwrap();
+        assert_eq!(app.global_variables()[&tip_boxes].value_labels(), None);
+    }
+
+    #[test]
+    fn reparse_one_method() {
+        let doc = load_complex_app();
+        let mut app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let load_tips = app.methods_in_order()[3];
+        let start = app.start_method();
+
+        let edited = doc.replace(
+            "<MethodDesignation>UTIL_Load Tips</MethodDesignation>",
+            "<MethodDesignation>UTIL_Load Tips Edited</MethodDesignation>",
+        );
+        let loader = Loader::new(&edited).unwrap();
+        app.reparse_method(&loader, load_tips).unwrap();
+
+        assert_eq!(app.name_method(load_tips), Some("UTIL_Load Tips Edited"));
+        assert_eq!(app.methods_in_order()[3], load_tips);
+        assert_eq!(
+            app.fingerprint(),
+            loader.build_application().unwrap().fingerprint()
+        );
+        assert_eq!(
+            app.name_method(start),
+            Loader::new(&doc)
+                .unwrap()
+                .build_application()
+                .unwrap()
+                .name_method(start)
+        );
+        assert!(matches!(
+            app.reparse_method(&loader, Uuid::from_u128(1)),
+            Err(LoaderError::UnknownMethod(_))
+        ));
+    }
+
+    #[test]
+    fn source_index_matches_position() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        for method_id in app.methods_in_order() {
+            for line in 0..app.instruction_count(method_id).unwrap() {
+                let instr = app.instruction(method_id, line).unwrap();
+                assert_eq!(instr.source_index(), line + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn duplicate_method() {
+        let doc = load_complex_app();
+        let mut app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let load_tips = app.methods_in_order()[3];
+        let copy = app
+            .duplicate_method(load_tips, "UTIL_Load Tips Copy")
+            .unwrap();
+
+        assert_ne!(copy, load_tips);
+        assert_eq!(app.methods_in_order().last(), Some(&copy));
+        assert_eq!(app.name_method(copy), Some("UTIL_Load Tips Copy"));
+        assert_eq!(app.name_method(load_tips), Some("UTIL_Load Tips"));
+        assert_eq!(
+            app.instruction_count(copy),
+            app.instruction_count(load_tips)
+        );
+        assert_eq!(app.layout_of_method(copy), app.layout_of_method(load_tips));
+        assert_ne!(
+            app.methods[&copy].parameters.id,
+            app.methods[&load_tips].parameters.id
+        );
+
+        // Changing the copy leaves the original as it was
+        let param = *app
+            .parameters_of_method(copy)
+            .unwrap()
+            .keys()
+            .next()
+            .unwrap();
+        app.methods
+            .get_mut(&copy)
+            .unwrap()
+            .parameters
+            .variables
+            .get_mut(&param)
+            .unwrap()
+            .set_value(VariableValue::String("changed".to_string()));
+        app.methods.get_mut(&copy).unwrap().instructions.clear();
+        assert_ne!(
+            app.parameters_of_method(load_tips).unwrap()[&param].value(),
+            &VariableValue::String("changed".to_string())
+        );
+        assert_ne!(app.instruction_count(load_tips), Some(0));
 
-#[derive(Debug)]
-pub struct Parameter {
-    id: Uuid,
-    value: InstructionValue,
-}
+        assert_eq!(
+            app.duplicate_method(Uuid::from_u128(1), "Nothing"),
+            Err(EditError::UnknownMethod(Uuid::from_u128(1)))
+        );
+    }
 
-#[derive(Debug)]
-pub struct PositionHead {
-    pub deck_parameter: Option<Uuid>,
-    pub deck_location: InstructionValue,
-    pub z_offset: InstructionValue,
-}
+    #[test]
+    fn referenced_uuids() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let referenced = app.all_referenced_uuids();
+
+        // The variable of a Set Travel Height
+        let travel_height = "366CE42D-C5B6-4A84-B26D-DDFD8452B0A4".parse().unwrap();
+        assert!(referenced.contains(&travel_height));
+        assert!(referenced.contains(&app.start_method()));
+        let load_tips = app.methods_in_order()[3];
+        assert!(referenced.contains(&app.layout_of_method(load_tips).unwrap()));
+        assert!(!referenced.contains(&Uuid::nil()));
+    }
 
-#[derive(Debug)]
-pub struct LoadEjectTipsHead {
-    pub deck_parameter: Option<Uuid>,
-    pub deck_location: InstructionValue,
-}
+    #[test]
+    fn parse_air_gaps() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let leading: Vec<f64> = app
+            .methods
+            .values()
+            .flat_map(|m| &m.instructions)
+            .filter_map(|i| match &i.command {
+                Command::Aspirate {
+                    leading_air_gap:
+                        Some(InstructionValue {
+                            direct: VariableValue::Float(f),
+                            ..
+                        }),
+                    ..
+                } => Some(*f),
+                _ => None,
+            })
+            .collect();
+        assert!(leading.contains(&5.0));
+    }
 
-fn get_float_text(xml: &Node, tag: &str) -> f64 {
-    xml.descendants()
-        .find(|n| n.has_tag_name(tag))
-        .unwrap()
-        .text()
-        .unwrap()
-        .parse()
-        .unwrap()
-}
+    #[test]
+    fn validate_arguments() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let load_tips = app.methods_in_order()[3];
+        let params = app.parameters_of_method(load_tips).unwrap();
+        let args: HashMap<Uuid, VariableValue> =
+            params.values().map(|p| (p.id, p.value().clone())).collect();
+        assert_eq!(app.validate_arguments(load_tips, &args), Ok(()));
+
+        let p = params
+            .values()
+            .find(|p| p.designation == "p_IsAutomated")
+            .unwrap();
+        let mut missing = args.clone();
+        missing.remove(&p.id);
+        assert_eq!(
+            app.validate_arguments(load_tips, &missing),
+            Err(ArgError::MissingArgument(p.id))
+        );
 
-fn get_int_text(xml: &Node, tag: &str) -> u32 {
-    xml.descendants()
-        .find(|n| n.has_tag_name(tag))
-        .unwrap()
-        .text()
-        .unwrap()
-        .parse()
-        .unwrap()
-}
+        let mut mistyped = args.clone();
+        mistyped.insert(p.id, VariableValue::Seconds(1));
+        assert_eq!(
+            app.validate_arguments(load_tips, &mistyped),
+            Err(ArgError::MistypedArgument(p.id))
+        );
 
-fn text_only_element<'a, 'b>(node: &Node<'a, 'b>) -> Option<&'a str> {
-    if !node.is_element() {
-        return None;
+        let mut extra = args;
+        extra.insert(Uuid::from_u128(1), VariableValue::Bool(true));
+        assert_eq!(
+            app.validate_arguments(load_tips, &extra),
+            Err(ArgError::UnknownParameter(Uuid::from_u128(1)))
+        );
+        assert_eq!(
+            app.validate_arguments(Uuid::from_u128(2), &HashMap::new()),
+            Err(ArgError::UnknownMethod(Uuid::from_u128(2)))
+        );
     }
-    if let Some(text) = node.first_child() {
-        if text.is_text() {
-            let len = node.children().collect::<Vec<_>>().len();
-            if len == 1 {
-                text.text()
-            } else {
-                None
+
+    #[test]
+    fn require_version() {
+        let doc = load_empty_app();
+        let loader = Loader::new(&doc).unwrap();
+        assert!(loader.require_version(6.8, 6.8).is_ok());
+        assert!(loader.require_version(6.0, 7.0).is_ok());
+        match loader.require_version(7.0, 7.5) {
+            Err(LoaderError::UnsupportedVersion { found, supported }) => {
+                assert_eq!(found, 6.8);
+                assert_eq!(supported, (7.0, 7.5));
             }
-        } else {
-            None
+            _ => panic!("expected an unsupported version"),
         }
-    } else {
-        // Maestro uses an element with nothing in it <a></a> as ""
-        Some("")
     }
-}
 
-fn text_only_children<'a, 'b>(node: &Node<'a, 'b>) -> HashMap<&'a str, &'a str> {
-    let mut result = HashMap::new();
-    for n in node.children() {
-        if let Some(text) = text_only_element(&n) {
-            result.insert(n.tag_name().name(), text);
-        } else {
+    #[test]
+    fn instruction_count_mismatch() {
+        let doc = load_empty_app();
+        assert!(Loader::new(&doc).unwrap().try_build_application().is_ok());
+
+        let doc = doc.replace(
+            "<InstructionsCount>0</InstructionsCount>",
+            "<InstructionsCount>2</InstructionsCount>",
+        );
+        match Loader::new(&doc).unwrap().try_build_application() {
+            Err(LoaderError::InstructionCountMismatch {
+                method,
+                declared,
+                found,
+            }) => {
+                assert_eq!(
+                    method,
+                    "3AC47C04-DCCE-4036-8F9F-6AD7D530E220".parse().unwrap()
+                );
+                assert_eq!(declared, 2);
+                assert_eq!(found, 0);
+            }
+            _ => panic!("expected an instruction count mismatch"),
         }
     }
-    result
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use roxmltree::Document;
-
-    fn load_empty_app() -> String {
-        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        d.push("resources/test/Application_Empty.eap");
-
-        std::fs::read_to_string(d).unwrap()
+    #[test]
+    fn operator_display_round_trip() {
+        for op in [Operator::Assign, Operator::Minus, Operator::Plus] {
+            assert_eq!(Loader::build_operator(&op.to_string()).unwrap(), op);
+        }
+        assert_eq!(Operator::Assign.to_string(), "(Assignment)");
     }
 
-    fn load_complex_app() -> String {
-        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        d.push("resources/test/Application_Complex.eap");
-
-        std::fs::read_to_string(d).unwrap()
+    #[test]
+    fn comparator_display_round_trip() {
+        for comp in [
+            Comparator::Equals,
+            Comparator::GreaterThan,
+            Comparator::GreaterThanOrEqual,
+            Comparator::LessThan,
+            Comparator::LessThanOrEqual,
+        ] {
+            assert_eq!(Loader::build_comparator(&comp.to_string()).unwrap(), comp);
+        }
+        assert_eq!(
+            Comparator::GreaterThanOrEqual.to_string(),
+            "Greater than or equal to"
+        );
     }
 
     #[test]
-    fn build_empty_application() {
-        let doc = load_empty_app();
-        let app = Loader::new(&doc).build_application();
+    fn methods_in_source_order() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let order = app.methods_in_order();
+        assert_eq!(order.len(), 30);
         assert_eq!(
-            app.start_method(),
-            "3AC47C04-DCCE-4036-8F9F-6AD7D530E220".parse().unwrap()
+            order[0],
+            "C86A76FD-2B83-4F74-9943-85495E4BC580".parse().unwrap()
         );
         assert_eq!(
-            app.name_layout("BB37AAC5-102D-4367-B1BA-98B7D1E47EF0".parse().unwrap()),
-            Some("MainLayout")
+            order[1],
+            "6F8E918F-178B-40D0-9F4A-A81B7AEA39C1".parse().unwrap()
         );
         assert_eq!(
-            app.name_method("3AC47C04-DCCE-4036-8F9F-6AD7D530E220".parse().unwrap()),
-            Some("Main")
+            order[2],
+            "B62E155C-FE01-4813-8507-8F711D5A0231".parse().unwrap()
         );
         assert_eq!(
-            app.layout_of_method("3AC47C04-DCCE-4036-8F9F-6AD7D530E220".parse().unwrap()),
-            Some("BB37AAC5-102D-4367-B1BA-98B7D1E47EF0".parse().unwrap())
+            order[29],
+            "466F9A14-C595-4C47-B10A-965482EEB783".parse().unwrap()
         );
     }
 
     #[test]
     fn build_complex_application() {
         let doc = load_complex_app();
-        let app = Loader::new(&doc).build_application();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
         assert_eq!(app.ids_layout().len(), 11);
         assert_eq!(app.ids_methods().len(), 30);
 
@@ -1264,7 +3416,7 @@ mod tests {
 
     #[test]
     fn int_float_parsing() {
-        const DATA: &'static str = r#"<ExportedApplication>
+        const DATA: &str = r#"<ExportedApplication>
 
   <ExportedApplicationVersion>6.8</ExportedApplicationVersion>
 
@@ -1274,13 +3426,117 @@ mod tests {
         let doc = Document::parse(DATA).unwrap();
         let version = get_float_text(&doc.root(), "ExportedApplicationVersion");
         let build = get_int_text(&doc.root(), "ExportedApplicationBuild");
-        assert_eq!(version, 6.8);
-        assert_eq!(build, 6);
+        assert_eq!(version, Some(6.8));
+        assert_eq!(build, Some(6));
+        assert_eq!(get_int_text(&doc.root(), "Missing"), None);
+    }
+
+    #[test]
+    fn reject_malformed_document() {
+        assert!(matches!(
+            Loader::new("<not-xml"),
+            Err(LoaderError::XmlParse(_))
+        ));
+        assert!(matches!(
+            Loader::new("<Application></Application>"),
+            Err(LoaderError::MissingField(APP_VERSION))
+        ));
+    }
+
+    #[test]
+    fn tip_size_from_consumable_name() {
+        assert_eq!(parse_tip_size("20ul Tips"), Some(20.0));
+        assert_eq!(parse_tip_size("Filter Tips 200uL"), Some(200.0));
+        assert_eq!(parse_tip_size("100ul & 200ul SBS Footprint"), None);
+        assert_eq!(parse_tip_size("Bio-Rad HSP-96 PCR96"), None);
+
+        // The only tip box of the complex app is for two sizes
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        for layout in app.layouts().values() {
+            assert!(layout.positions.values().all(|l| l.tip_size.is_none()));
+        }
+    }
+
+    #[test]
+    fn canonicalize_cosmetic_differences() {
+        let doc = load_complex_app();
+        let edited = doc
+            .replace(
+                "7CC9150A-FDF2-4A40-A8ED-F60D33C500C4",
+                "7cc9150a-fdf2-4a40-a8ed-f60d33c500c4",
+            )
+            .replace(
+                "<ThisDesignation>Sample</ThisDesignation>",
+                "<ThisDesignation>  Sample </ThisDesignation>",
+            );
+        let mut app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let mut other = Loader::new(&edited).unwrap().build_application().unwrap();
+        // The order methods were added in is cosmetic too
+        other.method_order.reverse();
+        assert!(app != other);
+
+        app.canonicalize();
+        other.canonicalize();
+        assert!(app == other);
+    }
+
+    #[test]
+    fn well_capacity_from_consumable_sizes() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let magnet = "a16247d0-f24a-43c3-a0ae-f747d7b7592a".parse().unwrap();
+        let tip_box = "5917e9be-ef73-403a-baeb-ff779944598e".parse().unwrap();
+        let locations: Vec<&Location> = app
+            .layouts()
+            .values()
+            .flat_map(|layout| layout.positions.values())
+            .collect();
+
+        // A well of 5.3 mm across and 14.5 mm deep
+        let on_magnet: Vec<&&Location> = locations
+            .iter()
+            .filter(|l| l.consumable == magnet)
+            .collect();
+        assert!(!on_magnet.is_empty());
+        for location in on_magnet {
+            let capacity = location.well_capacity.unwrap();
+            assert!((capacity - 319.9).abs() < 0.1);
+        }
+        assert!(locations
+            .iter()
+            .filter(|l| l.consumable == tip_box)
+            .all(|l| l.well_capacity.is_none()));
+    }
+
+    #[test]
+    fn report_missing_and_invalid_fields() {
+        let doc = load_empty_app();
+        let start = doc.find("<StartupMethod>").unwrap();
+        let end = doc.find("</StartupMethod>").unwrap() + "</StartupMethod>".len();
+        let missing = format!("{}{}", &doc[..start], &doc[end..]);
+        assert!(matches!(
+            Loader::new(&missing).unwrap().build_application(),
+            Err(LoaderError::MissingField(START_METHOD))
+        ));
+
+        const DATA: &str = r#"<Variable1>
+          <VariableType>2</VariableType>
+          <VariableID>82ADDA04-FE60-4F14-B0C6-81AF2B5E524B</VariableID>
+          <VariableDesignation>g_Volume</VariableDesignation>
+          <Value>lots</Value>
+        </Variable1>"#;
+        let doc = Document::parse(DATA).unwrap();
+        let node = doc.root().first_element_child().unwrap();
+        assert!(matches!(
+            Loader::build_variable(&node),
+            Err(LoaderError::InvalidField { tag: VAR_VALUE, value, .. }) if value == "lots"
+        ));
     }
 
     #[test]
     fn single_text_element() {
-        const DATA: &'static str = r#"<a>Hello<b>World</b></a>"#;
+        const DATA: &str = r#"<a>Hello<b>World</b></a>"#;
         let doc = Document::parse(DATA).unwrap();
         assert!(text_only_element(&doc.root().first_child().unwrap()).is_none());
         let text_node = doc.descendants().find(|n| n.has_tag_name("b")).unwrap();
@@ -1289,7 +3545,7 @@ mod tests {
 
     #[test]
     fn test_text_only_children() {
-        const DATA: &'static str = r#"<a>A
+        const DATA: &str = r#"<a>A
         <b>B</b>
         <c>
             C
@@ -1311,7 +3567,7 @@ mod tests {
 
     #[test]
     fn variable_pool_parsing() {
-        const DATA: &'static str = r#"<VariablesPool>
+        const DATA: &str = r#"<VariablesPool>
 
           <VariablesPoolDesignation>MainLayout</VariablesPoolDesignation>
 
@@ -1343,7 +3599,7 @@ mod tests {
         "#;
         let doc = Document::parse(DATA).unwrap();
         let node = doc.root().first_element_child().unwrap();
-        let var = Loader::build_variables_pool(&node);
+        let var = Loader::build_variables_pool(&node).unwrap();
         assert_eq!(
             var.id,
             "BB37AAC5-102D-4367-B1BA-98B7D1E47EF0".parse().unwrap()
@@ -1360,7 +3616,7 @@ mod tests {
             .descendants()
             .find(|n| n.has_tag_name("Method1"))
             .unwrap();
-        let var = Loader::build_method(&method_node);
+        let var = Loader::build_method(&method_node, false).unwrap();
         assert_eq!(var.designation, "Main".to_string());
         assert_eq!(
             var.id,
@@ -1382,7 +3638,7 @@ mod tests {
 
     #[test]
     fn variable_parsing() {
-        const DATA: &'static str = r#"<Variable2>
+        const DATA: &str = r#"<Variable2>
 
           <VariableType>2</VariableType>
 
@@ -1403,7 +3659,7 @@ mod tests {
         </Variable2>"#;
         let doc = Document::parse(DATA).unwrap();
         let node = doc.root().first_element_child().unwrap();
-        let var = Loader::build_variable(&node);
+        let var = Loader::build_variable(&node).unwrap();
         assert_eq!(var.designation, "g_ReservedTipBoxZOffset".to_string());
         assert_eq!(
             var.id,
@@ -1412,9 +3668,64 @@ mod tests {
         assert_eq!(var.value, VariableValue::Float(-10.0));
     }
 
+    #[test]
+    fn integer_variable_parsing() {
+        const DATA: &str = r#"<Variable3>
+          <VariableType>1</VariableType>
+          <VariableID>FF681C9B-896F-407B-B7DB-B7F7C1C7CE6E</VariableID>
+          <VariableDesignation>l_NumberOfTipBoxPerDeck</VariableDesignation>
+          <Value>4</Value>
+          <VariableDescription></VariableDescription>
+          <PermissibleValues>1-8</PermissibleValues>
+        </Variable3>"#;
+        let doc = Document::parse(DATA).unwrap();
+        let node = doc.root().first_element_child().unwrap();
+        let var = Loader::build_variable(&node).unwrap();
+        assert_eq!(var.value, VariableValue::Int(4));
+        assert_eq!(var.value.variable_type(), VariableType::Int);
+
+        let fractional = DATA.replace("<Value>4<", "<Value>4.5<");
+        let doc = Document::parse(&fractional).unwrap();
+        let node = doc.root().first_element_child().unwrap();
+        assert!(matches!(
+            Loader::build_variable(&node),
+            Err(LoaderError::InvalidField { tag: VAR_VALUE, .. })
+        ));
+    }
+
+    #[test]
+    fn whitespace_padded_values() {
+        const DATA: &str = r#"<Variable2>
+          <VariableType> 2 </VariableType>
+          <VariableID> 82ADDA04-FE60-4F14-B0C6-81AF2B5E524B </VariableID>
+          <VariableDesignation>g_Volume</VariableDesignation>
+          <Value> 25 </Value>
+          <VariableDescription></VariableDescription>
+          <PermissibleValues></PermissibleValues>
+        </Variable2>"#;
+        let doc = Document::parse(DATA).unwrap();
+        let node = doc.root().first_element_child().unwrap();
+        let var = Loader::build_variable(&node).unwrap();
+        assert_eq!(var.value, VariableValue::Float(25.0));
+        assert_eq!(
+            var.id,
+            "82ADDA04-FE60-4F14-B0C6-81AF2B5E524B".parse().unwrap()
+        );
+
+        const TEXT: &str = r#"<Text>
+          <_DirectValue> padded </_DirectValue>
+          <_Variable> [[[[---NONE---]]]] </_Variable>
+        </Text>"#;
+        let doc = Document::parse(TEXT).unwrap();
+        let node = doc.root().first_element_child().unwrap();
+        let value = Loader::build_instruction_value(&node, VariableType::String).unwrap();
+        assert_eq!(value.variable, None);
+        assert_eq!(value.direct, VariableValue::String(" padded ".to_string()));
+    }
+
     #[test]
     fn layout_parsing() {
-        const DATA: &'static str = r#"<VariablesPool>
+        const DATA: &str = r#"<VariablesPool>
 
         <VariablesPoolDesignation>MainLayout</VariablesPoolDesignation>
 
@@ -1459,7 +3770,7 @@ mod tests {
         </VariablesPool>"#;
         let doc = Document::parse(DATA).unwrap();
         let node = doc.root().first_element_child().unwrap();
-        let var = Loader::build_layout(&node);
+        let var = Loader::build_layout(&node).unwrap();
         assert_eq!(var.designation, "MainLayout".to_string());
         assert_eq!(
             var.id,
@@ -1472,9 +3783,103 @@ mod tests {
         assert_eq!(loc.position, "D1".to_string());
     }
 
+    #[test]
+    fn position_by_designation() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let d1: Uuid = "504C5661-C3EB-4CA2-9E7A-A974828D4C68".parse().unwrap();
+        let layout = app
+            .layouts()
+            .values()
+            .find(|l| l.positions.contains_key(&d1))
+            .unwrap();
+
+        let by_position = layout.position_by_designation("D1").unwrap();
+        assert_eq!(by_position.id(), d1);
+        assert_eq!(by_position.designation(), "Reserve Tip Box 4(1)");
+        let by_name = layout
+            .position_by_designation("Reserve Tip Box 4(1)")
+            .unwrap();
+        assert_eq!(by_name.position(), "D1");
+        assert!(layout.position_by_designation("Z9").is_none());
+
+        // Of two locations at the same position, the one with the lowest id
+        let mut layout = Layout::new(Uuid::from_u128(1), "MainLayout");
+        layout.add_position(Uuid::from_u128(3), "C3");
+        layout.add_position(Uuid::from_u128(2), "C3");
+        assert_eq!(
+            layout.position_by_designation("C3").unwrap().id(),
+            Uuid::from_u128(2)
+        );
+    }
+
+    #[test]
+    fn variables_by_designation() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let offset = app
+            .global_var_by_designation("g_ReservedTipBoxZOffset")
+            .unwrap();
+        assert_eq!(
+            offset.id(),
+            "82ADDA04-FE60-4F14-B0C6-81AF2B5E524B".parse().unwrap()
+        );
+        assert!(app.global_var_by_designation("l_TmpVar").is_none());
+
+        let load_tips = "AC3B3B52-58F2-40B1-8121-44F4F27F6E7D".parse().unwrap();
+        let tmp = app.local_var_by_designation(load_tips, "l_TmpVar").unwrap();
+        assert_eq!(
+            tmp.id(),
+            "7B969B10-9C7D-43DC-A4C1-1067EF302A56".parse().unwrap()
+        );
+        assert!(app
+            .local_var_by_designation(load_tips, "g_ReservedTipBoxZOffset")
+            .is_none());
+        assert!(app
+            .local_var_by_designation(Uuid::nil(), "l_TmpVar")
+            .is_none());
+    }
+
+    #[test]
+    fn layout_slots_of_complex_app() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let layout = app.layout_of_method(app.start_method()).unwrap();
+        let slots = app.layout_slots(layout).unwrap();
+        assert_eq!(slots, vec!["A1", "B3", "C3", "D5"]);
+        assert_eq!(app.layout_slot_count(layout), Some(4));
+        assert_eq!(app.layout_slot_count(Uuid::nil()), None);
+        assert!(app.layout_slots(Uuid::nil()).is_none());
+    }
+
+    #[test]
+    fn positions_of_main_layout() {
+        let doc = load_complex_app();
+        let app = Loader::new(&doc).unwrap().build_application().unwrap();
+        let main_layout = "1B8A66AB-2BA3-4FDF-8982-A5D364ED9874".parse().unwrap();
+        let positions = app.positions_of_layout(main_layout).unwrap();
+        assert_eq!(positions.len(), 17);
+        assert!(positions
+            .windows(2)
+            .all(|w| w[0].position() <= w[1].position()));
+
+        let d1 = positions.iter().find(|l| l.position() == "D1").unwrap();
+        assert_eq!(
+            d1.id(),
+            "504C5661-C3EB-4CA2-9E7A-A974828D4C68".parse().unwrap()
+        );
+        assert_eq!(d1.designation(), "Reserve Tip Box 4(1)");
+        assert_eq!(d1.number_stacked(), 1);
+        assert_eq!(
+            d1.consumable(),
+            "5917e9be-ef73-403a-baeb-ff779944598e".parse().unwrap()
+        );
+        assert!(app.positions_of_layout(Uuid::nil()).is_none());
+    }
+
     #[test]
     fn instruction_value_parsing() {
-        const DATA: &'static str = r#"<ZPosOffset>
+        const DATA: &str = r#"<ZPosOffset>
 
         <_DirectValue>0</_DirectValue>
 
@@ -1483,14 +3888,14 @@ mod tests {
     </ZPosOffset>"#;
         let doc = Document::parse(DATA).unwrap();
         let node = doc.root().first_element_child().unwrap();
-        let r = Loader::build_instruction_value(&node, VariableType::Float);
+        let r = Loader::build_instruction_value(&node, VariableType::Float).unwrap();
         assert_eq!(r.direct, VariableValue::Float(0.0));
         assert_eq!(r.variable, None);
     }
 
     #[test]
     fn parameter_parsing() {
-        const DATA: &'static str = r#"<Parameter1>
+        const DATA: &str = r#"<Parameter1>
 
         <ForParameter>4C09727C-1AF0-45D5-B756-BD21A058A7A7</ForParameter>
 
@@ -1503,7 +3908,7 @@ mod tests {
     </Parameter1>"#;
         let doc = Document::parse(DATA).unwrap();
         let node = doc.root().first_element_child().unwrap();
-        let p = Loader::build_parameter(&node);
+        let p = Loader::build_parameter(&node).unwrap();
         assert_eq!(
             p.id,
             "4C09727C-1AF0-45D5-B756-BD21A058A7A7".parse().unwrap()
@@ -1511,4 +3916,349 @@ mod tests {
         assert_eq!(p.value.direct, VariableValue::Float(25.0));
         assert_eq!(p.value.variable, None);
     }
+
+    fn method_with_instructions(instructions: &[String]) -> String {
+        format!(
+            r#"<Method1>
+        <MethodDesignation>Main</MethodDesignation>
+        <ProgramID>3AC47C04-DCCE-4036-8F9F-6AD7D530E220</ProgramID>
+        <LayoutID>BB37AAC5-102D-4367-B1BA-98B7D1E47EF0</LayoutID>
+        <LocalVariablesPool>
+          <VariablesPool>
+            <VariablesPoolDesignation>Main:LOCAL Variables</VariablesPoolDesignation>
+            <VariablesPoolID>9DC99ADE-3702-4D6A-A34C-489E64D46183</VariablesPoolID>
+            <VariablesCount>0</VariablesCount>
+          </VariablesPool>
+        </LocalVariablesPool>
+        <Parameters>
+          <VariablesPool>
+            <VariablesPoolDesignation>Main:Parameters</VariablesPoolDesignation>
+            <VariablesPoolID>68A3020C-9427-4E0E-9235-F8A40FF66969</VariablesPoolID>
+            <VariablesCount>0</VariablesCount>
+          </VariablesPool>
+        </Parameters>
+        <InstructionsCount>{}</InstructionsCount>
+        {}
+      </Method1>"#,
+            instructions.len(),
+            instructions.concat()
+        )
+    }
+
+    fn rem_instruction(comment: &str) -> String {
+        format!(
+            r#"<Instruction>
+          <IsComment>0</IsComment>
+          <InstructionDesignation>REM</InstructionDesignation>
+          <ControlInstr_REM>
+            <CommentText>{}</CommentText>
+          </ControlInstr_REM>
+        </Instruction>"#,
+            comment
+        )
+    }
+
+    const HOME_INSTRUCTION: &str = r#"<Instruction>
+          <IsComment>0</IsComment>
+          <InstructionDesignation>Home</InstructionDesignation>
+          <HomeInstr>
+            <X>-1</X>
+            <Y>-1</Y>
+            <Z>-1</Z>
+          </HomeInstr>
+        </Instruction>"#;
+
+    #[test]
+    fn unknown_instruction_still_loads() {
+        const DATA: &str = r#"<Instruction7>
+          <IsComment>1</IsComment>
+          <InstructionDesignation>Levitate Plate</InstructionDesignation>
+          <LevitateInstr>
+            <Height>12</Height>
+          </LevitateInstr>
+        </Instruction7>"#;
+        let doc = Document::parse(DATA).unwrap();
+        let instr = Loader::build_instruction(&doc.root_element(), false).unwrap();
+        assert_eq!(
+            instr.command,
+            Command::Unknown {
+                designation: "Levitate Plate".to_string()
+            }
+        );
+        assert!(instr.is_comment);
+
+        let method = method_with_instructions(&[
+            HOME_INSTRUCTION.to_string(),
+            DATA.replace("<IsComment>1", "<IsComment>0"),
+            rem_instruction("after"),
+        ]);
+        let doc = Document::parse(&method).unwrap();
+        let method = Loader::build_method(&doc.root_element(), false).unwrap();
+        assert_eq!(method.instructions.len(), 3);
+        assert!(matches!(
+            method.instructions[1].command,
+            Command::Unknown { .. }
+        ));
+    }
+
+    #[test]
+    fn disabled_aspirate_is_not_a_rem() {
+        const DISABLED_ASPIRATE: &str = r#"<Instruction2>
+          <IsComment>1</IsComment>
+          <InstructionDesignation>Aspirate</InstructionDesignation>
+          <AspirateInstr>
+            <HeadPosInstr>
+              <DeckVariableID>[[[[---NONE---]]]]</DeckVariableID>
+              <DeckLocation>
+                <_DirectValue>D3</_DirectValue>
+                <_Variable>[[[[---NONE---]]]]</_Variable>
+              </DeckLocation>
+              <ZPosOffset>
+                <_DirectValue>0</_DirectValue>
+                <_Variable>[[[[---NONE---]]]]</_Variable>
+              </ZPosOffset>
+            </HeadPosInstr>
+            <VarVolume>
+              <_DirectValue>10</_DirectValue>
+              <_Variable>[[[[---NONE---]]]]</_Variable>
+            </VarVolume>
+          </AspirateInstr>
+        </Instruction2>"#;
+        let kind = |xml: &str| {
+            let doc = Document::parse(xml).unwrap();
+            let instr = Loader::build_instruction(&doc.root_element(), false).unwrap();
+            (instr.is_comment, instr.kind())
+        };
+
+        assert_eq!(
+            kind(DISABLED_ASPIRATE),
+            (true, InstructionKind::DisabledActive)
+        );
+        assert_eq!(
+            kind(&DISABLED_ASPIRATE.replace("<IsComment>1", "<IsComment>0")),
+            (false, InstructionKind::Active)
+        );
+        assert_eq!(
+            kind(&rem_instruction("Only text")),
+            (false, InstructionKind::Comment)
+        );
+        assert_eq!(
+            kind(&rem_instruction("Only text").replace("<IsComment>0", "<IsComment>1")),
+            (true, InstructionKind::Comment)
+        );
+    }
+
+    #[test]
+    fn string_concatenation_parsing() {
+        // Assignment is the only operation seen in exports, so concatenation keeps its code
+        const DATA: &str = r#"<Instruction4>
+          <IsComment>0</IsComment>
+          <InstructionDesignation>String Operation</InstructionDesignation>
+          <ControlInstr_StringOps>
+            <Operation>0</Operation>
+            <Result>
+              <_DirectValue></_DirectValue>
+              <_Variable>AE8BBF3B-A725-4830-90C7-B96A37EF7C73</_Variable>
+            </Result>
+            <Operand1>
+              <_DirectValue>C:\Output\</_DirectValue>
+              <_Variable>[[[[---NONE---]]]]</_Variable>
+            </Operand1>
+            <Operand2>
+              <_DirectValue>run 1.csv</_DirectValue>
+              <_Variable>[[[[---NONE---]]]]</_Variable>
+            </Operand2>
+          </ControlInstr_StringOps>
+        </Instruction4>"#;
+        let doc = Document::parse(DATA).unwrap();
+        let instr = Loader::build_instruction(&doc.root_element(), false).unwrap();
+        let literal = |text: &str| InstructionValue {
+            direct: VariableValue::String(text.to_string()),
+            variable: None,
+        };
+        assert_eq!(
+            instr.command,
+            Command::StringOperation {
+                operator: StringOperator::Other(0),
+                operands: vec![literal("C:\\Output\\"), literal("run 1.csv")],
+                result: InstructionValue {
+                    direct: VariableValue::String(String::new()),
+                    variable: Some("AE8BBF3B-A725-4830-90C7-B96A37EF7C73".parse().unwrap()),
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn while_loop_parsing() {
+        const DATA: &str = r#"<Instruction6>
+          <IsComment>0</IsComment>
+          <InstructionDesignation>While Loop</InstructionDesignation>
+          <ControlInstr_WhileLoop>
+            <ComparisonType>2</ComparisonType>
+            <Comparator>Equals</Comparator>
+            <TestVariableBoolParam>
+              <_DirectValue>0</_DirectValue>
+              <_Variable>0F06FB30-258B-4F6C-AB29-32F7F58ADBD1</_Variable>
+            </TestVariableBoolParam>
+            <CompareValueBoolParam>
+              <_DirectValue>-1</_DirectValue>
+              <_Variable>[[[[---NONE---]]]]</_Variable>
+            </CompareValueBoolParam>
+          </ControlInstr_WhileLoop>
+        </Instruction6>"#;
+        let doc = Document::parse(DATA).unwrap();
+        let instr = Loader::build_instruction(&doc.root_element(), false).unwrap();
+        match instr.command {
+            Command::WhileLoop {
+                comparator,
+                lhs,
+                rhs,
+            } => {
+                assert_eq!(comparator, Comparator::Equals);
+                assert_eq!(
+                    lhs.variable,
+                    Some("0F06FB30-258B-4F6C-AB29-32F7F58ADBD1".parse().unwrap())
+                );
+                assert_eq!(rhs.variable, None);
+                assert_eq!(rhs.direct, VariableValue::Bool(true));
+            }
+            other => panic!("expected a While Loop, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn array_dispense_parsing() {
+        const HEAD: &str = r#"<HeadPosInstr>
+              <PositionHeadInstr>
+                <DeckParameter>
+                  <DeckVariableID>[[[[---NONE---]]]]</DeckVariableID>
+                </DeckParameter>
+                <DeckLocation>
+                  <_DirectValue>{}</_DirectValue>
+                  <_Variable>[[[[---NONE---]]]]</_Variable>
+                </DeckLocation>
+                <ZPosOffset>
+                  <_DirectValue>0</_DirectValue>
+                  <_Variable>[[[[---NONE---]]]]</_Variable>
+                </ZPosOffset>
+              </PositionHeadInstr>
+            </HeadPosInstr>"#;
+        let data = format!(
+            r#"<Instruction3>
+          <IsComment>0</IsComment>
+          <DCCControl>Sciclone</DCCControl>
+          <InstructionDesignation>Dispense</InstructionDesignation>
+          <AspirateDispenseInstr>
+            <DispenseAll>0</DispenseAll>
+            {}
+            <VarVolume>
+              <_DirectValue>20</_DirectValue>
+              <_Variable>[[[[---NONE---]]]]</_Variable>
+            </VarVolume>
+            <ArrayDestinations>
+              <Destination>
+                {}
+                <Volume> 30 </Volume>
+              </Destination>
+              <Destination>
+                {}
+                <Volume>25</Volume>
+              </Destination>
+            </ArrayDestinations>
+          </AspirateDispenseInstr>
+        </Instruction3>"#,
+            HEAD.replace("{}", "B4"),
+            HEAD.replace("{}", "B5"),
+            HEAD.replace("{}", "B6")
+        );
+        let location = |head: &PositionHead| head.deck_location.direct.clone();
+        let doc = Document::parse(&data).unwrap();
+        let instr = Loader::build_instruction(&doc.root_element(), false).unwrap();
+        match instr.command {
+            Command::Dispense {
+                position_head,
+                volume,
+                destinations,
+                ..
+            } => {
+                assert_eq!(
+                    location(&position_head),
+                    VariableValue::String("B4".to_string())
+                );
+                assert_eq!(volume.direct, VariableValue::Float(20.0));
+                let destinations: Vec<_> = destinations
+                    .iter()
+                    .map(|(head, volume)| (location(head), *volume))
+                    .collect();
+                assert_eq!(
+                    destinations,
+                    vec![
+                        (VariableValue::String("B5".to_string()), 30.0),
+                        (VariableValue::String("B6".to_string()), 25.0),
+                    ]
+                );
+            }
+            other => panic!("expected a Dispense, found {:?}", other),
+        }
+
+        // A dispense to one well has no destinations element
+        let start = data.find("<ArrayDestinations>").unwrap();
+        let end = data.find("</ArrayDestinations>").unwrap() + "</ArrayDestinations>".len();
+        let single = format!("{}{}", &data[..start], &data[end..]);
+        let doc = Document::parse(&single).unwrap();
+        let instr = Loader::build_instruction(&doc.root_element(), false).unwrap();
+        assert!(matches!(
+            instr.command,
+            Command::Dispense { destinations, .. } if destinations.is_empty()
+        ));
+    }
+
+    fn stable_ids(instructions: &[String]) -> Vec<u64> {
+        let xml_str = method_with_instructions(instructions);
+        let doc = Document::parse(&xml_str).unwrap();
+        let method = Loader::build_method(&doc.root_element(), false).unwrap();
+        method.instructions.iter().map(|i| i.stable_id()).collect()
+    }
+
+    #[test]
+    fn stable_id_independent_of_other_lines() {
+        let home = HOME_INSTRUCTION.to_string();
+        let original = stable_ids(&[rem_instruction("Start"), home.clone(), home.clone()]);
+        let edited = stable_ids(&[rem_instruction("Begin"), home.clone(), home.clone()]);
+        let inserted = stable_ids(&[
+            rem_instruction("Start"),
+            rem_instruction("Inserted"),
+            home.clone(),
+            home,
+        ]);
+
+        assert_ne!(original[0], edited[0]);
+        assert_eq!(original[1..], edited[1..]);
+        assert_eq!(original[1..], inserted[2..]);
+
+        // Identical instructions are told apart by their ordinal
+        assert_ne!(original[1], original[2]);
+    }
+
+    #[test]
+    fn variable_value_to_json() {
+        use serde_json::{json, Value};
+
+        assert_eq!(Value::from(&VariableValue::Bool(true)), json!(true));
+        assert_eq!(Value::from(&VariableValue::Float(2.5)), json!(2.5));
+        assert_eq!(Value::from(&VariableValue::Float(f64::NAN)), Value::Null);
+        assert_eq!(Value::from(&VariableValue::Int(7)), json!(7));
+        assert_eq!(
+            Value::from(&VariableValue::String("g_Count".to_string())),
+            json!("g_Count")
+        );
+        assert_eq!(Value::from(&VariableValue::Seconds(90)), json!(90));
+
+        // The derived serialization keeps the variant for round trips
+        let tagged = serde_json::to_value(VariableValue::Seconds(90)).unwrap();
+        assert_eq!(tagged, json!({"Seconds": 90}));
+        let back: VariableValue = serde_json::from_value(tagged).unwrap();
+        assert_eq!(back, VariableValue::Seconds(90));
+    }
 }
@@ -0,0 +1,163 @@
+use crate::SavedApplication;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+/// Applications kept together, such as the exports of one protocol suite, whose methods may call
+/// methods of the other applications. The workspace serializes as one document of its
+/// applications. The index of the application each method belongs to is built from them, so a Run
+/// Method of another application can still be resolved after it is read back.
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[serde(from = "StoredWorkspace")]
+pub struct Workspace {
+    applications: BTreeMap<String, SavedApplication>,
+    #[serde(skip)]
+    method_index: HashMap<Uuid, String>,
+}
+
+// What a workspace is read back from, before its index is built
+#[derive(serde::Deserialize)]
+struct StoredWorkspace {
+    applications: BTreeMap<String, SavedApplication>,
+}
+
+impl From<StoredWorkspace> for Workspace {
+    fn from(stored: StoredWorkspace) -> Self {
+        let mut workspace = Workspace {
+            applications: stored.applications,
+            method_index: HashMap::new(),
+        };
+        workspace.build_index();
+        workspace
+    }
+}
+
+impl Workspace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an application under a name, giving back the application it replaces. A method id in
+    /// several applications belongs to the last of them in alphabetical order.
+    pub fn add_application(
+        &mut self,
+        name: &str,
+        app: SavedApplication,
+    ) -> Option<SavedApplication> {
+        let replaced = self.applications.insert(name.to_string(), app);
+        self.build_index();
+        replaced
+    }
+
+    pub fn application(&self, name: &str) -> Option<&SavedApplication> {
+        self.applications.get(name)
+    }
+
+    /// Names of the applications, in alphabetical order
+    pub fn application_names(&self) -> Vec<&str> {
+        self.applications.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// The name of the application the method belongs to
+    pub fn owner_of_method(&self, method_id: Uuid) -> Option<&str> {
+        self.method_index.get(&method_id).map(|n| n.as_str())
+    }
+
+    /// The application the method belongs to, with its name
+    pub fn resolve_method(&self, method_id: Uuid) -> Option<(&str, &SavedApplication)> {
+        let name = self.owner_of_method(method_id)?;
+        self.applications.get(name).map(|app| (name, app))
+    }
+
+    fn build_index(&mut self) {
+        self.method_index.clear();
+        for (name, app) in &self.applications {
+            for &method_id in app.methods.keys() {
+                self.method_index.insert(method_id, name.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Command, Instruction, Layout, SavedApplicationBuilder};
+
+    fn app(main: Uuid, instructions: Vec<Instruction>) -> SavedApplication {
+        let layout = Uuid::from_u128(100);
+        SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout, "MainLayout"))
+            .add_method(main, "Main", layout, instructions)
+            .set_start_method(main)
+            .build()
+            .unwrap()
+    }
+
+    fn round_trip(workspace: &Workspace) -> Workspace {
+        let json = serde_json::to_string(workspace).unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn cross_application_call_after_round_trip() {
+        let (caller, callee) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let call = Instruction::new(Command::RunMethod {
+            method: callee,
+            parameters: vec![],
+        });
+        let mut workspace = Workspace::new();
+        workspace.add_application("Library", app(callee, vec![]));
+        workspace.add_application("Protocol", app(caller, vec![call]));
+
+        let back = round_trip(&workspace);
+        assert_eq!(
+            serde_json::to_value(&back).unwrap(),
+            serde_json::to_value(&workspace).unwrap()
+        );
+        assert_eq!(back.application_names(), vec!["Library", "Protocol"]);
+
+        let protocol = back.application("Protocol").unwrap();
+        let called = match &protocol.instruction(caller, 0).unwrap().command {
+            Command::RunMethod { method, .. } => *method,
+            c => panic!("expected a Run Method, found {:?}", c),
+        };
+        let (owner, library) = back.resolve_method(called).unwrap();
+        assert_eq!(owner, "Library");
+        assert!(library.has_method(called));
+    }
+
+    #[test]
+    fn index_is_not_serialized() {
+        let mut workspace = Workspace::new();
+        workspace.add_application("Library", app(Uuid::from_u128(1), vec![]));
+
+        let json = serde_json::to_value(&workspace).unwrap();
+        assert!(json.get("method_index").is_none());
+    }
+
+    #[test]
+    fn method_in_two_applications_after_round_trip() {
+        let shared = Uuid::from_u128(1);
+        let mut workspace = Workspace::new();
+        workspace.add_application("Protocol", app(shared, vec![]));
+        workspace.add_application("Library", app(shared, vec![]));
+        assert_eq!(workspace.owner_of_method(shared), Some("Protocol"));
+
+        let back = round_trip(&workspace);
+        assert_eq!(back.owner_of_method(shared), Some("Protocol"));
+    }
+
+    #[test]
+    fn replacing_an_application_drops_its_methods() {
+        let mut workspace = Workspace::new();
+        workspace.add_application("Library", app(Uuid::from_u128(1), vec![]));
+        let replaced = workspace.add_application("Library", app(Uuid::from_u128(2), vec![]));
+
+        assert!(replaced.is_some());
+        assert_eq!(workspace.owner_of_method(Uuid::from_u128(1)), None);
+        assert_eq!(
+            workspace.owner_of_method(Uuid::from_u128(2)),
+            Some("Library")
+        );
+    }
+}
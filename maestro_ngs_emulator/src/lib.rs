@@ -1,16 +1,28 @@
+mod lower;
 mod machine;
+#[cfg(any(test, feature = "test-util"))]
+mod recording;
+mod scope;
+mod sink;
 
-use machine::{Execute, Machine, MachineError, ScicloneG3};
+pub use lower::lower_command;
+pub use machine::{
+    DeckEnvelope, DispenseEmptyPolicy, Execute, Machine, MachineError, MachineSnapshot,
+    MachineState, ScicloneConfig, ScicloneG3,
+};
 use maestro_ngs_application::{
-    Command, InstructionValue, Layout, LoadEjectTipsHead, PositionHead, SavedApplication, Variable,
-    VariableValue,
+    Command, Layout, SavedApplication, Variable, VariableType, VariableValue,
 };
+#[cfg(any(test, feature = "test-util"))]
+pub use recording::{OwnedExecute, RecordingMachine};
+pub use scope::Scope;
 use serde::{self, ser::SerializeStruct};
-use std::collections::HashMap;
+pub use sink::{CsvSink, JsonLinesSink, TextSink, TraceSink};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use uuid::Uuid;
 
 type Result<T> = std::result::Result<T, EmulatorError>;
-type ScicloneG3Emulator<'a> = Emulator<'a, ScicloneG3>;
+pub type ScicloneG3Emulator<'a> = Emulator<'a, ScicloneG3>;
 
 pub struct Emulator<'a, M: Machine> {
     saved_app: &'a SavedApplication,
@@ -23,13 +35,106 @@ pub struct Emulator<'a, M: Machine> {
     stack_instructions: Vec<usize>,
     stack_params: Vec<HashMap<Uuid, Variable>>,
     stack_layout: Vec<Uuid>,
+    stack_loops: Vec<LoopFrame>,
+    stack_blocks: Vec<BlockFrame>,
+    volume_leaks: Vec<VolumeLeak>,
+    volume_aspirated: f64,
+    volume_dispensed: f64,
+    overrides: HashMap<(Uuid, usize), VariableValue>,
+    record_history: bool,
+    history: HashMap<Uuid, Vec<ValueChange>>,
+    dialog: Option<&'a str>,
+    method_trace: Vec<MethodEvent>,
+    expectations: Vec<Expectation<'a>>,
+    expectation_failures: Vec<ExpectationFailure>,
+    observer: Option<Observer<'a>>,
+    time_breakdown: BTreeMap<&'static str, f64>,
+    error: Option<String>,
+    initial_machine: MachineSnapshot,
+    record_undo: bool,
+    undo: Vec<UndoState<'a>>,
 }
 
+type Observer<'a> = Box<dyn FnMut(&Action<'a>) + 'a>;
+
 impl<'a, M: Machine> Emulator<'a, M> {
     pub fn new(saved_app: &'a SavedApplication) -> Result<Self> {
+        Emulator::with_machine(saved_app, M::new())
+    }
+
+    /// Lower every instruction reachable from the start method, without running the application.
+    /// Methods are followed through the instructions that call them, and each line is lowered
+    /// once with the variables' initial values, so both branches of conditionals and the body of
+    /// loops are covered. Commented out lines are left out. Conditions depend on the data at run
+    /// time, so their operands are only checked to exist and they are not in the result. All
+    /// errors are collected, rather than stopping at the first.
+    pub fn compile(
+        saved_app: &'a SavedApplication,
+    ) -> std::result::Result<Vec<(Uuid, usize, Execute<'a>)>, Vec<EmulatorError>> {
+        let mut compiled = Vec::new();
+        let mut errors = Vec::new();
+        let mut queue = vec![saved_app.start_method()];
+        let mut seen: HashSet<Uuid> = queue.iter().cloned().collect();
+
+        while let Some(method_id) = queue.pop() {
+            let (locals, params, layout_id) = match (
+                saved_app.local_variables_of_method(method_id),
+                saved_app.parameters_of_method(method_id),
+                saved_app.layout_of_method(method_id),
+            ) {
+                (Some(locals), Some(params), Some(layout_id)) => (locals, params, layout_id),
+                _ => {
+                    errors.push(EmulatorError::UnknownMethod(method_id));
+                    continue;
+                }
+            };
+            let layout = match saved_app.layouts().get(&layout_id) {
+                Some(layout) => layout,
+                None => {
+                    errors.push(EmulatorError::UnknownLayout(layout_id));
+                    continue;
+                }
+            };
+            let scope = Scope::new(locals, params, saved_app.global_variables());
+
+            let count = saved_app.instruction_count(method_id).unwrap_or(0);
+            for line in 0..count {
+                let instr = saved_app.instruction(method_id, line).unwrap();
+                if instr.is_comment {
+                    continue;
+                }
+                let command = &instr.command;
+                if let Command::RunMethod { method, .. } = command {
+                    if seen.insert(*method) {
+                        queue.push(*method);
+                    }
+                }
+                if let Command::IfThen { lhs, rhs, .. } | Command::WhileLoop { lhs, rhs, .. } =
+                    command
+                {
+                    if let Err(e) = scope.resolve(lhs).and(scope.resolve(rhs)) {
+                        errors.push(locate_error(e, method_id, line));
+                    }
+                    continue;
+                }
+                match lower_command(command, &scope, layout) {
+                    Ok(exe) => compiled.push((method_id, line, exe)),
+                    Err(e) => errors.push(locate_error(e, method_id, line)),
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(compiled)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Emulate on a machine that has already been set up, such as one with its own configuration
+    pub fn with_machine(saved_app: &'a SavedApplication, machine: M) -> Result<Self> {
         let mut emu = Emulator {
             saved_app,
-            machine: M::new(),
             action_executed: Vec::new(),
             global_variables: saved_app.global_variables().clone(),
             layouts: saved_app.layouts(),
@@ -38,6 +143,25 @@ impl<'a, M: Machine> Emulator<'a, M> {
             stack_params: Vec::new(),
             local_variables: HashMap::new(),
             stack_layout: Vec::new(),
+            stack_loops: Vec::new(),
+            stack_blocks: Vec::new(),
+            volume_leaks: Vec::new(),
+            volume_aspirated: 0.0,
+            volume_dispensed: 0.0,
+            overrides: HashMap::new(),
+            record_history: false,
+            history: HashMap::new(),
+            dialog: None,
+            method_trace: Vec::new(),
+            expectations: Vec::new(),
+            expectation_failures: Vec::new(),
+            observer: None,
+            time_breakdown: BTreeMap::new(),
+            error: None,
+            initial_machine: machine.snapshot(),
+            record_undo: false,
+            undo: Vec::new(),
+            machine,
         };
 
         let uuid = saved_app.start_method();
@@ -50,12 +174,17 @@ impl<'a, M: Machine> Emulator<'a, M> {
             emu.local_variables.insert(uuid, local.clone());
         }
 
-        Emulator::push_method(&mut emu, uuid)?;
+        Emulator::push_method(&mut emu, uuid, Vec::new())?;
         Ok(emu)
     }
 
-    fn push_method(emu: &mut Self, uuid: Uuid) -> Result<()> {
+    fn push_method(
+        emu: &mut Self,
+        uuid: Uuid,
+        arguments: Vec<(Uuid, VariableValue)>,
+    ) -> Result<()> {
         emu.stack_methods.push(uuid);
+        emu.method_trace.push(MethodEvent::Enter(uuid));
 
         let layout_uuid = emu
             .saved_app
@@ -63,40 +192,476 @@ impl<'a, M: Machine> Emulator<'a, M> {
             .ok_or(EmulatorError::UnknownMethod(uuid))?;
         emu.stack_layout.push(layout_uuid);
 
-        let saved_param = emu
+        let mut saved_param = emu
             .saved_app
             .parameters_of_method(uuid)
             .cloned()
             .ok_or(EmulatorError::UnknownMethod(uuid))?;
+        for (param_id, value) in arguments {
+            saved_param
+                .get_mut(&param_id)
+                .ok_or(EmulatorError::UnknownVariable(param_id))?
+                .set_value(value);
+        }
         emu.stack_params.push(saved_param);
 
         emu.stack_instructions.push(0);
         Ok(())
     }
 
+    /// The state of the machine as JSON, such as at the end of a run
+    pub fn final_state_json(&self) -> String {
+        serde_json::to_string(&self.machine.state()).unwrap()
+    }
+
+    /// The machine the application is emulated on
+    pub fn machine(&self) -> &M {
+        &self.machine
+    }
+
     pub fn done(&self) -> bool {
-        self.stack_methods.len() == 0
+        self.stack_methods.is_empty()
+    }
+
+    /// Start the application over, with the variables at their saved values and the machine in the
+    /// state it was given in, keeping its settings. Overrides, expectations, the observer and
+    /// whether variable history and undo states are recorded are kept.
+    pub fn reset(&mut self) -> Result<()> {
+        let mut machine = std::mem::replace(&mut self.machine, M::new());
+        machine.restore(&self.initial_machine);
+        let mut emu = Emulator::with_machine(self.saved_app, machine)?;
+        emu.overrides = std::mem::take(&mut self.overrides);
+        emu.expectations = std::mem::take(&mut self.expectations);
+        emu.observer = self.observer.take();
+        emu.record_history = self.record_history;
+        emu.record_undo = self.record_undo;
+        *self = emu;
+        Ok(())
+    }
+
+    /// Undo the last executed action, going back to the machine, variables, blocks and method
+    /// stack from before it. Only actions executed while `record_undo` is on can be undone, so
+    /// nothing happens before the first of them.
+    pub fn step_back(&mut self) -> Result<()> {
+        let state = match self.undo.pop() {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+        self.action_executed.pop();
+        self.machine.restore(&state.machine);
+        self.global_variables = state.global_variables;
+        self.local_variables = state.local_variables;
+        self.stack_methods = state.stack_methods;
+        self.stack_instructions = state.stack_instructions;
+        self.stack_params = state.stack_params;
+        self.stack_layout = state.stack_layout;
+        self.stack_loops = state.stack_loops;
+        self.stack_blocks = state.stack_blocks;
+        self.volume_leaks.truncate(state.volume_leaks);
+        self.volume_aspirated = state.volume_aspirated;
+        self.volume_dispensed = state.volume_dispensed;
+        let history = state.history;
+        self.history
+            .retain(|uuid, changes| match history.get(uuid) {
+                Some(&count) => {
+                    changes.truncate(count);
+                    true
+                }
+                None => false,
+            });
+        self.dialog = state.dialog;
+        self.method_trace.truncate(state.method_trace);
+        self.expectation_failures
+            .truncate(state.expectation_failures);
+        self.time_breakdown = state.time_breakdown;
+        self.error = state.error;
+        Ok(())
+    }
+
+    fn undo_state(&self) -> UndoState<'a> {
+        UndoState {
+            machine: self.machine.snapshot(),
+            global_variables: self.global_variables.clone(),
+            local_variables: self.local_variables.clone(),
+            stack_methods: self.stack_methods.clone(),
+            stack_instructions: self.stack_instructions.clone(),
+            stack_params: self.stack_params.clone(),
+            stack_layout: self.stack_layout.clone(),
+            stack_loops: self.stack_loops.clone(),
+            stack_blocks: self.stack_blocks.clone(),
+            volume_leaks: self.volume_leaks.len(),
+            volume_aspirated: self.volume_aspirated,
+            volume_dispensed: self.volume_dispensed,
+            history: self
+                .history
+                .iter()
+                .map(|(&uuid, changes)| (uuid, changes.len()))
+                .collect(),
+            dialog: self.dialog,
+            method_trace: self.method_trace.len(),
+            expectation_failures: self.expectation_failures.len(),
+            time_breakdown: self.time_breakdown.clone(),
+            error: self.error.clone(),
+        }
+    }
+
+    /// Totals of the run so far
+    pub fn run_summary(&self) -> RunSummary {
+        let skipped = self.action_executed.iter().filter(|a| a.skip).count();
+        RunSummary {
+            total_steps: self.action_executed.len(),
+            executed: self.action_executed.len() - skipped,
+            skipped,
+            volume_aspirated: self.volume_aspirated,
+            volume_dispensed: self.volume_dispensed,
+            tip_events: self.tip_events().len(),
+            volume_leaks: self.volume_leaks.clone(),
+            estimated_seconds: self.machine.elapsed_seconds(),
+            time_breakdown: self
+                .time_breakdown
+                .iter()
+                .map(|(&name, &seconds)| (name.to_string(), seconds))
+                .collect(),
+            error: self.error.clone(),
+        }
+    }
+
+    /// Tips ejected while still holding liquid, in the order they were ejected. Only a machine
+    /// with a `ScicloneConfig::max_eject_volume` over zero ejects such tips.
+    pub fn volume_leaks(&self) -> Vec<VolumeLeak> {
+        self.volume_leaks.clone()
+    }
+
+    /// Keep the state from before every action executed from now on, so `step_back` can undo it.
+    /// Each state holds a copy of the variables and stacks, so recording is off by default.
+    /// Turning it off drops the states kept so far.
+    pub fn record_undo(&mut self, record: bool) {
+        self.record_undo = record;
+        if !record {
+            self.undo.clear();
+        }
+    }
+
+    /// Record every write to a variable from now on, so it can be explained later. Recording is off
+    /// by default.
+    pub fn record_variable_history(&mut self, record: bool) {
+        self.record_history = record;
+    }
+
+    /// The saved value of a variable followed by every recorded write to it, in order
+    pub fn explain_variable(&self, uuid: Uuid) -> Vec<ValueChange> {
+        let mut changes = Vec::new();
+        if let Some(value) = self.saved_value(uuid) {
+            changes.push(ValueChange {
+                method: None,
+                line: None,
+                value: value.clone(),
+            });
+        }
+        if let Some(recorded) = self.history.get(&uuid) {
+            changes.extend(recorded.iter().cloned());
+        }
+        changes
+    }
+
+    /// The global and local variables with their current values as CSV, with a header. Globals come
+    /// first, then the locals of each method in the order of the application, each sorted by name.
+    pub fn variables_csv(&self) -> String {
+        let mut csv = String::from("scope,id,designation,value\n");
+        let mut push_rows = |scope: &str, variables: &HashMap<Uuid, Variable>| {
+            let mut sorted: Vec<(&Uuid, &Variable)> = variables.iter().collect();
+            sorted.sort_by_key(|&(id, v)| (v.designation(), *id));
+            for (id, variable) in sorted {
+                let value = match variable.value() {
+                    VariableValue::Bool(b) => b.to_string(),
+                    VariableValue::Float(f) => f.to_string(),
+                    VariableValue::Int(i) => i.to_string(),
+                    VariableValue::String(s) => s.clone(),
+                    VariableValue::Seconds(s) => s.to_string(),
+                };
+                csv.push_str(&format!(
+                    "{},{},{},{}\n",
+                    csv_field(scope),
+                    id,
+                    csv_field(variable.designation()),
+                    csv_field(&value)
+                ));
+            }
+        };
+        push_rows("global", &self.global_variables);
+        for method_id in self.saved_app.methods_in_order() {
+            if let Some(locals) = self.local_variables.get(&method_id) {
+                let name = self.saved_app.name_method(method_id).unwrap_or_default();
+                push_rows(&format!("local {}", name), locals);
+            }
+        }
+        csv
+    }
+
+    /// Every method entered and exited so far, in the order it happened, starting with the entry
+    /// of the start method
+    pub fn method_trace(&self) -> &[MethodEvent] {
+        &self.method_trace
+    }
+
+    /// Every executed tip load and eject, in the order they happened
+    pub fn tip_events(&self) -> Vec<TipEvent<'a>> {
+        self.action_executed
+            .iter()
+            .filter(|a| !a.skip)
+            .filter_map(|a| {
+                let (position, tips_loaded) = match a.execute {
+                    Execute::LoadTips { position, .. } => (position, true),
+                    Execute::EjectTips { position } => (position, false),
+                    _ => return None,
+                };
+                Some(TipEvent {
+                    method: a.method,
+                    line: a.line,
+                    position,
+                    tips_loaded,
+                })
+            })
+            .collect()
+    }
+
+    /// Number of tip loads executed so far. Run to the end first for the tips a whole run uses.
+    pub fn total_tips_used(&self) -> usize {
+        self.tip_events().iter().filter(|e| e.tips_loaded).count()
+    }
+
+    /// The run so far split at tip loads, each cycle ending with the eject of its tips. A cycle whose
+    /// tips are still loaded is last.
+    pub fn tip_cycles(&self) -> Vec<TipCycle<'_, 'a>> {
+        let mut cycles = Vec::new();
+        let mut current: Option<TipCycle> = None;
+        for action in self.action_executed.iter().filter(|a| !a.skip) {
+            match &action.execute {
+                Execute::LoadTips { .. } => {
+                    cycles.extend(current.take());
+                    current = Some(TipCycle {
+                        load: action,
+                        eject: None,
+                        transfers: Vec::new(),
+                        volume_aspirated: 0.0,
+                        volume_dispensed: 0.0,
+                    });
+                }
+                Execute::EjectTips { .. } => {
+                    if let Some(mut cycle) = current.take() {
+                        cycle.eject = Some(action);
+                        cycles.push(cycle);
+                    }
+                }
+                exe => {
+                    if let Some(cycle) = current.as_mut() {
+                        let held = cycle.volume_aspirated - cycle.volume_dispensed;
+                        match exe {
+                            Execute::Aspirate { volume, .. } => cycle.volume_aspirated += volume,
+                            Execute::Dispense { volume, .. } => {
+                                cycle.volume_dispensed += volume.unwrap_or(held)
+                            }
+                            Execute::ArrayDispense { destinations } => {
+                                cycle.volume_dispensed +=
+                                    destinations.iter().map(|(_, v)| v).sum::<f64>()
+                            }
+                            _ => continue,
+                        }
+                        cycle.transfers.push(action);
+                    }
+                }
+            }
+        }
+        cycles.extend(current);
+        cycles
+    }
+
+    /// The blocks the current method is running in, outermost first. Empty at the top level of the
+    /// method or when the run is done.
+    pub fn current_block_context(&self) -> Vec<BlockKind> {
+        let depth = self.stack_methods.len();
+        self.stack_blocks
+            .iter()
+            .filter(|f| f.depth == depth)
+            .map(|f| f.kind)
+            .collect()
     }
 
-    pub fn next(&mut self) -> Result<Option<&Action>> {
+    /// Replace the volume of an aspirate or dispense instruction for this emulator only. The
+    /// override is used whether the instruction gives its volume directly or through a variable,
+    /// and must have the same type as the instruction's value.
+    pub fn override_literal(
+        &mut self,
+        method_id: Uuid,
+        line: usize,
+        new: VariableValue,
+    ) -> Result<()> {
+        let instr = self
+            .saved_app
+            .instruction(method_id, line)
+            .ok_or(EmulatorError::UnknownInstruction(method_id, line))?;
+        let original = match &instr.command {
+            Command::Aspirate { volume, .. } => &volume.direct,
+            Command::Dispense { volume, .. } => &volume.direct,
+            _ => return Err(EmulatorError::NotOverridable(method_id, line)),
+        };
+        if original.variable_type() != new.variable_type() {
+            return Err(EmulatorError::UnexpectedType {
+                expected: original.variable_type(),
+                found: new.variable_type(),
+                method: method_id,
+                line,
+            });
+        }
+        self.overrides.insert((method_id, line), new);
+        Ok(())
+    }
+
+    /// Check the state of the machine every time emulation reaches a line, before the line is
+    /// executed. A predicate that does not hold is a failure, given by `expectation_failures`,
+    /// and emulation goes on.
+    pub fn expect_at(
+        &mut self,
+        method_id: Uuid,
+        line: usize,
+        predicate: impl Fn(&MachineState) -> bool + 'a,
+    ) -> Result<()> {
+        self.saved_app
+            .instruction(method_id, line)
+            .ok_or(EmulatorError::UnknownInstruction(method_id, line))?;
+        self.expectations.push(Expectation {
+            method: method_id,
+            line,
+            predicate: Box::new(predicate),
+        });
+        Ok(())
+    }
+
+    /// Give every action `next` executes to the observer, as it is executed. Skipped actions, such
+    /// as comments, are not given. Replaces any earlier observer.
+    pub fn set_observer(&mut self, observer: impl FnMut(&Action<'a>) + 'a) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Expectations that did not hold so far, in the order they were checked
+    pub fn expectation_failures(&self) -> &[ExpectationFailure] {
+        &self.expectation_failures
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<&Action<'a>>> {
+        match self.advance() {
+            Ok(true) => Ok(self.action_executed.last()),
+            Ok(false) => Ok(None),
+            Err(e) => {
+                self.error = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    // Execute the next action, if the run is not done
+    fn advance(&mut self) -> Result<bool> {
+        let undo = if self.record_undo {
+            Some(self.undo_state())
+        } else {
+            None
+        };
         // Multiple methods may be finished. If a method A is last instruction of Main method.
         while self.try_finish_method()? {
             continue;
         }
 
         if self.done() {
-            return Ok(None);
+            return Ok(false);
         }
 
+        self.check_expectations()?;
         let action = self.build_action()?;
         self.execute_action(&action)?;
         let line = self
             .stack_instructions
             .last_mut()
             .ok_or(EmulatorError::EmptyStack)?;
-        self.action_executed.push(action);
         *line += 1;
-        Ok(Some(self.action_executed.last().unwrap()))
+        // The caller has already moved past the call, so it resumes on the next line
+        self.execute_control(&action)?;
+        if let Some(observer) = self.observer.as_mut().filter(|_| !action.skip) {
+            observer(&action);
+        }
+        self.action_executed.push(action);
+        if let Some(undo) = undo {
+            self.undo.push(undo);
+        }
+        Ok(true)
+    }
+
+    /// Like `next`, but emulation suspends at every dialog until `continue_after_dialog` is called,
+    /// so a driver can wait for the operator. Stepping while suspended returns the same dialog.
+    pub fn step(&mut self) -> Result<StepOutcome<'_, 'a>> {
+        if let Some(text) = self.dialog {
+            return Ok(StepOutcome::AwaitingDialog(text));
+        }
+        let dialog = match self.next()? {
+            None => return Ok(StepOutcome::Finished),
+            Some(Action {
+                skip: false,
+                execute: Execute::ShowDialog { text },
+                ..
+            }) => Some(*text),
+            Some(_) => None,
+        };
+        match dialog {
+            Some(text) => {
+                self.dialog = Some(text);
+                Ok(StepOutcome::AwaitingDialog(text))
+            }
+            None => Ok(StepOutcome::Action(self.action_executed.last().unwrap())),
+        }
+    }
+
+    /// Resume emulation suspended at a dialog
+    pub fn continue_after_dialog(&mut self) {
+        self.dialog = None;
+    }
+
+    /// Run to the end, stopping at the first error, and give every action executed since the
+    /// emulator was made or reset
+    pub fn run_to_end(&mut self) -> Result<&[Action<'a>]> {
+        while self.next()?.is_some() {}
+        Ok(&self.action_executed)
+    }
+
+    /// Run to the end, giving every step to the sink
+    pub fn run_with_sink(&mut self, sink: &mut dyn TraceSink) -> Result<()> {
+        while self.next()?.is_some() {
+            let action = self.action_executed.last().unwrap();
+            sink.emit(action, &self.machine.state())
+                .map_err(EmulatorError::Sink)?;
+        }
+        Ok(())
+    }
+
+    fn check_expectations(&mut self) -> Result<()> {
+        if self.expectations.is_empty() {
+            return Ok(());
+        }
+        let method = self.get_current_method()?;
+        let line = self.get_current_instruction()?;
+        let state = self.machine.state();
+        for (index, expectation) in self.expectations.iter().enumerate() {
+            if expectation.method == method
+                && expectation.line == line
+                && !(expectation.predicate)(&state)
+            {
+                self.expectation_failures.push(ExpectationFailure {
+                    method,
+                    line,
+                    expectation: index,
+                });
+            }
+        }
+        Ok(())
     }
 
     fn build_action(&self) -> Result<Action<'a>> {
@@ -110,7 +675,15 @@ impl<'a, M: Machine> Emulator<'a, M> {
                 .instruction(method_id, current_line)
                 .ok_or(EmulatorError::UnknownInstruction(method_id, current_line))
         }?;
-        let exe = self.build_execute(&instr.command)?;
+        let exe = match self.build_execute(&instr.command) {
+            Ok(exe) => exe,
+            // A commented out line is not run, so it may hold what the emulator can not lower
+            Err(_) if instr.is_comment => Execute::Unknown {
+                designation: instr.command.designation(),
+            },
+            Err(e) => return Err(locate_error(e, method_id, current_line)),
+        };
+        let exe = self.apply_override(method_id, current_line, exe)?;
         Ok(Action {
             method: method_id,
             line: current_line,
@@ -120,64 +693,367 @@ impl<'a, M: Machine> Emulator<'a, M> {
     }
 
     fn build_execute(&self, command: &'a Command) -> Result<Execute<'a>> {
-        match command {
-            Command::Aspirate {
-                position_head,
-                volume,
-            } => {
-                let position = self.get_position_positionhead(position_head)?;
-                let vol = self.get_instruction_value_float(volume)?;
-                Ok(Execute::Aspirate {
-                    position,
-                    volume: vol,
+        let uuid = self.get_current_layout()?;
+        let layout = self
+            .layouts
+            .get(&uuid)
+            .ok_or(EmulatorError::UnknownLayout(uuid))?;
+        lower_command(command, &self.get_current_scope()?, layout)
+    }
+
+    fn apply_override(
+        &self,
+        method_id: Uuid,
+        line: usize,
+        exe: Execute<'a>,
+    ) -> Result<Execute<'a>> {
+        let volume = match self.overrides.get(&(method_id, line)) {
+            Some(VariableValue::Float(f)) => *f,
+            Some(value) => {
+                return Err(EmulatorError::UnexpectedType {
+                    expected: VariableType::Float,
+                    found: value.variable_type(),
+                    method: method_id,
+                    line,
                 })
             }
-            Command::Dispense {
-                position_head,
+            None => return Ok(exe),
+        };
+        match exe {
+            Execute::Aspirate {
+                position, air_gap, ..
+            } => Ok(Execute::Aspirate {
+                position,
                 volume,
-                dispense_all,
+                air_gap,
+            }),
+            // Dispense all does not use the volume, so there is nothing to replace
+            Execute::Dispense {
+                position,
+                volume: Some(_),
+                capacity,
+            } => Ok(Execute::Dispense {
+                position,
+                volume: Some(volume),
+                capacity,
+            }),
+            exe => Ok(exe),
+        }
+    }
+
+    fn execute_action(&mut self, action: &Action) -> Result<()> {
+        if action.skip {
+            return Ok(());
+        }
+
+        let residual = self.machine.tip_volume();
+        let start = self.machine.elapsed_seconds();
+        self.machine.execute(&action.execute)?;
+        let seconds = self.machine.elapsed_seconds() - start;
+        if seconds > 0.0 {
+            *self
+                .time_breakdown
+                .entry(action.execute.name())
+                .or_default() += seconds;
+        }
+        // The change in tip volume is what the machine actually moved, e.g. after rounding
+        match action.execute {
+            Execute::Aspirate { .. } => {
+                self.volume_aspirated += self.machine.tip_volume() - residual;
+            }
+            Execute::ArrayDispense { .. } | Execute::Dispense { .. } => {
+                self.volume_dispensed += residual - self.machine.tip_volume();
+            }
+            Execute::EjectTips { .. } if residual > 0.0 => {
+                self.volume_leaks.push(VolumeLeak {
+                    method: action.method,
+                    line: action.line,
+                    volume: residual,
+                });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    // Instructions that change the flow of the application or its variables, rather than the machine
+    fn execute_control(&mut self, action: &Action) -> Result<()> {
+        if action.skip {
+            return Ok(());
+        }
+
+        match action.execute {
+            Execute::BeginLoop {
+                index,
+                from,
+                to,
+                step,
             } => {
-                let position = self.get_position_positionhead(position_head)?;
-                let vol = if *dispense_all {
-                    None
+                if loop_continues(from, to, step) {
+                    if let Some(uuid) = index {
+                        self.set_number(uuid, from, action)?;
+                    }
+                    self.stack_loops.push(LoopFrame {
+                        depth: self.stack_methods.len(),
+                        begin: action.line,
+                        index,
+                        value: from,
+                        to,
+                        step,
+                    });
+                    self.open_block(BlockKind::Loop);
                 } else {
-                    Some(self.get_instruction_value_float(volume)?)
-                };
-                Ok(Execute::Dispense {
-                    position,
-                    volume: vol,
-                })
+                    let end = self.find_block_end(action.method, action.line)?;
+                    self.jump(end + 1)?;
+                }
             }
-            Command::EjectTips {
-                load_eject_tips_head,
-            } => {
-                let position = self.get_position_loadeject_tip_head(load_eject_tips_head)?;
-                Ok(Execute::EjectTips { position })
+            Execute::EndLoop => {
+                let depth = self.stack_methods.len();
+                let frame = self
+                    .stack_loops
+                    .last_mut()
+                    .filter(|f| f.depth == depth)
+                    .ok_or(EmulatorError::UnmatchedBlock(action.method, action.line))?;
+                let value = frame.value + frame.step;
+                if loop_continues(value, frame.to, frame.step) {
+                    frame.value = value;
+                    let (begin, index) = (frame.begin, frame.index);
+                    if let Some(uuid) = index {
+                        self.set_number(uuid, value, action)?;
+                    }
+                    self.jump(begin + 1)?;
+                } else {
+                    self.stack_loops.pop();
+                    self.close_block(BlockKind::Loop, action)?;
+                }
             }
-            Command::LoadTips {
-                load_eject_tips_head,
-            } => {
-                let position = self.get_position_loadeject_tip_head(load_eject_tips_head)?;
-                Ok(Execute::LoadTips { position })
+            Execute::EndIf => self.close_block(BlockKind::If, action)?,
+            Execute::IfThen { condition: true } => self.open_block(BlockKind::If),
+            Execute::WhileLoop { condition: true } => self.open_block(BlockKind::While),
+            Execute::IfThen { condition: false } | Execute::WhileLoop { condition: false } => {
+                let end = self.find_block_end(action.method, action.line)?;
+                self.jump(end + 1)?;
+            }
+            // The test is evaluated again on every pass
+            Execute::EndWhile => {
+                self.close_block(BlockKind::While, action)?;
+                let begin = self.find_block_begin(action.method, action.line)?;
+                self.jump(begin)?;
             }
-            Command::Mix { position_head } => {
-                let position = self.get_position_positionhead(position_head)?;
-                Ok(Execute::Mix { position })
+            Execute::MathOperation { variable, value } => {
+                self.set_number(variable, value, action)?
             }
-            Command::REM { comment } => Ok(Execute::REM { comment }),
-            _ => panic!("Unknown command {:?}", command),
+            Execute::StringOperation {
+                variable,
+                ref value,
+            } => self.set_string(variable, value, action)?,
+            Execute::RunMethod { method } => {
+                let arguments = self.bind_arguments(action.method, action.line)?;
+                for (param_id, value) in &arguments {
+                    self.record_change(*param_id, action, value);
+                }
+                Emulator::push_method(self, method, arguments)?;
+            }
+            _ => {}
         }
+        Ok(())
     }
 
-    fn execute_action(&mut self, action: &Action) -> Result<()> {
-        if action.skip {
-            return Ok(());
+    // The End Loop, End If or End While that closes the block opened at the line. A loop that does
+    // not run or a test that does not hold resumes after it. A Run Method does not open a block, so a skipped
+    // call is passed over like any other line.
+    fn find_block_end(&self, method_id: Uuid, begin: usize) -> Result<usize> {
+        let instr_at = |line| {
+            self.saved_app
+                .instruction(method_id, line)
+                .ok_or(EmulatorError::UnknownInstruction(method_id, line))
+        };
+        let opener = &instr_at(begin)?.command;
+        let closer = match opener {
+            Command::BeginLoop { .. } => Command::EndLoop,
+            Command::IfThen { .. } => Command::EndIf,
+            Command::WhileLoop { .. } => Command::EndWhile,
+            _ => return Err(EmulatorError::UnmatchedBlock(method_id, begin)),
+        };
+        let open = std::mem::discriminant(opener);
+        let close = std::mem::discriminant(&closer);
+        let count = self
+            .saved_app
+            .instruction_count(method_id)
+            .ok_or(EmulatorError::UnknownMethod(method_id))?;
+        let mut depth = 0;
+        for line in begin + 1..count {
+            let instr = instr_at(line)?;
+            if instr.is_comment {
+                continue;
+            }
+            let kind = std::mem::discriminant(&instr.command);
+            if kind == open {
+                depth += 1;
+            } else if kind == close {
+                if depth == 0 {
+                    return Ok(line);
+                }
+                depth -= 1;
+            }
         }
+        Err(EmulatorError::UnmatchedBlock(method_id, begin))
+    }
 
-        self.machine.execute(&action.execute)?;
+    fn open_block(&mut self, kind: BlockKind) {
+        self.stack_blocks.push(BlockFrame {
+            depth: self.stack_methods.len(),
+            kind,
+        });
+    }
+
+    // The block closed must be the innermost one the current method is running in
+    fn close_block(&mut self, kind: BlockKind, action: &Action) -> Result<()> {
+        let depth = self.stack_methods.len();
+        match self.stack_blocks.last() {
+            Some(f) if f.depth == depth && f.kind == kind => {
+                self.stack_blocks.pop();
+                Ok(())
+            }
+            _ => Err(EmulatorError::UnmatchedBlock(action.method, action.line)),
+        }
+    }
+
+    // The While Loop that opens the block closed at the line
+    fn find_block_begin(&self, method_id: Uuid, end: usize) -> Result<usize> {
+        let mut depth = 0;
+        for line in (0..end).rev() {
+            let instr = self
+                .saved_app
+                .instruction(method_id, line)
+                .ok_or(EmulatorError::UnknownInstruction(method_id, line))?;
+            if instr.is_comment {
+                continue;
+            }
+            match instr.command {
+                Command::EndWhile => depth += 1,
+                Command::WhileLoop { .. } if depth == 0 => return Ok(line),
+                Command::WhileLoop { .. } => depth -= 1,
+                _ => {}
+            }
+        }
+        Err(EmulatorError::UnmatchedBlock(method_id, end))
+    }
+
+    fn jump(&mut self, line: usize) -> Result<()> {
+        let current = self
+            .stack_instructions
+            .last_mut()
+            .ok_or(EmulatorError::EmptyStack)?;
+        *current = line;
+        Ok(())
+    }
+
+    // Numbers are stored with the type the variable already has
+    fn set_number(&mut self, uuid: Uuid, number: f64, action: &Action) -> Result<()> {
+        let variable = self
+            .get_current_scope()?
+            .variable(uuid)
+            .ok_or(EmulatorError::UnknownVariable(uuid))?;
+        let value = match variable.value() {
+            VariableValue::Float(_) => VariableValue::Float(number),
+            VariableValue::Int(_) => VariableValue::Int(whole_number(number, action)?),
+            VariableValue::Seconds(_) => VariableValue::Seconds(whole_number(number, action)?),
+            value => {
+                return Err(EmulatorError::UnexpectedType {
+                    expected: VariableType::Float,
+                    found: value.variable_type(),
+                    method: action.method,
+                    line: action.line,
+                })
+            }
+        };
+        self.set_variable(uuid, value, action)
+    }
+
+    fn set_string(&mut self, uuid: Uuid, string: &str, action: &Action) -> Result<()> {
+        let variable = self
+            .get_current_scope()?
+            .variable(uuid)
+            .ok_or(EmulatorError::UnknownVariable(uuid))?;
+        match variable.value() {
+            VariableValue::String(_) => {
+                self.set_variable(uuid, VariableValue::String(string.to_string()), action)
+            }
+            value => Err(EmulatorError::UnexpectedType {
+                expected: VariableType::String,
+                found: value.variable_type(),
+                method: action.method,
+                line: action.line,
+            }),
+        }
+    }
+
+    // A variable is written where it is visible, in the same order as `Scope` looks it up
+    fn set_variable(&mut self, uuid: Uuid, value: VariableValue, action: &Action) -> Result<()> {
+        self.record_change(uuid, action, &value);
+        let method_id = self.get_current_method()?;
+        let locals = self
+            .local_variables
+            .get_mut(&method_id)
+            .ok_or(EmulatorError::UnknownMethod(method_id))?;
+        let params = self
+            .stack_params
+            .last_mut()
+            .ok_or(EmulatorError::EmptyStack)?;
+        let variable = if locals.contains_key(&uuid) {
+            locals.get_mut(&uuid)
+        } else if params.contains_key(&uuid) {
+            params.get_mut(&uuid)
+        } else {
+            self.global_variables.get_mut(&uuid)
+        };
+        variable
+            .ok_or(EmulatorError::UnknownVariable(uuid))?
+            .set_value(value);
         Ok(())
     }
 
+    fn record_change(&mut self, uuid: Uuid, action: &Action, value: &VariableValue) {
+        if self.record_history {
+            self.history.entry(uuid).or_default().push(ValueChange {
+                method: Some(action.method),
+                line: Some(action.line),
+                value: value.clone(),
+            });
+        }
+    }
+
+    fn saved_value(&self, uuid: Uuid) -> Option<&'a VariableValue> {
+        let saved_app = self.saved_app;
+        let variable = saved_app.global_variables().get(&uuid).or_else(|| {
+            saved_app.ids_methods().into_iter().find_map(|&m| {
+                saved_app
+                    .local_variables_of_method(m)
+                    .and_then(|l| l.get(&uuid))
+                    .or_else(|| saved_app.parameters_of_method(m).and_then(|p| p.get(&uuid)))
+            })
+        })?;
+        Some(variable.value())
+    }
+
+    // Parameter values are resolved in the caller's scope, so a parameter passed on from the
+    // caller's own parameters carries the value it was called with
+    fn bind_arguments(&self, method_id: Uuid, line: usize) -> Result<Vec<(Uuid, VariableValue)>> {
+        let instr = self
+            .saved_app
+            .instruction(method_id, line)
+            .ok_or(EmulatorError::UnknownInstruction(method_id, line))?;
+        let scope = self.get_current_scope()?;
+        match &instr.command {
+            Command::RunMethod { parameters, .. } => parameters
+                .iter()
+                .map(|p| Ok((p.id, scope.resolve(&p.value)?.clone())))
+                .collect(),
+            _ => Ok(Vec::new()),
+        }
+    }
+
     fn get_current_instruction(&self) -> Result<usize> {
         self.stack_instructions
             .last()
@@ -192,18 +1068,6 @@ impl<'a, M: Machine> Emulator<'a, M> {
             .ok_or(EmulatorError::EmptyStack)
     }
 
-    fn get_current_layout_position(&self, position_uuid: Uuid) -> Result<&'a String> {
-        let uuid = self.get_current_layout()?;
-        let layout = self
-            .layouts
-            .get(&uuid)
-            .ok_or(EmulatorError::UnknownLayout(uuid))?;
-        let pos = layout
-            .position(position_uuid)
-            .ok_or(EmulatorError::UnknownLayoutPosition(position_uuid))?;
-        Ok(pos)
-    }
-
     fn get_current_method(&self) -> Result<Uuid> {
         self.stack_methods
             .last()
@@ -211,35 +1075,14 @@ impl<'a, M: Machine> Emulator<'a, M> {
             .ok_or(EmulatorError::EmptyStack)
     }
 
-    fn get_instruction_value_float(&self, inst: &'a InstructionValue) -> Result<f64> {
-        if inst.variable.is_some() {
-            panic!("Can't deal with variable in InstructionValue {:?}", inst)
-        }
-
-        match inst.direct {
-            VariableValue::Float(f) => Ok(f),
-            _ => Err(EmulatorError::UnexpectedType),
-        }
-    }
-
-    fn get_position_positionhead(&self, pos: &'a PositionHead) -> Result<&'a String> {
-        match pos.deck_parameter {
-            Some(uuid) => Ok(self.get_current_layout_position(uuid)?),
-            None => panic!(
-                "Did not expect InstructionValue for {:?}",
-                pos.deck_location
-            ),
-        }
-    }
-
-    fn get_position_loadeject_tip_head(&self, pos: &'a LoadEjectTipsHead) -> Result<&'a String> {
-        match pos.deck_parameter {
-            Some(uuid) => Ok(self.get_current_layout_position(uuid)?),
-            None => panic!(
-                "Did not expect InstructionValue for {:?}",
-                pos.deck_location
-            ),
-        }
+    fn get_current_scope(&self) -> Result<Scope<'_>> {
+        let method_id = self.get_current_method()?;
+        let locals = self
+            .local_variables
+            .get(&method_id)
+            .ok_or(EmulatorError::UnknownMethod(method_id))?;
+        let params = self.stack_params.last().ok_or(EmulatorError::EmptyStack)?;
+        Ok(Scope::new(locals, params, &self.global_variables))
     }
 
     fn try_finish_method(&mut self) -> Result<bool> {
@@ -261,55 +1104,423 @@ impl<'a, M: Machine> Emulator<'a, M> {
     }
 
     fn pop_method(&mut self) -> Result<()> {
-        self.stack_methods.pop().ok_or(EmulatorError::EmptyStack)?;
+        let method_id = self.stack_methods.pop().ok_or(EmulatorError::EmptyStack)?;
+        self.method_trace.push(MethodEvent::Exit(method_id));
         self.stack_instructions
             .pop()
             .ok_or(EmulatorError::EmptyStack)?;
         self.stack_params.pop().ok_or(EmulatorError::EmptyStack)?;
         self.stack_layout.pop().ok_or(EmulatorError::EmptyStack)?;
+        // A loop left open by the returning method must not be closed by its caller
+        let depth = self.stack_methods.len();
+        self.stack_loops.retain(|f| f.depth <= depth);
+        self.stack_blocks.retain(|f| f.depth <= depth);
         Ok(())
     }
 }
 
-#[derive(Debug)]
-pub struct Action<'a> {
-    pub method: Uuid,
-    pub line: usize,
-    pub skip: bool,
-    pub execute: Execute<'a>,
+// Fields with a comma, quote or line break are quoted, with quotes doubled
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
 }
 
-impl<'a> serde::Serialize for Action<'a> {
-    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        let mut state = serializer.serialize_struct("Action", 4)?;
-        state.serialize_field("method", &self.method.to_string())?;
-        state.serialize_field("line", &self.line)?;
-        state.serialize_field("skip", &self.skip)?;
-        state.serialize_field("execute", &self.execute)?;
-        state.end()
+// Integer and seconds variables hold whole numbers from 0 up
+fn whole_number(number: f64, action: &Action) -> Result<u32> {
+    if number.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&number) {
+        Ok(number as u32)
+    } else {
+        Err(EmulatorError::NotAWholeNumber {
+            value: number,
+            method: action.method,
+            line: action.line,
+        })
     }
 }
 
-#[derive(Debug)]
-pub enum EmulatorError {
-    EmptyStack,
-    MachineError(MachineError),
-    UnexpectedType,
-    UnknownLayout(Uuid),
-    UnknownLayoutPosition(Uuid),
-    UnknownMethod(Uuid),
-    UnknownInstruction(Uuid, usize),
+fn loop_continues(value: f64, to: f64, step: f64) -> bool {
+    if step < 0.0 {
+        value >= to
+    } else {
+        value <= to
+    }
 }
 
-impl std::fmt::Display for EmulatorError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::EmptyStack => write!(f, "emulator stack is unexpectendly empty"),
-            Self::MachineError(m) => m.fmt(f),
-            Self::UnexpectedType => write!(f, "unexpected variable type"),
+/// Check every method of the application without running it: blocks that are not closed, or closed
+/// by the wrong end instruction, and instructions that refer to variables, layout positions or
+/// methods the application does not have. Comments are not checked.
+pub fn validate(saved_app: &SavedApplication) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    for method_id in saved_app.methods_in_order() {
+        let mut issue = |line, description: String| {
+            issues.push(ValidationIssue {
+                method: method_id,
+                line,
+                description,
+            })
+        };
+        let (locals, params, layout_id) = match (
+            saved_app.local_variables_of_method(method_id),
+            saved_app.parameters_of_method(method_id),
+            saved_app.layout_of_method(method_id),
+        ) {
+            (Some(locals), Some(params), Some(layout_id)) => (locals, params, layout_id),
+            _ => continue,
+        };
+        let scope = Scope::new(locals, params, saved_app.global_variables());
+        let layout = saved_app.layouts().get(&layout_id);
+        if layout.is_none() {
+            issue(0, format!("Layout {} does not exist", layout_id));
+        }
+
+        let mut blocks: Vec<(BlockKind, usize)> = Vec::new();
+        let count = saved_app.instruction_count(method_id).unwrap_or(0);
+        for line in 0..count {
+            let instr = match saved_app.instruction(method_id, line) {
+                Some(i) if !i.is_comment => i,
+                _ => continue,
+            };
+            let closes = match instr.command {
+                Command::BeginLoop { .. } => {
+                    blocks.push((BlockKind::Loop, line));
+                    None
+                }
+                Command::IfThen { .. } => {
+                    blocks.push((BlockKind::If, line));
+                    None
+                }
+                Command::WhileLoop { .. } => {
+                    blocks.push((BlockKind::While, line));
+                    None
+                }
+                Command::EndIf => Some(BlockKind::If),
+                Command::EndLoop => Some(BlockKind::Loop),
+                Command::EndWhile => Some(BlockKind::While),
+                _ => None,
+            };
+            if let Some(kind) = closes {
+                match blocks.pop() {
+                    Some((open, _)) if open == kind => {}
+                    Some((open, begin)) => issue(
+                        line,
+                        format!(
+                            "{:?} block opened on line {} is closed as {:?}",
+                            open, begin, kind
+                        ),
+                    ),
+                    None => issue(line, format!("{:?} block is closed but never opened", kind)),
+                }
+            }
+
+            for variable in instr.command.variables() {
+                if scope.variable(variable).is_none() {
+                    issue(line, format!("Variable {} does not exist", variable));
+                }
+            }
+            if let Some(layout) = layout {
+                for position in instr.command.deck_positions() {
+                    if layout.position(position).is_none() {
+                        issue(
+                            line,
+                            format!("Position {} is not in layout {}", position, layout_id),
+                        );
+                    }
+                }
+            }
+            if let Command::RunMethod { method, .. } = instr.command {
+                if !saved_app.has_method(method) {
+                    issue(line, format!("Method {} does not exist", method));
+                }
+            }
+        }
+        for (open, begin) in blocks {
+            issue(begin, format!("{:?} block is never closed", open));
+        }
+    }
+    issues
+}
+
+// An active Begin Loop. The depth is the size of the method stack of the method that owns it.
+// Like Maestro, the bounds and step are evaluated once when the loop starts, so a loop body that
+// changes the variables they came from does not change how many times the loop runs. Only the
+// index variable is written on every iteration.
+#[derive(Clone)]
+struct LoopFrame {
+    depth: usize,
+    begin: usize,
+    index: Option<Uuid>,
+    value: f64,
+    to: f64,
+    step: f64,
+}
+
+// A block the emulator is running in, opened by a loop that runs or a test that holds. The depth is
+// the size of the method stack of the method that owns it.
+#[derive(Clone)]
+struct BlockFrame {
+    depth: usize,
+    kind: BlockKind,
+}
+
+// The emulator before an action was executed, for `Emulator::step_back`. What is only ever added to
+// is kept as its length.
+struct UndoState<'a> {
+    machine: MachineSnapshot,
+    global_variables: HashMap<Uuid, Variable>,
+    local_variables: HashMap<Uuid, HashMap<Uuid, Variable>>,
+    stack_methods: Vec<Uuid>,
+    stack_instructions: Vec<usize>,
+    stack_params: Vec<HashMap<Uuid, Variable>>,
+    stack_layout: Vec<Uuid>,
+    stack_loops: Vec<LoopFrame>,
+    stack_blocks: Vec<BlockFrame>,
+    volume_leaks: usize,
+    volume_aspirated: f64,
+    volume_dispensed: f64,
+    history: HashMap<Uuid, usize>,
+    dialog: Option<&'a str>,
+    method_trace: usize,
+    expectation_failures: usize,
+    time_breakdown: BTreeMap<&'static str, f64>,
+    error: Option<String>,
+}
+
+// A check of the machine state registered with `Emulator::expect_at`
+struct Expectation<'a> {
+    method: Uuid,
+    line: usize,
+    predicate: Box<dyn Fn(&MachineState) -> bool + 'a>,
+}
+
+/// An expectation that did not hold when emulation reached its line
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpectationFailure {
+    pub method: Uuid,
+    pub line: usize,
+    /// Which expectation failed, counting from 0 in the order they were registered
+    pub expectation: usize,
+}
+
+#[derive(Debug)]
+pub struct Action<'a> {
+    pub method: Uuid,
+    pub line: usize,
+    pub skip: bool,
+    pub execute: Execute<'a>,
+}
+
+impl<'a> serde::Serialize for Action<'a> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("Action", 4)?;
+        state.serialize_field("method", &self.method.to_string())?;
+        state.serialize_field("line", &self.line)?;
+        state.serialize_field("skip", &self.skip)?;
+        state.serialize_field("execute", &self.execute)?;
+        state.end()
+    }
+}
+
+/// A problem `validate` found in an instruction
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    pub method: Uuid,
+    pub line: usize,
+    pub description: String,
+}
+
+/// A block of instructions that encloses others
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockKind {
+    If,
+    Loop,
+    While,
+}
+
+/// The result of stepping the emulator
+#[derive(Debug)]
+pub enum StepOutcome<'b, 'a> {
+    Action(&'b Action<'a>),
+    /// A dialog was shown, with its text. Emulation does not continue until the dialog is closed.
+    AwaitingDialog(&'a str),
+    Finished,
+}
+
+/// The pipetting done with one set of tips. Volumes are as the instructions give them, before any
+/// rounding by the machine.
+#[derive(Debug)]
+pub struct TipCycle<'b, 'a> {
+    pub load: &'b Action<'a>,
+    pub eject: Option<&'b Action<'a>>,
+    /// Aspirates and dispenses, in order
+    pub transfers: Vec<&'b Action<'a>>,
+    pub volume_aspirated: f64,
+    pub volume_dispensed: f64,
+}
+
+/// A method call starting or returning, as recorded by `Emulator::method_trace`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MethodEvent {
+    Enter(Uuid),
+    Exit(Uuid),
+}
+
+/// A tip load or eject, with whether tips are loaded afterwards
+#[derive(Debug, Clone, PartialEq)]
+pub struct TipEvent<'a> {
+    pub method: Uuid,
+    pub line: usize,
+    pub position: &'a str,
+    pub tips_loaded: bool,
+}
+
+/// A value a variable was given. The saved value of the variable has no method or line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueChange {
+    pub method: Option<Uuid>,
+    pub line: Option<usize>,
+    pub value: VariableValue,
+}
+
+/// Totals of an emulation run
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct RunSummary {
+    /// Instructions stepped through, including skipped ones
+    pub total_steps: usize,
+    pub executed: usize,
+    pub skipped: usize,
+    pub volume_aspirated: f64,
+    pub volume_dispensed: f64,
+    /// Number of tip loads and ejects
+    pub tip_events: usize,
+    pub volume_leaks: Vec<VolumeLeak>,
+    /// Emulated time of the run, in seconds. Only instructions that take a set time, like
+    /// shaking, are counted.
+    pub estimated_seconds: f64,
+    /// Seconds taken by each kind of instruction that took any time
+    pub time_breakdown: BTreeMap<String, f64>,
+    /// The error the run stopped at, if any
+    pub error: Option<String>,
+}
+
+/// Liquid left in the tips when they were ejected
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct VolumeLeak {
+    pub method: Uuid,
+    pub line: usize,
+    pub volume: f64,
+}
+
+/// Errors of the emulator. New variants may be added, so matches outside this crate need a
+/// wildcard arm.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum EmulatorError {
+    /// An operation whose result is a literal value rather than a variable
+    CannotAssignToLiteral {
+        method: Uuid,
+        line: usize,
+    },
+    EmptyStack,
+    InvalidComparison,
+    MachineError(MachineError),
+    /// A position given as a value, rather than as a position of the layout
+    MissingDeckParameter {
+        method: Uuid,
+        line: usize,
+    },
+    /// An operation saved without the operand it needs
+    MissingOperand {
+        method: Uuid,
+        line: usize,
+    },
+    /// A number that does not fit an integer or seconds variable, being negative or fractional
+    NotAWholeNumber {
+        value: f64,
+        method: Uuid,
+        line: usize,
+    },
+    NotOverridable(Uuid, usize),
+    Sink(std::io::Error),
+    UnexpectedType {
+        expected: VariableType,
+        found: VariableType,
+        method: Uuid,
+        line: usize,
+    },
+    UnknownLayout(Uuid),
+    UnknownLayoutPosition(Uuid),
+    UnknownMethod(Uuid),
+    UnknownInstruction(Uuid, usize),
+    UnknownVariable(Uuid),
+    UnmatchedBlock(Uuid, usize),
+    /// An instruction the emulator does not know how to run, by its designation
+    Unsupported {
+        designation: String,
+        method: Uuid,
+        line: usize,
+    },
+    /// A String Operation other than assignment, by the code Maestro saves it with
+    UnsupportedStringOperation {
+        code: u32,
+        method: Uuid,
+        line: usize,
+    },
+}
+
+impl std::fmt::Display for EmulatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CannotAssignToLiteral { method, line } => write!(
+                f,
+                "result is not a variable at instruction line {} of method {}",
+                line, method
+            ),
+            Self::EmptyStack => write!(f, "emulator stack is unexpectendly empty"),
+            Self::InvalidComparison => {
+                write!(f, "comparator can not be used with these values")
+            }
+            Self::MachineError(m) => m.fmt(f),
+            Self::MissingDeckParameter { method, line } => write!(
+                f,
+                "position is not a deck parameter at instruction line {} of method {}",
+                line, method
+            ),
+            Self::MissingOperand { method, line } => write!(
+                f,
+                "operand is missing at instruction line {} of method {}",
+                line, method
+            ),
+            Self::NotAWholeNumber {
+                value,
+                method,
+                line,
+            } => write!(
+                f,
+                "{} is not a whole number at instruction line {} of method {}",
+                value, line, method
+            ),
+            Self::NotOverridable(uuid, line) => write!(
+                f,
+                "instruction line {} of method {} has no value to override",
+                line, uuid
+            ),
+            Self::Sink(e) => write!(f, "could not write the trace: {}", e),
+            Self::UnexpectedType {
+                expected,
+                found,
+                method,
+                line,
+            } => write!(
+                f,
+                "expected {:?} but found {:?} at instruction line {} of method {}",
+                expected, found, line, method
+            ),
             Self::UnknownLayout(uuid) => write!(f, "unknown layout ({})", uuid),
             Self::UnknownLayoutPosition(uuid) => {
                 write!(f, "unknown layout position variable ({})", uuid)
@@ -320,6 +1531,26 @@ impl std::fmt::Display for EmulatorError {
                 line, uuid
             ),
             Self::UnknownMethod(uuid) => write!(f, "unknown method ({})", uuid),
+            Self::UnknownVariable(uuid) => write!(f, "unknown variable ({})", uuid),
+            Self::UnmatchedBlock(uuid, line) => write!(
+                f,
+                "instruction line {} of method {} has no matching block instruction",
+                line, uuid
+            ),
+            Self::Unsupported {
+                designation,
+                method,
+                line,
+            } => write!(
+                f,
+                "{} is not supported at instruction line {} of method {}",
+                designation, line, method
+            ),
+            Self::UnsupportedStringOperation { code, method, line } => write!(
+                f,
+                "string operation {} is not supported at instruction line {} of method {}",
+                code, line, method
+            ),
         }
     }
 }
@@ -327,13 +1558,24 @@ impl std::fmt::Display for EmulatorError {
 impl std::error::Error for EmulatorError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
+            Self::CannotAssignToLiteral { .. } => None,
             Self::EmptyStack => None,
+            Self::InvalidComparison => None,
             Self::MachineError(m) => Some(m),
-            Self::UnexpectedType => None,
+            Self::MissingDeckParameter { .. } => None,
+            Self::MissingOperand { .. } => None,
+            Self::NotAWholeNumber { .. } => None,
+            Self::NotOverridable(_, _) => None,
+            Self::Sink(e) => Some(e),
+            Self::UnexpectedType { .. } => None,
             Self::UnknownLayout(_) => None,
             Self::UnknownLayoutPosition(_) => None,
             Self::UnknownInstruction(_, _) => None,
             Self::UnknownMethod(_) => None,
+            Self::UnknownVariable(_) => None,
+            Self::UnmatchedBlock(_, _) => None,
+            Self::Unsupported { .. } => None,
+            Self::UnsupportedStringOperation { .. } => None,
         }
     }
 }
@@ -344,11 +1586,49 @@ impl From<MachineError> for EmulatorError {
     }
 }
 
+// Lowering does not know where the instruction is, so its errors are given the location here
+fn locate_error(error: EmulatorError, method: Uuid, line: usize) -> EmulatorError {
+    match error {
+        EmulatorError::CannotAssignToLiteral { .. } => {
+            EmulatorError::CannotAssignToLiteral { method, line }
+        }
+        EmulatorError::MissingDeckParameter { .. } => {
+            EmulatorError::MissingDeckParameter { method, line }
+        }
+        EmulatorError::MissingOperand { .. } => EmulatorError::MissingOperand { method, line },
+        EmulatorError::Unsupported { designation, .. } => EmulatorError::Unsupported {
+            designation,
+            method,
+            line,
+        },
+        EmulatorError::UnsupportedStringOperation { code, .. } => {
+            EmulatorError::UnsupportedStringOperation { code, method, line }
+        }
+        EmulatorError::UnexpectedType {
+            expected, found, ..
+        } => EmulatorError::UnexpectedType {
+            expected,
+            found,
+            method,
+            line,
+        },
+        e => e,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use maestro_ngs_application::Loader;
 
+    /// A machine that ejects tips with liquid left in them
+    fn leaky_machine() -> ScicloneG3 {
+        ScicloneG3::with_config(ScicloneConfig {
+            max_eject_volume: f64::INFINITY,
+            ..ScicloneConfig::default()
+        })
+    }
+
     fn load_empty_app() -> String {
         let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         d.push("resources/test/Application_Empty.eap");
@@ -363,9 +1643,61 @@ mod tests {
         std::fs::read_to_string(d).unwrap()
     }
 
+    fn load_volume_leak_app() -> String {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Volume_Leak.eap");
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    fn load_looped_counter_app() -> String {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Looped_Counter.eap");
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    fn load_unclosed_loop_app() -> String {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Unclosed_Loop.eap");
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    fn load_mutated_loop_bound_app() -> String {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Mutated_Loop_Bound.eap");
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    fn load_complex_app() -> String {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Application_Complex.eap");
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    fn load_boolean_if_app() -> String {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Boolean_If.eap");
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    fn load_nested_parameters_app() -> String {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Nested_Parameters.eap");
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
     #[test]
     fn emulate_empty_app() {
-        let app = Loader::new(&load_empty_app()).build_application();
+        let app = Loader::new(&load_empty_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
         let mut emu = ScicloneG3Emulator::new(&app).unwrap();
         let uuid = "3AC47C04-DCCE-4036-8F9F-6AD7D530E220".parse().unwrap();
         assert_eq!(emu.stack_methods.len(), 1);
@@ -383,7 +1715,10 @@ mod tests {
 
     #[test]
     fn emulate_pipette_and_mix_app() {
-        let app = Loader::new(&load_pipette_and_mix_app()).build_application();
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
         let mut emu = ScicloneG3Emulator::new(&app).unwrap();
 
         // Load tips
@@ -418,4 +1753,1655 @@ mod tests {
         assert!(step.is_none());
         assert!(emu.done());
     }
+
+    #[test]
+    fn run_pipette_and_mix_to_end() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let actions = emu.run_to_end().unwrap();
+        assert_eq!(
+            actions.len(),
+            app.instruction_count(app.start_method()).unwrap()
+        );
+        assert!(matches!(
+            actions.last().unwrap().execute,
+            Execute::EjectTips { position: "D5" }
+        ));
+        assert!(emu.done());
+    }
+
+    #[test]
+    fn expectations_on_pipette_and_mix() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let main = app.start_method();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        // Tips are loaded on line 0, so they are loaded when line 1 is reached but not line 0
+        emu.expect_at(main, 1, |state| state.tips_loaded).unwrap();
+        emu.expect_at(main, 0, |state| state.tips_loaded).unwrap();
+        assert!(matches!(
+            emu.expect_at(main, 100, |_| true),
+            Err(EmulatorError::UnknownInstruction(_, 100))
+        ));
+        emu.run_to_end().unwrap();
+
+        assert_eq!(
+            emu.expectation_failures(),
+            &[ExpectationFailure {
+                method: main,
+                line: 0,
+                expectation: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn final_state_of_pipette_and_mix() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.run_to_end().unwrap();
+
+        let state: serde_json::Value = serde_json::from_str(&emu.final_state_json()).unwrap();
+        assert_eq!(state["tips_loaded"], false);
+        assert_eq!(state["tip_volume"], 0.0);
+        assert_eq!(state["deck_location"], "D5");
+        assert!(state.get("location_volumes").is_none());
+    }
+
+    #[test]
+    fn step_back_to_first_action() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.record_undo(true);
+        emu.step_back().unwrap();
+        let first = emu.next().unwrap().unwrap().execute.position().unwrap();
+        let second = emu.next().unwrap().unwrap().execute.position().unwrap();
+        assert_ne!(first, second);
+
+        emu.step_back().unwrap();
+        assert_eq!(emu.machine.get_deck_location().unwrap(), first);
+        assert!(emu.machine.get_tips_loaded());
+        assert_eq!(emu.run_summary().total_steps, 1);
+        let again = emu.next().unwrap().unwrap();
+        assert_eq!((again.line, again.execute.position()), (1, Some(second)));
+    }
+
+    #[test]
+    fn undo_is_not_recorded_by_default() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.next().unwrap();
+        assert!(emu.undo.is_empty());
+        emu.step_back().unwrap();
+        assert_eq!(emu.run_summary().total_steps, 1);
+
+        // Only what was executed while recording is undone
+        emu.record_undo(true);
+        emu.next().unwrap();
+        emu.step_back().unwrap();
+        emu.step_back().unwrap();
+        assert_eq!(emu.run_summary().total_steps, 1);
+
+        emu.next().unwrap();
+        emu.record_undo(false);
+        assert!(emu.undo.is_empty());
+    }
+
+    #[test]
+    fn step_back_keeps_machine_setup() {
+        // Main calls A, B and C, and C aspirates from C4
+        let app = Loader::new(&load_nested_parameters_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut machine = leaky_machine();
+        machine.set_location_volume("C4", 500.0);
+        let mut emu = Emulator::with_machine(&app, machine).unwrap();
+        emu.record_undo(true);
+        let mut states = vec![emu.machine.state()];
+        let mut methods = vec![emu.stack_methods.clone()];
+        while emu.next().unwrap().is_some() {
+            states.push(emu.machine.state());
+            methods.push(emu.stack_methods.clone());
+        }
+        let trace = emu.method_trace().len();
+
+        // Every method has returned once the run is done, so only the machine is as it was
+        assert_eq!(emu.machine.state(), states.pop().unwrap());
+        assert!(emu.stack_methods.is_empty());
+        methods.pop();
+        while let Some(state) = states.pop() {
+            emu.step_back().unwrap();
+            assert_eq!(emu.machine.state(), state);
+            assert_eq!(emu.stack_methods, methods.pop().unwrap());
+        }
+        assert_eq!(emu.run_summary().total_steps, 0);
+        assert_eq!(emu.method_trace().len(), 1);
+
+        // The tips are ejected with liquid, which only the machine's own setting allows
+        emu.run_to_end().unwrap();
+        assert_eq!(emu.method_trace().len(), trace);
+        emu.reset().unwrap();
+        assert_eq!(emu.machine.get_location_volume("C4"), Some(500.0));
+        emu.run_to_end().unwrap();
+    }
+
+    #[test]
+    fn observer_sees_executed_actions() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let observed = std::cell::RefCell::new(Vec::new());
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.set_observer(|action| observed.borrow_mut().push((action.method, action.line)));
+        let executed: Vec<_> = emu
+            .run_to_end()
+            .unwrap()
+            .iter()
+            .filter(|a| !a.skip)
+            .map(|a| (a.method, a.line))
+            .collect();
+        assert_eq!(observed.borrow().len(), emu.run_summary().executed);
+        assert_eq!(*observed.borrow(), executed);
+    }
+
+    #[test]
+    fn observer_skips_comments() {
+        use maestro_ngs_application::{Instruction, SavedApplicationBuilder};
+
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let unknown = || {
+            Instruction::new(Command::Unknown {
+                designation: "Levitate Plate".to_string(),
+            })
+        };
+        let mut disabled = unknown();
+        disabled.is_comment = true;
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(main, "Main", layout_id, vec![disabled, unknown()])
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let observed = std::cell::RefCell::new(Vec::new());
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.set_observer(|action| observed.borrow_mut().push(action.line));
+        emu.run_to_end().unwrap();
+        assert_eq!(*observed.borrow(), vec![1]);
+    }
+
+    #[test]
+    fn only_comments_are_skipped() {
+        use maestro_ngs_application::{Instruction, SavedApplicationBuilder};
+
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let mut disabled = Instruction::new(Command::Unknown {
+            designation: "Levitate Plate".to_string(),
+        });
+        disabled.is_comment = true;
+        let rem = Instruction::new(Command::REM {
+            comment: "Not flagged as a comment".to_string(),
+        });
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(main, "Main", layout_id, vec![disabled, rem])
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let skips: Vec<_> = emu.run_to_end().unwrap().iter().map(|a| a.skip).collect();
+        assert_eq!(skips, vec![true, false]);
+    }
+
+    #[test]
+    fn reset_reruns_same_actions() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let run = |emu: &mut ScicloneG3Emulator| {
+            let mut actions = Vec::new();
+            while let Some(action) = emu.next().unwrap() {
+                actions.push(format!("{} {:?}", action.line, action.execute));
+            }
+            actions
+        };
+
+        let first = run(&mut emu);
+        assert!(emu.done());
+        emu.reset().unwrap();
+        assert!(!emu.done());
+        assert!(!emu.machine.get_tips_loaded());
+        assert_eq!(run(&mut emu), first);
+        assert_eq!(first.len(), 5);
+    }
+
+    #[test]
+    fn record_pipette_and_mix_app() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = Emulator::<RecordingMachine>::new(&app).unwrap();
+        while emu.next().unwrap().is_some() {}
+
+        assert_eq!(
+            emu.machine().executed,
+            vec![
+                OwnedExecute::LoadTips {
+                    position: "C3".to_string(),
+                    tip_size: None
+                },
+                OwnedExecute::Aspirate {
+                    position: "C4".to_string(),
+                    volume: 100.0,
+                    air_gap: None
+                },
+                OwnedExecute::Dispense {
+                    position: "B4".to_string(),
+                    volume: None,
+                    // A well of the generic plate is 6.85 mm across and 11.12 mm deep
+                    capacity: Some(std::f64::consts::PI * (6.85f64 / 2.0).powi(2) * 11.12)
+                },
+                OwnedExecute::Mix {
+                    position: "B4".to_string()
+                },
+                OwnedExecute::EjectTips {
+                    position: "D5".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn emulate_nested_parameters() {
+        // Main calls A(x = 42), A calls B(x_a), B calls C(x_b) and C aspirates x_c
+        let app = Loader::new(&load_nested_parameters_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = Emulator::with_machine(&app, leaky_machine()).unwrap();
+
+        for _ in 0..3 {
+            let step = emu.next().unwrap().unwrap();
+            assert!(matches!(step.execute, Execute::RunMethod { .. }));
+        }
+        assert_eq!(emu.stack_methods.len(), 4);
+        let x_c = "F0E3B7A1-65D2-4C98-A07F-C4B19E2D5836".parse().unwrap();
+        assert_eq!(
+            emu.stack_params.last().unwrap().get(&x_c).unwrap().value(),
+            &VariableValue::Float(42.0)
+        );
+
+        // Load tips and aspirate x_c
+        emu.next().unwrap();
+        emu.next().unwrap();
+        assert_eq!(emu.machine.get_tip_volume(), 42.0);
+
+        // Eject tips, then all methods return
+        emu.next().unwrap();
+        assert!(emu.next().unwrap().is_none());
+        assert!(emu.done());
+    }
+
+    #[test]
+    fn run_method_binds_parameter() {
+        use maestro_ngs_application::{
+            Instruction, InstructionValue, LoadEjectTipsHead, Parameter, PositionHead,
+            SavedApplicationBuilder, Variable,
+        };
+
+        // Main: Load Tips, Run Sub(volume = 25), then a comment. Sub aspirates volume.
+        let main = Uuid::from_u128(1);
+        let sub = Uuid::from_u128(2);
+        let layout_id = Uuid::from_u128(3);
+        let (c3, c4) = (Uuid::from_u128(4), Uuid::from_u128(5));
+        let volume = Uuid::from_u128(6);
+        let direct = |value| InstructionValue {
+            direct: value,
+            variable: None,
+        };
+        let empty = || direct(VariableValue::String(String::new()));
+        let mut layout = Layout::new(layout_id, "MainLayout");
+        layout.add_position(c3, "C3");
+        layout.add_position(c4, "C4");
+        let main_instructions = vec![
+            Instruction::new(Command::LoadTips {
+                load_eject_tips_head: LoadEjectTipsHead {
+                    deck_parameter: Some(c3),
+                    deck_location: empty(),
+                },
+            }),
+            Instruction::new(Command::RunMethod {
+                method: sub,
+                parameters: vec![Parameter {
+                    id: volume,
+                    value: direct(VariableValue::Float(25.0)),
+                }],
+            }),
+            Instruction::new(Command::REM {
+                comment: "after".to_string(),
+            }),
+        ];
+        let sub_instructions = vec![Instruction::new(Command::Aspirate {
+            position_head: PositionHead {
+                deck_parameter: Some(c4),
+                deck_location: empty(),
+                z_offset: direct(VariableValue::Float(0.0)),
+            },
+            volume: InstructionValue {
+                direct: VariableValue::Float(0.0),
+                variable: Some(volume),
+            },
+            leading_air_gap: None,
+            trailing_air_gap: None,
+        })];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(layout)
+            .add_method(main, "Main", layout_id, main_instructions)
+            .add_method_with_parameters(
+                sub,
+                "Sub",
+                layout_id,
+                vec![Variable::new(volume, "volume", VariableValue::Float(0.0))],
+                sub_instructions,
+            )
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let mut steps = Vec::new();
+        while let Some(action) = emu.next().unwrap() {
+            steps.push((action.method, action.line));
+        }
+        assert_eq!(steps, vec![(main, 0), (main, 1), (sub, 0), (main, 2)]);
+        assert_eq!(emu.machine.get_tip_volume(), 25.0);
+    }
+
+    #[test]
+    fn trace_nested_calls() {
+        let app = Loader::new(&load_nested_parameters_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let main: Uuid = "A7C3E512-0D9F-4B6A-8E21-5F34C7B9D0E6".parse().unwrap();
+        let a: Uuid = "1E9D4C27-B83F-4A50-96E7-D2C05F1A8B34".parse().unwrap();
+        let b: Uuid = "6F0A2D85-C71E-4B39-A4D6-08E5B3C9F127".parse().unwrap();
+        let c: Uuid = "C25B7E09-4D1A-4F86-B3E2-7A90D6F41C58".parse().unwrap();
+        let mut emu = Emulator::with_machine(&app, leaky_machine()).unwrap();
+        assert_eq!(emu.method_trace(), &[MethodEvent::Enter(main)]);
+        while emu.next().unwrap().is_some() {}
+
+        use MethodEvent::{Enter, Exit};
+        assert_eq!(
+            emu.method_trace(),
+            &[
+                Enter(main),
+                Enter(a),
+                Enter(b),
+                Enter(c),
+                Exit(c),
+                Exit(b),
+                Exit(a),
+                Exit(main)
+            ]
+        );
+    }
+
+    #[test]
+    fn emulate_volume_leak() {
+        // The first tips dispense 80 of 100 uL, the second tips dispense all
+        let app = Loader::new(&load_volume_leak_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = Emulator::with_machine(&app, leaky_machine()).unwrap();
+        while emu.next().unwrap().is_some() {}
+
+        let leaks = emu.volume_leaks();
+        assert_eq!(leaks.len(), 1);
+        assert_eq!(leaks[0].method, app.start_method());
+        assert_eq!(leaks[0].line, 3);
+        assert_eq!(leaks[0].volume, 20.0);
+    }
+
+    #[test]
+    fn override_aspirate_volume() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let main = app.start_method();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+
+        assert!(matches!(
+            emu.override_literal(main, 0, VariableValue::Float(150.0)),
+            Err(EmulatorError::NotOverridable(_, 0))
+        ));
+        assert!(matches!(
+            emu.override_literal(main, 1, VariableValue::String("150".to_string())),
+            Err(EmulatorError::UnexpectedType {
+                expected: VariableType::Float,
+                found: VariableType::String,
+                line: 1,
+                ..
+            })
+        ));
+        emu.override_literal(main, 1, VariableValue::Float(150.0))
+            .unwrap();
+
+        // Load tips, then aspirate the overridden volume
+        emu.next().unwrap();
+        emu.next().unwrap();
+        assert_eq!(emu.machine.get_tip_volume(), 150.0);
+    }
+
+    #[test]
+    fn tip_events_of_pipette_and_mix() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let main = app.start_method();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        while emu.next().unwrap().is_some() {}
+
+        assert_eq!(
+            emu.tip_events(),
+            vec![
+                TipEvent {
+                    method: main,
+                    line: 0,
+                    position: "C3",
+                    tips_loaded: true,
+                },
+                TipEvent {
+                    method: main,
+                    line: 4,
+                    position: "D5",
+                    tips_loaded: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tip_cycles_of_pipette_and_mix() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        while emu.next().unwrap().is_some() {}
+
+        let cycles = emu.tip_cycles();
+        assert_eq!(cycles.len(), 1);
+        assert_eq!(cycles[0].load.line, 0);
+        assert_eq!(cycles[0].eject.unwrap().line, 4);
+        let lines: Vec<usize> = cycles[0].transfers.iter().map(|a| a.line).collect();
+        assert_eq!(lines, vec![1, 2]);
+        assert_eq!(cycles[0].volume_aspirated, 100.0);
+        assert_eq!(cycles[0].volume_dispensed, 100.0);
+    }
+
+    #[test]
+    fn eject_full_tips_is_an_error() {
+        // The first tips still hold 20 uL when they are ejected on line 3
+        let app = Loader::new(&load_volume_leak_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        for _ in 0..3 {
+            emu.next().unwrap();
+        }
+
+        assert!(matches!(
+            emu.next(),
+            Err(EmulatorError::MachineError(MachineError::EjectWithVolume(volume))) if volume == 20.0
+        ));
+
+        // Over a threshold that lets some liquid be ejected
+        let machine = ScicloneG3::with_config(ScicloneConfig {
+            max_eject_volume: 10.0,
+            ..ScicloneConfig::default()
+        });
+        let mut emu = Emulator::with_machine(&app, machine).unwrap();
+        for _ in 0..3 {
+            emu.next().unwrap();
+        }
+
+        assert!(matches!(
+            emu.next(),
+            Err(EmulatorError::MachineError(MachineError::EjectWithVolume(volume))) if volume == 20.0
+        ));
+    }
+
+    #[test]
+    fn tip_cycles_of_volume_leak() {
+        // Two cycles, the first leaves 20 uL in its tips
+        let app = Loader::new(&load_volume_leak_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = Emulator::with_machine(&app, leaky_machine()).unwrap();
+        while emu.next().unwrap().is_some() {}
+
+        let cycles = emu.tip_cycles();
+        assert_eq!(cycles.len(), 2);
+        assert_eq!(
+            cycles[0].volume_aspirated - cycles[0].volume_dispensed,
+            20.0
+        );
+        assert_eq!(cycles[1].volume_aspirated, cycles[1].volume_dispensed);
+    }
+
+    #[test]
+    fn explain_nested_parameter() {
+        // Main calls A(x = 42), A calls B(x_a), B calls C(x_b)
+        let app = Loader::new(&load_nested_parameters_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let x_c = "F0E3B7A1-65D2-4C98-A07F-C4B19E2D5836".parse().unwrap();
+        let b = "6F0A2D85-C71E-4B39-A4D6-08E5B3C9F127".parse().unwrap();
+        let mut emu = Emulator::with_machine(&app, leaky_machine()).unwrap();
+        emu.record_variable_history(true);
+        while emu.next().unwrap().is_some() {}
+
+        assert_eq!(
+            emu.explain_variable(x_c),
+            vec![
+                ValueChange {
+                    method: None,
+                    line: None,
+                    value: VariableValue::Float(0.0),
+                },
+                ValueChange {
+                    method: Some(b),
+                    line: Some(0),
+                    value: VariableValue::Float(42.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn explain_looped_counter() {
+        // g_Count = g_Count + 2 in a loop over i = 1 to 3
+        let app = Loader::new(&load_looped_counter_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let main = app.start_method();
+        let count = "4C9E2B71-A6D3-4E08-B5F2-7A1D3C8E9B60".parse().unwrap();
+        let index = "D8A3F6C2-1B7E-4F94-9C05-3E2A6B7D1F48".parse().unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.record_variable_history(true);
+        while emu.next().unwrap().is_some() {}
+
+        let change = |line: Option<usize>, value| ValueChange {
+            method: line.map(|_| main),
+            line,
+            value: VariableValue::Float(value),
+        };
+        assert_eq!(
+            emu.explain_variable(count),
+            vec![
+                change(None, 0.0),
+                change(Some(1), 2.0),
+                change(Some(1), 4.0),
+                change(Some(1), 6.0),
+            ]
+        );
+        assert_eq!(
+            emu.explain_variable(index),
+            vec![
+                change(None, 0.0),
+                change(Some(0), 1.0),
+                change(Some(2), 2.0),
+                change(Some(2), 3.0),
+            ]
+        );
+        assert_eq!(
+            emu.global_variables.get(&count).unwrap().value(),
+            &VariableValue::Float(6.0)
+        );
+    }
+
+    #[test]
+    fn variables_csv_after_run() {
+        let app = Loader::new(&load_boolean_if_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        while emu.next().unwrap().is_some() {}
+
+        let csv = emu.variables_csv();
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("scope,id,designation,value"));
+        assert!(csv
+            .lines()
+            .any(|l| l == "global,7c9e1a3d-5f6b-4b80-92d4-6a8c0e2b4d97,g_Flag,true"));
+        assert_eq!(csv_field("a,b \"c\""), "\"a,b \"\"c\"\"\"");
+    }
+
+    #[test]
+    fn history_is_not_recorded_by_default() {
+        let app = Loader::new(&load_nested_parameters_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let x_c = "F0E3B7A1-65D2-4C98-A07F-C4B19E2D5836".parse().unwrap();
+        let mut emu = Emulator::with_machine(&app, leaky_machine()).unwrap();
+        while emu.next().unwrap().is_some() {}
+
+        assert_eq!(emu.explain_variable(x_c).len(), 1);
+    }
+
+    #[test]
+    fn math_operation_sets_local() {
+        use maestro_ngs_application::{
+            Instruction, InstructionValue, Operator, SavedApplicationBuilder, Variable,
+        };
+
+        // Main: l_Sum = 5 + 3, then 5 + 3 into a literal
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let sum = Uuid::from_u128(3);
+        let int = |i| InstructionValue {
+            direct: VariableValue::Int(i),
+            variable: None,
+        };
+        let add_into = |lhs| {
+            Instruction::new(Command::MathOperation {
+                operator: Operator::Plus,
+                lhs,
+                rhs_op1: int(5),
+                rhs_op2: int(3),
+            })
+        };
+        let target = InstructionValue {
+            direct: VariableValue::Int(0),
+            variable: Some(sum),
+        };
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(
+                main,
+                "Main",
+                layout_id,
+                vec![add_into(target), add_into(int(0))],
+            )
+            .add_local(main, Variable::new(sum, "l_Sum", VariableValue::Int(0)))
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.next().unwrap();
+        assert_eq!(
+            emu.local_variables[&main][&sum].value(),
+            &VariableValue::Int(8)
+        );
+        assert!(matches!(
+            emu.next(),
+            Err(EmulatorError::CannotAssignToLiteral { method, line: 1 }) if method == main
+        ));
+    }
+
+    #[test]
+    fn string_operation_assigns_local() {
+        use maestro_ngs_application::{
+            Instruction, InstructionValue, SavedApplicationBuilder, StringOperator, Variable,
+        };
+
+        // Main: l_Plate = "D5", then an operation that is not an assignment
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let plate = Uuid::from_u128(3);
+        let string = |s: &str| InstructionValue {
+            direct: VariableValue::String(s.to_string()),
+            variable: None,
+        };
+        let operation = |operator| {
+            Instruction::new(Command::StringOperation {
+                operator,
+                operands: vec![string("D5")],
+                result: InstructionValue {
+                    direct: VariableValue::String(String::new()),
+                    variable: Some(plate),
+                },
+            })
+        };
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(
+                main,
+                "Main",
+                layout_id,
+                vec![
+                    operation(StringOperator::Assign),
+                    operation(StringOperator::Other(3)),
+                ],
+            )
+            .add_local(
+                main,
+                Variable::new(plate, "l_Plate", VariableValue::String("A1".to_string())),
+            )
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.next().unwrap();
+        assert_eq!(
+            emu.local_variables[&main][&plate].value(),
+            &VariableValue::String("D5".to_string())
+        );
+        assert!(matches!(
+            emu.next(),
+            Err(EmulatorError::UnsupportedStringOperation {
+                code: 3,
+                line: 1,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn negative_integer_is_an_error() {
+        use maestro_ngs_application::{
+            Instruction, InstructionValue, Operator, SavedApplicationBuilder, Variable,
+        };
+
+        // Main: l_Count = 3 - 5
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let count = Uuid::from_u128(3);
+        let int = |i| InstructionValue {
+            direct: VariableValue::Int(i),
+            variable: None,
+        };
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(
+                main,
+                "Main",
+                layout_id,
+                vec![Instruction::new(Command::MathOperation {
+                    operator: Operator::Minus,
+                    lhs: InstructionValue {
+                        direct: VariableValue::Int(0),
+                        variable: Some(count),
+                    },
+                    rhs_op1: int(3),
+                    rhs_op2: int(5),
+                })],
+            )
+            .add_local(main, Variable::new(count, "l_Count", VariableValue::Int(3)))
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        assert!(matches!(
+            emu.next(),
+            Err(EmulatorError::NotAWholeNumber { value, line: 0, .. }) if value == -2.0
+        ));
+        assert_eq!(
+            emu.local_variables[&main][&count].value(),
+            &VariableValue::Int(3)
+        );
+    }
+
+    #[test]
+    fn unknown_instruction_does_nothing() {
+        use maestro_ngs_application::{Instruction, SavedApplicationBuilder};
+
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(
+                main,
+                "Main",
+                layout_id,
+                vec![Instruction::new(Command::Unknown {
+                    designation: "Levitate Plate".to_string(),
+                })],
+            )
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let action = emu.next().unwrap().unwrap();
+        assert!(matches!(
+            action.execute,
+            Execute::Unknown {
+                designation: "Levitate Plate"
+            }
+        ));
+        assert!(emu.next().unwrap().is_none());
+        assert_eq!(emu.machine.state(), ScicloneG3::new().state());
+    }
+
+    #[test]
+    fn validate_dangling_method_call() {
+        use maestro_ngs_application::{Instruction, SavedApplicationBuilder};
+
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let missing = Uuid::from_u128(99);
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(
+                main,
+                "Main",
+                layout_id,
+                vec![
+                    Instruction::new(Command::Initialize),
+                    Instruction::new(Command::RunMethod {
+                        method: missing,
+                        parameters: vec![],
+                    }),
+                ],
+            )
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let issues = validate(&app);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].method, main);
+        assert_eq!(issues[0].line, 1);
+        assert!(issues[0].description.contains(&missing.to_string()));
+    }
+
+    #[test]
+    fn validate_pipette_and_mix() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        assert_eq!(validate(&app), vec![]);
+    }
+
+    #[test]
+    fn emulate_built_app() {
+        use maestro_ngs_application::{
+            Instruction, InstructionValue, LoadEjectTipsHead, SavedApplicationBuilder,
+        };
+
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let (c3, d5) = (Uuid::from_u128(3), Uuid::from_u128(4));
+        let tips_head = |slot| LoadEjectTipsHead {
+            deck_parameter: Some(slot),
+            deck_location: InstructionValue {
+                direct: VariableValue::String(String::new()),
+                variable: None,
+            },
+        };
+        let mut layout = Layout::new(layout_id, "MainLayout");
+        layout.add_position(c3, "C3");
+        layout.add_position(d5, "D5");
+        let instructions = vec![
+            Instruction::new(Command::LoadTips {
+                load_eject_tips_head: tips_head(c3),
+            }),
+            Instruction::new(Command::EjectTips {
+                load_eject_tips_head: tips_head(d5),
+            }),
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(layout)
+            .add_method(main, "Main", layout_id, instructions)
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.next().unwrap();
+        assert!(emu.machine.get_tips_loaded());
+        emu.next().unwrap();
+        assert!(!emu.machine.get_tips_loaded());
+        assert_eq!(emu.machine.get_deck_location(), Some(&"D5".to_string()));
+        assert!(emu.next().unwrap().is_none());
+    }
+
+    #[test]
+    fn method_ending_inside_loop() {
+        // Main loops twice over a call to A, and A ends inside a loop it never closes
+        let app = Loader::new(&load_unclosed_loop_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let mut calls = 0;
+        while let Some(action) = emu.next().unwrap() {
+            if let Execute::RunMethod { .. } = action.execute {
+                calls += 1;
+            }
+        }
+
+        assert_eq!(calls, 2);
+        assert!(emu.stack_loops.is_empty());
+        assert!(emu.done());
+    }
+
+    #[test]
+    fn loop_bound_evaluated_once() {
+        // Loop i = 1 to n with n = 3, and the body increments n
+        let app = Loader::new(&load_mutated_loop_bound_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let main = app.start_method();
+        let n = "1C3E5A7D-9F0B-4D28-A6C8-E2B4D6F8A0C1".parse().unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let mut iterations = 0;
+        while let Some(action) = emu.next().unwrap() {
+            if let Execute::MathOperation { .. } = action.execute {
+                iterations += 1;
+            }
+        }
+
+        assert_eq!(iterations, 3);
+        assert_eq!(
+            emu.local_variables[&main][&n].value(),
+            &VariableValue::Float(6.0)
+        );
+    }
+
+    #[test]
+    fn loop_over_aspirate() {
+        use maestro_ngs_application::{
+            Instruction, InstructionValue, LoadEjectTipsHead, PositionHead, SavedApplicationBuilder,
+        };
+
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let (c3, c4) = (Uuid::from_u128(3), Uuid::from_u128(4));
+        let direct = |value| InstructionValue {
+            direct: value,
+            variable: None,
+        };
+        let int = |i| direct(VariableValue::Int(i));
+        let begin_loop = |to| {
+            Instruction::new(Command::BeginLoop {
+                index: int(0),
+                from: int(1),
+                to: int(to),
+                steps: int(1),
+            })
+        };
+        let aspirate = |volume| {
+            Instruction::new(Command::Aspirate {
+                position_head: PositionHead {
+                    deck_parameter: Some(c4),
+                    deck_location: direct(VariableValue::String(String::new())),
+                    z_offset: direct(VariableValue::Float(0.0)),
+                },
+                volume: direct(VariableValue::Float(volume)),
+                leading_air_gap: None,
+                trailing_air_gap: None,
+            })
+        };
+        let mut layout = Layout::new(layout_id, "MainLayout");
+        layout.add_position(c3, "C3");
+        layout.add_position(c4, "C4");
+        // Three times, aspirate 10 uL and then twice 5 uL in a nested loop
+        let instructions = vec![
+            Instruction::new(Command::LoadTips {
+                load_eject_tips_head: LoadEjectTipsHead {
+                    deck_parameter: Some(c3),
+                    deck_location: direct(VariableValue::String(String::new())),
+                },
+            }),
+            begin_loop(3),
+            aspirate(10.0),
+            begin_loop(2),
+            aspirate(5.0),
+            Instruction::new(Command::EndLoop),
+            Instruction::new(Command::EndLoop),
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(layout)
+            .add_method(main, "Main", layout_id, instructions)
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let mut aspirates = 0;
+        while let Some(action) = emu.next().unwrap() {
+            if let Execute::Aspirate { .. } = action.execute {
+                aspirates += 1;
+            }
+        }
+        assert_eq!(aspirates, 9);
+        assert_eq!(emu.machine.get_tip_volume(), 60.0);
+    }
+
+    #[test]
+    fn while_loop_counts_down() {
+        use maestro_ngs_application::{
+            Comparator, Instruction, InstructionValue, Operator, SavedApplicationBuilder, Variable,
+        };
+
+        // Main: while l_Count > 0, l_Count = l_Count - 1, with a disabled While Loop after it
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let count = Uuid::from_u128(3);
+        let int = |i| InstructionValue {
+            direct: VariableValue::Int(i),
+            variable: None,
+        };
+        let counter = InstructionValue {
+            direct: VariableValue::Int(0),
+            variable: Some(count),
+        };
+        let mut disabled = Instruction::new(Command::WhileLoop {
+            comparator: Comparator::GreaterThan,
+            lhs: InstructionValue {
+                direct: VariableValue::Int(0),
+                variable: Some(Uuid::from_u128(4)),
+            },
+            rhs: int(0),
+        });
+        disabled.is_comment = true;
+        let instructions = vec![
+            Instruction::new(Command::WhileLoop {
+                comparator: Comparator::GreaterThan,
+                lhs: counter.clone(),
+                rhs: int(0),
+            }),
+            Instruction::new(Command::MathOperation {
+                operator: Operator::Minus,
+                lhs: counter.clone(),
+                rhs_op1: counter,
+                rhs_op2: int(1),
+            }),
+            Instruction::new(Command::EndWhile),
+            disabled,
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(main, "Main", layout_id, instructions)
+            .add_local(main, Variable::new(count, "l_Count", VariableValue::Int(3)))
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.next().unwrap();
+        assert_eq!(emu.current_block_context(), vec![BlockKind::While]);
+        emu.next().unwrap();
+        emu.next().unwrap();
+        // Back at the test, which opens the block again if it holds
+        assert!(emu.current_block_context().is_empty());
+        let lines: Vec<usize> = emu.run_to_end().unwrap().iter().map(|a| a.line).collect();
+        assert_eq!(lines, vec![0, 1, 2, 0, 1, 2, 0, 1, 2, 0, 3]);
+        assert!(emu.current_block_context().is_empty());
+        assert_eq!(
+            emu.local_variables[&main][&count].value(),
+            &VariableValue::Int(0)
+        );
+        assert!(emu.action_executed[10].skip);
+    }
+
+    #[test]
+    fn tips_loaded_every_iteration() {
+        use maestro_ngs_application::{
+            Comparator, Instruction, InstructionValue, LoadEjectTipsHead, SavedApplicationBuilder,
+        };
+
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let (c3, d5) = (Uuid::from_u128(3), Uuid::from_u128(4));
+        let direct = |value| InstructionValue {
+            direct: value,
+            variable: None,
+        };
+        let int = |i| direct(VariableValue::Int(i));
+        let head = |deck_parameter| LoadEjectTipsHead {
+            deck_parameter: Some(deck_parameter),
+            deck_location: direct(VariableValue::String(String::new())),
+        };
+        let load = || {
+            Instruction::new(Command::LoadTips {
+                load_eject_tips_head: head(c3),
+            })
+        };
+        let eject = || {
+            Instruction::new(Command::EjectTips {
+                load_eject_tips_head: head(d5),
+            })
+        };
+        let mut layout = Layout::new(layout_id, "MainLayout");
+        layout.add_position(c3, "C3");
+        layout.add_position(d5, "D5");
+        // Fresh tips on each of 4 iterations, and a load that the If..Then skips
+        let instructions = vec![
+            Instruction::new(Command::BeginLoop {
+                index: int(0),
+                from: int(1),
+                to: int(4),
+                steps: int(1),
+            }),
+            load(),
+            eject(),
+            Instruction::new(Command::EndLoop),
+            Instruction::new(Command::IfThen {
+                comparator: Comparator::Equals,
+                lhs: int(1),
+                rhs: int(2),
+            }),
+            load(),
+            eject(),
+            Instruction::new(Command::EndIf),
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(layout)
+            .add_method(main, "Main", layout_id, instructions)
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        assert_eq!(emu.total_tips_used(), 0);
+        emu.run_to_end().unwrap();
+        assert_eq!(emu.total_tips_used(), 4);
+    }
+
+    #[test]
+    fn emulate_boolean_if() {
+        // If g_Flag is set one remark runs, if not a remark in a nested and an outer block run
+        let app = Loader::new(&load_boolean_if_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let flag = "7C9E1A3D-5F6B-4B80-92D4-6A8C0E2B4D97".parse().unwrap();
+
+        for (set, expect) in [
+            (true, vec!["flag set", "done"]),
+            (false, vec!["flag not set, nested", "flag not set", "done"]),
+        ] {
+            let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+            emu.global_variables
+                .get_mut(&flag)
+                .unwrap()
+                .set_value(VariableValue::Bool(set));
+            let mut remarks = Vec::new();
+            while let Some(action) = emu.next().unwrap() {
+                if let Execute::REM { comment } = action.execute {
+                    remarks.push(comment.to_string());
+                }
+            }
+
+            assert_eq!(remarks, expect);
+        }
+    }
+
+    #[test]
+    fn compile_both_branches() {
+        let app = Loader::new(&load_boolean_if_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+
+        // The three conditions are checked but not lowered
+        let compiled = ScicloneG3Emulator::compile(&app).unwrap();
+        assert_eq!(compiled.len(), 7);
+        assert!(compiled.iter().all(|(method, _, exe)| {
+            *method == app.start_method() && !matches!(exe, Execute::IfThen { .. })
+        }));
+        let remarks: Vec<&str> = compiled
+            .iter()
+            .filter_map(|(_, _, exe)| match exe {
+                Execute::REM { comment } => Some(*comment),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            remarks,
+            vec!["flag set", "flag not set, nested", "flag not set", "done"]
+        );
+    }
+
+    #[test]
+    fn compile_collects_unsupported() {
+        let app = Loader::new(&load_complex_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let errors = ScicloneG3Emulator::compile(&app).unwrap_err();
+        assert!(errors.iter().any(|e| matches!(
+            e,
+            EmulatorError::Unsupported { designation, .. } if designation == "Home"
+        )));
+        assert!(errors
+            .iter()
+            .all(|e| !matches!(e, EmulatorError::Unsupported { method, .. } if method.is_nil())));
+        assert!(errors.iter().all(|e| !matches!(
+            e,
+            EmulatorError::Unsupported { designation, .. } if designation == "Absolute Move"
+        )));
+        // Its String Operations are all assignments
+        assert!(errors
+            .iter()
+            .all(|e| !matches!(e, EmulatorError::UnsupportedStringOperation { .. })));
+    }
+
+    #[test]
+    fn false_if_skips_run_method() {
+        use maestro_ngs_application::{
+            Comparator, Instruction, InstructionValue, SavedApplicationBuilder,
+        };
+
+        // Main: If 1 > 2 [If 1 > 2 [] End If, Run Sub] End If, then a comment
+        let main = Uuid::from_u128(1);
+        let sub = Uuid::from_u128(2);
+        let layout_id = Uuid::from_u128(3);
+        let literal = |f| InstructionValue {
+            direct: VariableValue::Float(f),
+            variable: None,
+        };
+        let if_false = || {
+            Instruction::new(Command::IfThen {
+                comparator: Comparator::GreaterThan,
+                lhs: literal(1.0),
+                rhs: literal(2.0),
+            })
+        };
+        let rem = |comment: &str| {
+            Instruction::new(Command::REM {
+                comment: comment.to_string(),
+            })
+        };
+        let instructions = vec![
+            if_false(),
+            if_false(),
+            Instruction::new(Command::EndIf),
+            Instruction::new(Command::RunMethod {
+                method: sub,
+                parameters: Vec::new(),
+            }),
+            Instruction::new(Command::EndIf),
+            rem("after"),
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(main, "Main", layout_id, instructions)
+            .add_method(sub, "Sub", layout_id, vec![rem("in sub")])
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let mut lines = Vec::new();
+        while let Some(action) = emu.next().unwrap() {
+            assert_eq!(action.method, main);
+            lines.push(action.line);
+        }
+        assert_eq!(lines, vec![0, 5]);
+    }
+
+    #[test]
+    fn false_if_guards_aspirate() {
+        use maestro_ngs_application::{
+            Comparator, Instruction, InstructionValue, LoadEjectTipsHead, PositionHead,
+            SavedApplicationBuilder,
+        };
+
+        // Main: Load Tips, If "Water" = "Buffer" [Aspirate 10 uL] End If
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let (c3, c4) = (Uuid::from_u128(3), Uuid::from_u128(4));
+        let direct = |value| InstructionValue {
+            direct: value,
+            variable: None,
+        };
+        let text = |s: &str| direct(VariableValue::String(s.to_string()));
+        let mut layout = Layout::new(layout_id, "MainLayout");
+        layout.add_position(c3, "C3");
+        layout.add_position(c4, "C4");
+        let instructions = vec![
+            Instruction::new(Command::LoadTips {
+                load_eject_tips_head: LoadEjectTipsHead {
+                    deck_parameter: Some(c3),
+                    deck_location: text(""),
+                },
+            }),
+            Instruction::new(Command::IfThen {
+                comparator: Comparator::Equals,
+                lhs: text("Water"),
+                rhs: text("Buffer"),
+            }),
+            Instruction::new(Command::Aspirate {
+                position_head: PositionHead {
+                    deck_parameter: Some(c4),
+                    deck_location: text(""),
+                    z_offset: direct(VariableValue::Float(0.0)),
+                },
+                volume: direct(VariableValue::Float(10.0)),
+                leading_air_gap: None,
+                trailing_air_gap: None,
+            }),
+            Instruction::new(Command::EndIf),
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(layout)
+            .add_method(main, "Main", layout_id, instructions)
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let mut lines = Vec::new();
+        while let Some(action) = emu.next().unwrap() {
+            lines.push(action.line);
+        }
+        assert_eq!(lines, vec![0, 1]);
+        assert_eq!(emu.machine.get_tip_volume(), 0.0);
+    }
+
+    #[test]
+    fn concurrent_emulators_share_app() {
+        let app = std::sync::Arc::new(
+            Loader::new(&load_pipette_and_mix_app())
+                .unwrap()
+                .build_application()
+                .unwrap(),
+        );
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let app = std::sync::Arc::clone(&app);
+                std::thread::spawn(move || {
+                    let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+                    while emu.next().unwrap().is_some() {}
+                    emu.run_summary()
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap().volume_dispensed, 100.0);
+        }
+    }
+
+    #[test]
+    fn string_volume_is_located() {
+        use maestro_ngs_application::{
+            Instruction, InstructionValue, PositionHead, SavedApplicationBuilder,
+        };
+
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let c4 = Uuid::from_u128(3);
+        let string = |s: &str| InstructionValue {
+            direct: VariableValue::String(s.to_string()),
+            variable: None,
+        };
+        let mut layout = Layout::new(layout_id, "MainLayout");
+        layout.add_position(c4, "C4");
+        let instructions = vec![
+            Instruction::new(Command::REM {
+                comment: "aspirate ten".to_string(),
+            }),
+            Instruction::new(Command::Aspirate {
+                position_head: PositionHead {
+                    deck_parameter: Some(c4),
+                    deck_location: string(""),
+                    z_offset: string(""),
+                },
+                volume: string("ten"),
+                leading_air_gap: None,
+                trailing_air_gap: None,
+            }),
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(layout)
+            .add_method(main, "Main", layout_id, instructions)
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.next().unwrap();
+        match emu.next() {
+            Err(EmulatorError::UnexpectedType {
+                expected,
+                found,
+                method,
+                line,
+            }) => {
+                assert_eq!(expected, VariableType::Float);
+                assert_eq!(found, VariableType::String);
+                assert_eq!(method, main);
+                assert_eq!(line, 1);
+            }
+            _ => panic!("expected a type error"),
+        }
+    }
+
+    #[test]
+    fn run_with_counting_sink() {
+        struct Counter(usize);
+        impl TraceSink for Counter {
+            fn emit(&mut self, _: &Action, _: &MachineState) -> std::io::Result<()> {
+                self.0 += 1;
+                Ok(())
+            }
+        }
+
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let mut counter = Counter(0);
+        emu.run_with_sink(&mut counter).unwrap();
+        assert_eq!(counter.0, 5);
+        assert!(emu.done());
+    }
+
+    #[test]
+    fn run_with_csv_sink() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let main = app.start_method();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        let mut sink = CsvSink::new(Vec::new());
+        emu.run_with_sink(&mut sink).unwrap();
+
+        let csv = String::from_utf8(sink.into_inner()).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(
+            lines[0],
+            "method,line,skip,instruction,deck_location,tips_loaded,tip_volume"
+        );
+        assert_eq!(lines[2], format!("{},1,false,Aspirate,C4,true,100", main));
+    }
+
+    #[test]
+    fn suspend_at_dialog() {
+        use maestro_ngs_application::{Instruction, SavedApplicationBuilder};
+
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let rem = |comment: &str| {
+            Instruction::new(Command::REM {
+                comment: comment.to_string(),
+            })
+        };
+        let instructions = vec![
+            rem("before"),
+            Instruction::new(Command::ShowDialog {
+                text: "Load the plate".to_string(),
+            }),
+            rem("after"),
+        ];
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(main, "Main", layout_id, instructions)
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        assert!(matches!(
+            emu.step().unwrap(),
+            StepOutcome::Action(Action { line: 0, .. })
+        ));
+        for _ in 0..2 {
+            assert!(matches!(
+                emu.step().unwrap(),
+                StepOutcome::AwaitingDialog("Load the plate")
+            ));
+        }
+        assert_eq!(emu.get_current_instruction().unwrap(), 2);
+
+        emu.continue_after_dialog();
+        assert!(matches!(
+            emu.step().unwrap(),
+            StepOutcome::Action(Action { line: 2, .. })
+        ));
+        assert!(matches!(emu.step().unwrap(), StepOutcome::Finished));
+    }
+
+    #[test]
+    fn block_context_in_nested_blocks() {
+        let app = Loader::new(&load_boolean_if_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let flag = "7C9E1A3D-5F6B-4B80-92D4-6A8C0E2B4D97".parse().unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.global_variables
+            .get_mut(&flag)
+            .unwrap()
+            .set_value(VariableValue::Bool(false));
+        assert!(emu.current_block_context().is_empty());
+
+        // The first test does not hold, the next two nested ones do
+        for _ in 0..3 {
+            emu.next().unwrap();
+        }
+        assert_eq!(emu.get_current_instruction().unwrap(), 5);
+        assert_eq!(
+            emu.current_block_context(),
+            vec![BlockKind::If, BlockKind::If]
+        );
+        emu.next().unwrap();
+        emu.next().unwrap();
+        assert_eq!(emu.current_block_context(), vec![BlockKind::If]);
+        while emu.next().unwrap().is_some() {}
+        assert!(emu.current_block_context().is_empty());
+
+        let app = Loader::new(&load_looped_counter_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        emu.next().unwrap();
+        assert_eq!(emu.current_block_context(), vec![BlockKind::Loop]);
+    }
+
+    #[test]
+    fn summary_of_pipette_and_mix() {
+        let app = Loader::new(&load_pipette_and_mix_app())
+            .unwrap()
+            .build_application()
+            .unwrap();
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        while emu.next().unwrap().is_some() {}
+
+        let summary = emu.run_summary();
+        assert_eq!(
+            summary,
+            RunSummary {
+                total_steps: 5,
+                executed: 5,
+                skipped: 0,
+                volume_aspirated: 100.0,
+                volume_dispensed: 100.0,
+                tip_events: 2,
+                volume_leaks: Vec::new(),
+                estimated_seconds: 0.0,
+                time_breakdown: BTreeMap::new(),
+                error: None,
+            }
+        );
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["total_steps"], 5);
+        assert_eq!(json["volume_dispensed"], 100.0);
+    }
+
+    #[test]
+    fn summary_keeps_time_and_error() {
+        use maestro_ngs_application::{Instruction, InstructionValue, SavedApplicationBuilder};
+
+        let main = Uuid::from_u128(1);
+        let layout_id = Uuid::from_u128(2);
+        let missing = Uuid::from_u128(99);
+        let shake = |seconds| {
+            Instruction::new(Command::RunShakerForTime {
+                speed: InstructionValue {
+                    direct: VariableValue::Float(500.0),
+                    variable: None,
+                },
+                timeout: InstructionValue {
+                    direct: VariableValue::Seconds(seconds),
+                    variable: None,
+                },
+            })
+        };
+        let call = Instruction::new(Command::RunMethod {
+            method: missing,
+            parameters: vec![],
+        });
+        let app = SavedApplicationBuilder::new()
+            .add_layout(Layout::new(layout_id, "MainLayout"))
+            .add_method(main, "Main", layout_id, vec![shake(30), shake(15), call])
+            .set_start_method(main)
+            .build()
+            .unwrap();
+
+        let mut emu = ScicloneG3Emulator::new(&app).unwrap();
+        assert!(emu.run_to_end().is_err());
+        let summary = emu.run_summary();
+        assert_eq!(summary.estimated_seconds, 45.0);
+        assert_eq!(summary.time_breakdown["RunShakerForTime"], 45.0);
+        assert_eq!(
+            summary.error,
+            Some(EmulatorError::UnknownMethod(missing).to_string())
+        );
+    }
 }
@@ -0,0 +1,517 @@
+use crate::{EmulatorError, Execute, Result, Scope};
+use maestro_ngs_application::{
+    Command, Comparator, InstructionValue, Layout, LoadEjectTipsHead, Operator, PositionHead,
+    StringOperator, VariableType, VariableValue,
+};
+use uuid::Uuid;
+
+/// Lower a saved command to the instruction a machine executes. Values are resolved in the scope
+/// and deck positions in the layout of the method the command belongs to. A command does not know
+/// where it is, so an error with a method and line, such as `UnexpectedType`, has a nil method and
+/// line 0 for the caller to fill in.
+pub fn lower_command<'a>(
+    command: &'a Command,
+    scope: &Scope,
+    layout: &'a Layout,
+) -> Result<Execute<'a>> {
+    match command {
+        Command::AbsoluteMove { x, y, z } => {
+            let coordinate = |axis: &Option<InstructionValue>| {
+                axis.as_ref()
+                    .map(|value| value_float(value, scope))
+                    .transpose()
+            };
+            Ok(Execute::AbsoluteMove {
+                x: coordinate(x)?,
+                y: coordinate(y)?,
+                z: coordinate(z)?,
+            })
+        }
+        Command::Aspirate {
+            position_head,
+            volume,
+            leading_air_gap,
+            trailing_air_gap,
+        } => {
+            let position = position_of_head(position_head, layout)?;
+            let vol = value_float(volume, scope)?;
+            let mut air_gap = 0.0;
+            for gap in leading_air_gap.iter().chain(trailing_air_gap) {
+                air_gap += value_float(gap, scope)?;
+            }
+            Ok(Execute::Aspirate {
+                position,
+                volume: vol,
+                air_gap: Some(air_gap).filter(|&a| a > 0.0),
+            })
+        }
+        Command::BeginLoop {
+            index,
+            from,
+            to,
+            steps,
+        } => Ok(Execute::BeginLoop {
+            index: index.variable,
+            from: value_number(from, scope)?,
+            to: value_number(to, scope)?,
+            step: value_number(steps, scope)?,
+        }),
+        Command::EndIf => Ok(Execute::EndIf),
+        Command::HeadPosition { position_head } => Ok(Execute::HeadPosition {
+            position: position_of_head(position_head, layout)?,
+        }),
+        Command::HomePAxis => Ok(Execute::HomePAxis),
+        Command::EndLoop => Ok(Execute::EndLoop),
+        Command::EndWhile => Ok(Execute::EndWhile),
+        Command::Dispense {
+            position_head,
+            volume,
+            dispense_all,
+            destinations,
+        } if !*dispense_all && !destinations.is_empty() => {
+            let mut wells = vec![(
+                position_of_head(position_head, layout)?,
+                value_float(volume, scope)?,
+            )];
+            for (head, vol) in destinations {
+                wells.push((position_of_head(head, layout)?, *vol));
+            }
+            Ok(Execute::ArrayDispense {
+                destinations: wells,
+            })
+        }
+        Command::Dispense {
+            position_head,
+            volume,
+            dispense_all,
+            ..
+        } => {
+            let position = position_of_head(position_head, layout)?;
+            let vol = if *dispense_all {
+                None
+            } else {
+                Some(value_float(volume, scope)?)
+            };
+            let capacity = position_head
+                .deck_parameter
+                .and_then(|uuid| layout.well_capacity(uuid));
+            Ok(Execute::Dispense {
+                position,
+                volume: vol,
+                capacity,
+            })
+        }
+        Command::EjectTips {
+            load_eject_tips_head,
+        } => {
+            let position = position_of_tips_head(load_eject_tips_head, layout)?;
+            Ok(Execute::EjectTips { position })
+        }
+        Command::IfThen {
+            comparator,
+            lhs,
+            rhs,
+        } => {
+            let condition = compare(comparator, scope.resolve(lhs)?, scope.resolve(rhs)?)?;
+            Ok(Execute::IfThen { condition })
+        }
+        Command::LoadTips {
+            load_eject_tips_head,
+        } => {
+            let position = position_of_tips_head(load_eject_tips_head, layout)?;
+            let tip_size = load_eject_tips_head
+                .deck_parameter
+                .and_then(|uuid| layout.tip_size(uuid));
+            Ok(Execute::LoadTips { position, tip_size })
+        }
+        Command::MathOperation {
+            operator,
+            lhs,
+            rhs_op1,
+            rhs_op2,
+        } => {
+            let variable = lhs.variable.ok_or_else(assign_to_literal)?;
+            let op1 = value_number(rhs_op1, scope)?;
+            let value = match operator {
+                Operator::Assign => op1,
+                Operator::Minus => op1 - value_number(rhs_op2, scope)?,
+                Operator::Plus => op1 + value_number(rhs_op2, scope)?,
+            };
+            Ok(Execute::MathOperation { variable, value })
+        }
+        Command::MoveMaterial { from, to } => Ok(Execute::MoveMaterial {
+            from: position_of_head(from, layout)?,
+            to: position_of_head(to, layout)?,
+        }),
+        Command::Mix { position_head } => {
+            let position = position_of_head(position_head, layout)?;
+            Ok(Execute::Mix { position })
+        }
+        Command::PAxisSetPosition { position } => Ok(Execute::PAxisSetPosition {
+            position: value_float(position, scope)?,
+        }),
+        Command::Pick { position_head } => Ok(Execute::Pick {
+            position: position_of_head(position_head, layout)?,
+        }),
+        Command::Place { position_head } => Ok(Execute::Place {
+            position: position_of_head(position_head, layout)?,
+        }),
+        Command::REM { comment } => Ok(Execute::REM { comment }),
+        Command::RelativeMove { x, y, z } => {
+            let distance = |axis: &Option<InstructionValue>| match axis {
+                Some(value) => value_float(value, scope),
+                None => Ok(0.0),
+            };
+            Ok(Execute::RelativeMove {
+                dx: distance(x)?,
+                dy: distance(y)?,
+                dz: distance(z)?,
+            })
+        }
+        Command::RunMethod { method, .. } => Ok(Execute::RunMethod { method: *method }),
+        Command::RunShakerForTime { speed, timeout } => Ok(Execute::RunShakerForTime {
+            speed: value_float(speed, scope)?,
+            seconds: value_number(timeout, scope)?,
+        }),
+        Command::SetTemperature {
+            device,
+            temperature,
+        } => Ok(Execute::SetTemperature {
+            device,
+            temperature: value_float(temperature, scope)?,
+        }),
+        Command::SetTravelHeight { height } => Ok(Execute::SetTravelHeight {
+            height: value_float(height, scope)?,
+        }),
+        Command::ShakerOnOff { device, on_off } => Ok(Execute::ShakerOnOff {
+            device,
+            on: value_bool(on_off, scope)?,
+        }),
+        Command::ShowDialog { text } => Ok(Execute::ShowDialog { text }),
+        Command::StringOperation {
+            operator,
+            operands,
+            result,
+        } => {
+            let variable = result.variable.ok_or_else(assign_to_literal)?;
+            match operator {
+                StringOperator::Assign => {
+                    let operand = operands.first().ok_or(EmulatorError::MissingOperand {
+                        method: Uuid::nil(),
+                        line: 0,
+                    })?;
+                    Ok(Execute::StringOperation {
+                        variable,
+                        value: value_string(operand, scope)?,
+                    })
+                }
+                StringOperator::Other(code) => Err(EmulatorError::UnsupportedStringOperation {
+                    code: *code,
+                    method: Uuid::nil(),
+                    line: 0,
+                }),
+            }
+        }
+        Command::TemperatureOnOff { device, on_off } => Ok(Execute::TemperatureOnOff {
+            device,
+            on: value_bool(on_off, scope)?,
+        }),
+        Command::Unknown { designation } => Ok(Execute::Unknown { designation }),
+        Command::WhileLoop {
+            comparator,
+            lhs,
+            rhs,
+        } => {
+            let condition = compare(comparator, scope.resolve(lhs)?, scope.resolve(rhs)?)?;
+            Ok(Execute::WhileLoop { condition })
+        }
+        _ => Err(EmulatorError::Unsupported {
+            designation: command.designation().to_string(),
+            method: Uuid::nil(),
+            line: 0,
+        }),
+    }
+}
+
+fn value_float(inst: &InstructionValue, scope: &Scope) -> Result<f64> {
+    match scope.resolve(inst)? {
+        VariableValue::Float(f) => Ok(*f),
+        found => Err(unexpected_type(VariableType::Float, found)),
+    }
+}
+
+fn value_string(inst: &InstructionValue, scope: &Scope) -> Result<String> {
+    match scope.resolve(inst)? {
+        VariableValue::String(s) => Ok(s.clone()),
+        found => Err(unexpected_type(VariableType::String, found)),
+    }
+}
+
+fn value_bool(inst: &InstructionValue, scope: &Scope) -> Result<bool> {
+    match scope.resolve(inst)? {
+        VariableValue::Bool(b) => Ok(*b),
+        found => Err(unexpected_type(VariableType::Bool, found)),
+    }
+}
+
+// Loop bounds and math operands may be given as integers or floats
+fn value_number(inst: &InstructionValue, scope: &Scope) -> Result<f64> {
+    number(scope.resolve(inst)?)
+}
+
+fn number(value: &VariableValue) -> Result<f64> {
+    match value {
+        VariableValue::Float(f) => Ok(*f),
+        VariableValue::Int(i) => Ok(*i as f64),
+        VariableValue::Seconds(s) => Ok(*s as f64),
+        found => Err(unexpected_type(VariableType::Float, found)),
+    }
+}
+
+fn assign_to_literal() -> EmulatorError {
+    EmulatorError::CannotAssignToLiteral {
+        method: Uuid::nil(),
+        line: 0,
+    }
+}
+
+fn unexpected_type(expected: VariableType, found: &VariableValue) -> EmulatorError {
+    EmulatorError::UnexpectedType {
+        expected,
+        found: found.variable_type(),
+        method: Uuid::nil(),
+        line: 0,
+    }
+}
+
+// Booleans and strings can only be tested for equality, numbers can also be ordered
+fn compare(comparator: &Comparator, lhs: &VariableValue, rhs: &VariableValue) -> Result<bool> {
+    match (lhs, rhs) {
+        (VariableValue::Bool(a), VariableValue::Bool(b)) => match comparator {
+            Comparator::Equals => Ok(a == b),
+            _ => Err(EmulatorError::InvalidComparison),
+        },
+        (VariableValue::String(a), VariableValue::String(b)) => match comparator {
+            Comparator::Equals => Ok(a == b),
+            _ => Err(EmulatorError::InvalidComparison),
+        },
+        _ => {
+            let (a, b) = (number(lhs)?, number(rhs)?);
+            Ok(match comparator {
+                Comparator::Equals => a == b,
+                Comparator::GreaterThan => a > b,
+                Comparator::GreaterThanOrEqual => a >= b,
+                Comparator::LessThan => a < b,
+                Comparator::LessThanOrEqual => a <= b,
+            })
+        }
+    }
+}
+
+fn position_of_head<'a>(pos: &PositionHead, layout: &'a Layout) -> Result<&'a str> {
+    layout_position(pos.deck_parameter, layout)
+}
+
+fn position_of_tips_head<'a>(pos: &LoadEjectTipsHead, layout: &'a Layout) -> Result<&'a str> {
+    layout_position(pos.deck_parameter, layout)
+}
+
+// Positions given as values, rather than deck parameters, are not resolved
+fn layout_position(deck_parameter: Option<Uuid>, layout: &Layout) -> Result<&str> {
+    let uuid = deck_parameter.ok_or(EmulatorError::MissingDeckParameter {
+        method: Uuid::nil(),
+        line: 0,
+    })?;
+    layout
+        .position(uuid)
+        .map(|p| p.as_str())
+        .ok_or(EmulatorError::UnknownLayoutPosition(uuid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use maestro_ngs_application::{Loader, SavedApplication, Variable};
+    use std::collections::HashMap;
+
+    fn load_app(name: &str) -> SavedApplication {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test");
+        d.push(name);
+
+        Loader::new(&std::fs::read_to_string(d).unwrap())
+            .unwrap()
+            .build_application()
+            .unwrap()
+    }
+
+    fn lower_line(app: &SavedApplication, line: usize) -> Result<Execute<'_>> {
+        let main = app.start_method();
+        let layout = &app.layouts()[&app.layout_of_method(main).unwrap()];
+        let locals = app.local_variables_of_method(main).unwrap();
+        let params = app.parameters_of_method(main).unwrap();
+        let scope = Scope::new(locals, params, app.global_variables());
+        lower_command(
+            &app.instruction(main, line).unwrap().command,
+            &scope,
+            layout,
+        )
+    }
+
+    #[test]
+    fn lower_pipetting() {
+        let app = load_app("Pipette_and_Mix.eap");
+        assert!(matches!(
+            lower_line(&app, 0),
+            Ok(Execute::LoadTips {
+                position: "C3",
+                tip_size: None
+            })
+        ));
+        assert!(matches!(
+            lower_line(&app, 1),
+            Ok(Execute::Aspirate {
+                position: "C4",
+                volume,
+                air_gap: None,
+            }) if volume == 100.0
+        ));
+        assert!(matches!(
+            lower_line(&app, 3),
+            Ok(Execute::Mix { position: "B4" })
+        ));
+        assert!(matches!(
+            lower_line(&app, 4),
+            Ok(Execute::EjectTips { position: "D5" })
+        ));
+    }
+
+    #[test]
+    fn lower_loop_bounds() {
+        // Loop i = 1 to 3
+        let app = load_app("Looped_Counter.eap");
+        assert!(matches!(
+            lower_line(&app, 0),
+            Ok(Execute::BeginLoop { index: Some(_), from, to, step })
+                if from == 1.0 && to == 3.0 && step == 1.0
+        ));
+    }
+
+    #[test]
+    fn lower_math_operation() {
+        // g_Count = g_Count + 2, with g_Count saved as 0
+        let app = load_app("Looped_Counter.eap");
+        assert!(matches!(
+            lower_line(&app, 1),
+            Ok(Execute::MathOperation { value, .. }) if value == 2.0
+        ));
+    }
+
+    // The first aspirate of the application and the layout of its method
+    fn find_aspirate(app: &SavedApplication) -> (&Command, &Layout) {
+        app.ids_methods()
+            .into_iter()
+            .find_map(|&m| {
+                let layout = &app.layouts()[&app.layout_of_method(m).unwrap()];
+                (0..app.instruction_count(m).unwrap())
+                    .map(|l| &app.instruction(m, l).unwrap().command)
+                    .find(|c| matches!(c, Command::Aspirate { .. }))
+                    .map(|c| (c, layout))
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn lower_unknown_variable() {
+        // C aspirates the value of its parameter, which is not in an empty scope
+        let app = load_app("Nested_Parameters.eap");
+        let (command, layout) = find_aspirate(&app);
+        let empty = HashMap::new();
+        let scope = Scope::new(&empty, &empty, &empty);
+        assert!(matches!(
+            lower_command(command, &scope, layout),
+            Err(EmulatorError::UnknownVariable(_))
+        ));
+    }
+
+    #[test]
+    fn lower_volume_from_local_variable() {
+        // The volume references a variable, found here among the local variables
+        let app = load_app("Nested_Parameters.eap");
+        let (command, layout) = find_aspirate(&app);
+        let volume = match command {
+            Command::Aspirate { volume, .. } => volume.variable.unwrap(),
+            _ => unreachable!(),
+        };
+        let empty = HashMap::new();
+        let mut locals = HashMap::new();
+        locals.insert(
+            volume,
+            Variable::new(volume, "l_Volume", VariableValue::Float(50.0)),
+        );
+        let scope = Scope::new(&locals, &empty, &empty);
+        assert!(matches!(
+            lower_command(command, &scope, layout),
+            Ok(Execute::Aspirate { volume, .. }) if volume == 50.0
+        ));
+
+        locals.insert(
+            volume,
+            Variable::new(volume, "l_Volume", VariableValue::String("50".to_string())),
+        );
+        let scope = Scope::new(&locals, &empty, &empty);
+        assert!(matches!(
+            lower_command(command, &scope, layout),
+            Err(EmulatorError::UnexpectedType {
+                expected: VariableType::Float,
+                found: VariableType::String,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn lower_without_deck_parameter() {
+        let direct = |value| InstructionValue {
+            direct: value,
+            variable: None,
+        };
+        let mix = Command::Mix {
+            position_head: PositionHead {
+                deck_parameter: None,
+                deck_location: direct(VariableValue::String("C4".to_string())),
+                z_offset: direct(VariableValue::Float(0.0)),
+            },
+        };
+        let layout = Layout::new(Uuid::from_u128(1), "Layout");
+        let empty = HashMap::new();
+        let scope = Scope::new(&empty, &empty, &empty);
+        assert!(matches!(
+            lower_command(&mix, &scope, &layout),
+            Err(EmulatorError::MissingDeckParameter { .. })
+        ));
+        assert!(matches!(
+            lower_command(&Command::Initialize, &scope, &layout),
+            Err(EmulatorError::Unsupported { designation, .. }) if designation == "Initialize"
+        ));
+    }
+
+    #[test]
+    fn compare_bool() {
+        let (t, f) = (VariableValue::Bool(true), VariableValue::Bool(false));
+        assert!(compare(&Comparator::Equals, &t, &t).unwrap());
+        assert!(!compare(&Comparator::Equals, &t, &f).unwrap());
+        assert!(matches!(
+            compare(&Comparator::GreaterThan, &t, &f),
+            Err(EmulatorError::InvalidComparison)
+        ));
+        assert!(matches!(
+            compare(&Comparator::Equals, &t, &VariableValue::Float(1.0)),
+            Err(EmulatorError::UnexpectedType {
+                expected: VariableType::Float,
+                found: VariableType::Bool,
+                ..
+            })
+        ));
+    }
+}
@@ -1,79 +1,381 @@
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
 type Result<T> = std::result::Result<T, MachineError>;
 
+// Temperature devices start at this temperature, in °C, and return to it when turned off
+const ROOM_TEMPERATURE: f64 = 22.0;
+
+// Volumes, in uL, that differ by less than this only differ by floating point rounding
+const VOLUME_EPSILON: f64 = 1e-9;
+
 pub trait Machine {
     fn new() -> Self;
     fn execute(&mut self, exe: &Execute) -> Result<()>;
+
+    /// Volume currently held in each tip
+    fn tip_volume(&self) -> f64;
+
+    /// Emulated time that has passed, in seconds. Machines that do not model time stay at zero.
+    fn elapsed_seconds(&self) -> f64 {
+        0.0
+    }
+
+    /// A snapshot of the machine, for reporting
+    fn state(&self) -> MachineState;
+
+    /// Everything about the machine that executing instructions changes, to be restored later
+    fn snapshot(&self) -> MachineSnapshot;
+
+    /// Go back to the state of a snapshot. Settings and warnings are kept.
+    fn restore(&mut self, snap: &MachineSnapshot);
+
+    /// Execute a sequence of instructions, stopping at the first error. Machines that can take
+    /// shortcuts over a whole sequence should override this.
+    fn execute_batch(&mut self, exes: &[Execute]) -> Result<()> {
+        for exe in exes {
+            self.execute(exe)?;
+        }
+        Ok(())
+    }
 }
 
 impl Machine for ScicloneG3 {
-
     fn new() -> Self {
-        ScicloneG3 {
-            deck_location: None,
-            tips_loaded: false,
-            tip_volume: 0.0,
-        }
+        ScicloneG3::with_config(ScicloneConfig::default())
     }
 
     fn execute(&mut self, exe: &Execute) -> Result<()> {
-        match exe {
-            &Execute::Aspirate { position, volume } => {
-                self.move_to(position);
-                self.aspirate(volume)?;
-            }
-            &Execute::Dispense { position, volume } => {
-                self.move_to(position);
-                self.dispense(volume)?;
-            }
-            &Execute::EjectTips {position} => {
-                self.move_to(position);
-                self.eject_tips();
-            }
-            &Execute::LoadTips { position } => {
-                self.move_to(position);
-                self.load_tips()?;
+        if let Some(position) = exe.position() {
+            self.move_to(position);
+        }
+        self.operate(exe)
+    }
+
+    fn tip_volume(&self) -> f64 {
+        self.get_tip_volume()
+    }
+
+    fn elapsed_seconds(&self) -> f64 {
+        self.elapsed_seconds
+    }
+
+    fn state(&self) -> MachineState {
+        MachineState {
+            deck_location: self.deck_location.clone(),
+            tips_loaded: self.tips_loaded,
+            tip_volume: self.get_tip_volume(),
+            location_volumes: sorted(&self.location_volumes),
+            shaker_on: sorted(&self.shaker_on),
+            temperature_on: sorted(&self.temperature_on),
+            device_temperature: sorted(&self.device_temperature),
+            current_temperature: sorted(&self.current_temperature),
+            elapsed_seconds: self.elapsed_seconds,
+        }
+    }
+
+    fn snapshot(&self) -> MachineSnapshot {
+        MachineSnapshot {
+            deck_location: self.deck_location.clone(),
+            tips_loaded: self.tips_loaded,
+            tip_volumes: self.tip_volumes.clone(),
+            air_volume: self.air_volume,
+            tip_capacity: self.tip_capacity,
+            location_volumes: self.location_volumes.clone(),
+            travel_height: self.travel_height,
+            p_axis_position: self.p_axis_position,
+            xyz: self.xyz,
+            holding_plate: self.holding_plate,
+            shaker_on: self.shaker_on.clone(),
+            temperature_on: self.temperature_on.clone(),
+            device_temperature: self.device_temperature.clone(),
+            current_temperature: self.current_temperature.clone(),
+            elapsed_seconds: self.elapsed_seconds,
+        }
+    }
+
+    fn restore(&mut self, snap: &MachineSnapshot) {
+        let snap = snap.clone();
+        self.deck_location = snap.deck_location;
+        self.tips_loaded = snap.tips_loaded;
+        self.tip_volumes = snap.tip_volumes;
+        self.air_volume = snap.air_volume;
+        self.tip_capacity = snap.tip_capacity;
+        self.location_volumes = snap.location_volumes;
+        self.travel_height = snap.travel_height;
+        self.p_axis_position = snap.p_axis_position;
+        self.xyz = snap.xyz;
+        self.holding_plate = snap.holding_plate;
+        self.shaker_on = snap.shaker_on;
+        self.temperature_on = snap.temperature_on;
+        self.device_temperature = snap.device_temperature;
+        self.current_temperature = snap.current_temperature;
+        self.elapsed_seconds = snap.elapsed_seconds;
+    }
+
+    fn execute_batch(&mut self, exes: &[Execute]) -> Result<()> {
+        for (i, exe) in exes.iter().enumerate() {
+            // The head only passes through a position it is moved on from at once
+            let passing = matches!(exe, Execute::HeadPosition { .. })
+                && matches!(exes.get(i + 1), Some(Execute::HeadPosition { .. }));
+            if passing {
+                continue;
             }
-            &Execute::Mix { position } => {
-                self.move_to(position);
+            if let Some(position) = exe.position() {
+                if self.deck_location.as_deref() != Some(position) {
+                    self.move_to(position);
+                }
             }
-            &Execute::REM { comment: _ } => {}
+            self.operate(exe)?;
         }
-
         Ok(())
     }
 }
 
+/// What a machine reports about itself between instructions
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct MachineState {
+    pub deck_location: Option<String>,
+    pub tips_loaded: bool,
+    pub tip_volume: f64,
+    /// Liquid in each tracked deck location. Left out of the serialization when nothing is
+    /// tracked.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub location_volumes: BTreeMap<String, f64>,
+    /// Whether the shaker of each device that was turned on or off is running. Left out of the
+    /// serialization when no shaker was used, as are the other device maps.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub shaker_on: BTreeMap<String, bool>,
+    /// Whether each temperature device that was turned on or off is heating or cooling
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub temperature_on: BTreeMap<String, bool>,
+    /// The temperature, in °C, each device was set to
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub device_temperature: BTreeMap<String, f64>,
+    /// The temperature, in °C, each device has reached
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub current_temperature: BTreeMap<String, f64>,
+    /// Emulated time that has passed, in seconds
+    pub elapsed_seconds: f64,
+}
+
+// The state reports devices and locations in order of their name
+fn sorted<V: Copy>(map: &HashMap<String, V>) -> BTreeMap<String, V> {
+    map.iter()
+        .map(|(key, &value)| (key.clone(), value))
+        .collect()
+}
+
+/// The state of a machine that executing instructions changes, as taken by `Machine::snapshot`.
+/// Machines leave out what they do not model.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MachineSnapshot {
+    pub deck_location: Option<String>,
+    pub tips_loaded: bool,
+    pub tip_volumes: Vec<f64>,
+    pub air_volume: f64,
+    pub tip_capacity: Option<f64>,
+    pub location_volumes: HashMap<String, f64>,
+    pub travel_height: Option<f64>,
+    pub p_axis_position: f64,
+    pub xyz: (f64, f64, f64),
+    pub holding_plate: bool,
+    pub shaker_on: HashMap<String, bool>,
+    pub temperature_on: HashMap<String, bool>,
+    pub device_temperature: HashMap<String, f64>,
+    pub current_temperature: HashMap<String, f64>,
+    pub elapsed_seconds: f64,
+}
+
+/// Hardware settings of a Sciclone G3
+#[derive(Debug, Clone, Default)]
+pub struct ScicloneConfig {
+    /// Aspirate and dispense volumes are rounded to a multiple of this. Zero means no rounding.
+    pub volume_resolution: f64,
+    /// Least volume that must remain in a tracked location after aspirating from it, so the tips
+    /// stay below the liquid surface
+    pub min_source_volume: f64,
+    /// Most volume the tips may still hold when they are ejected, over which that is a
+    /// `MachineError::EjectWithVolume`. Zero, the default, means the tips must be empty, as on the
+    /// real machine ejecting liquid is a mistake. What is left within the volume resolution, or
+    /// within rounding error without one, counts as empty. `f64::INFINITY` ejects any volume,
+    /// which the emulator reports as volume leaks.
+    pub max_eject_volume: f64,
+    /// What to do when dispensing all from empty tips, which usually means the aspirate was
+    /// skipped
+    pub dispense_empty: DispenseEmptyPolicy,
+    /// Where the gantry can go. Without it, moves are not checked.
+    pub deck_envelope: Option<DeckEnvelope>,
+    /// How fast temperature devices heat or cool, in °C per second of emulated time. Zero means
+    /// they reach their temperature at once.
+    pub temperature_ramp_rate: f64,
+}
+
+/// The least and greatest x, y and z coordinates of the gantry, in mm from where it starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeckEnvelope {
+    pub min: (f64, f64, f64),
+    pub max: (f64, f64, f64),
+}
+
+impl DeckEnvelope {
+    pub fn contains(&self, (x, y, z): (f64, f64, f64)) -> bool {
+        (self.min.0..=self.max.0).contains(&x)
+            && (self.min.1..=self.max.1).contains(&y)
+            && (self.min.2..=self.max.2).contains(&z)
+    }
+}
+
+/// How `ScicloneG3` treats dispensing all from empty tips
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DispenseEmptyPolicy {
+    /// Dispense nothing
+    #[default]
+    Allow,
+    /// Dispense nothing, and keep a `MachineError::DispenseFromEmpty` among the warnings
+    Warn,
+    /// Fail with `MachineError::DispenseFromEmpty`
+    Error,
+}
+
 pub struct ScicloneG3 {
+    config: ScicloneConfig,
     deck_location: Option<String>,
     tips_loaded: bool,
-    tip_volume: f64,
+    tip_volumes: Vec<f64>,
+    air_volume: f64,
+    tip_capacity: Option<f64>,
+    location_volumes: HashMap<String, f64>,
+    location_capacities: HashMap<String, f64>,
+    travel_height: Option<f64>,
+    obstacle_heights: HashMap<String, f64>,
+    p_axis_position: f64,
+    xyz: (f64, f64, f64),
+    warnings: Vec<MachineError>,
+    shaker_on: HashMap<String, bool>,
+    temperature_on: HashMap<String, bool>,
+    device_temperature: HashMap<String, f64>,
+    current_temperature: HashMap<String, f64>,
+    elapsed_seconds: f64,
+    holding_plate: bool,
 }
 
-
 impl ScicloneG3 {
+    pub fn with_config(config: ScicloneConfig) -> Self {
+        ScicloneG3 {
+            config,
+            deck_location: None,
+            tips_loaded: false,
+            tip_volumes: vec![0.0],
+            air_volume: 0.0,
+            tip_capacity: None,
+            location_volumes: HashMap::new(),
+            location_capacities: HashMap::new(),
+            travel_height: None,
+            obstacle_heights: HashMap::new(),
+            p_axis_position: 0.0,
+            xyz: (0.0, 0.0, 0.0),
+            warnings: Vec::new(),
+            shaker_on: HashMap::new(),
+            temperature_on: HashMap::new(),
+            device_temperature: HashMap::new(),
+            current_temperature: HashMap::new(),
+            elapsed_seconds: 0.0,
+            holding_plate: false,
+        }
+    }
+
+    /// A machine whose head has this many channels, with the default configuration. Every channel
+    /// aspirates and dispenses the same volume, each from its own well, so the volume of a tracked
+    /// location is the volume of one of its wells. Panics if there are no channels.
+    pub fn with_channels(channels: usize) -> Self {
+        assert!(channels > 0, "a head needs at least one channel");
+        let mut machine = ScicloneG3::with_config(ScicloneConfig::default());
+        machine.tip_volumes = vec![0.0; channels];
+        machine
+    }
+
     pub fn aspirate(&mut self, volume: f64) -> Result<()> {
         self.assert_tips()?;
-        self.tip_volume = self.tip_volume + volume;
+        let volume = self.round_volume(volume);
+        self.assert_fits(self.get_tip_volume() + self.air_volume + volume)?;
+        if let Some(location) = &self.deck_location {
+            if let Some(source) = self.location_volumes.get_mut(location) {
+                let min_volume = self.config.min_source_volume;
+                if min_volume > 0.0 && *source - volume < min_volume {
+                    return Err(MachineError::SourceTooShallow(location.clone()));
+                }
+                if volume > *source {
+                    return Err(MachineError::NotEnoughWellVolume(location.clone()));
+                }
+                *source -= volume;
+            }
+        }
+        self.add_to_tips(volume);
+        Ok(())
+    }
+
+    /// Draw air into the tips. Air is held apart from the liquid, so it is not counted as volume
+    /// dispensed or left in the tips.
+    pub fn aspirate_air(&mut self, volume: f64) -> Result<()> {
+        self.assert_tips()?;
+        let volume = self.round_volume(volume);
+        self.assert_fits(self.get_tip_volume() + self.air_volume + volume)?;
+        self.air_volume += volume;
         Ok(())
     }
 
     pub fn dispense(&mut self, volume: Option<f64>) -> Result<()> {
         self.assert_tips()?;
+        let blow_out = volume.is_none();
         let volume = match volume {
-            Some(v) => v,
-            None => self.tip_volume
+            Some(v) => self.round_volume(v),
+            None => {
+                if self.get_tip_volume() == 0.0 {
+                    match self.config.dispense_empty {
+                        DispenseEmptyPolicy::Allow => {}
+                        DispenseEmptyPolicy::Warn => {
+                            self.warnings.push(MachineError::DispenseFromEmpty)
+                        }
+                        DispenseEmptyPolicy::Error => return Err(MachineError::DispenseFromEmpty),
+                    }
+                }
+                self.get_tip_volume()
+            }
         };
-        if volume > self.tip_volume {
+        self.assert_fits(volume)?;
+        if volume > self.get_tip_volume() {
             Err(MachineError::NotEnoughTipVolume)
         } else {
-            self.tip_volume = self.tip_volume - volume;
+            if let Some(location) = &self.deck_location {
+                if let Some(target) = self.location_volumes.get_mut(location) {
+                    match self.location_capacities.get(location) {
+                        Some(&capacity) if *target + volume > capacity => {
+                            return Err(MachineError::DestinationOverflow(location.clone()));
+                        }
+                        _ => *target += volume,
+                    }
+                }
+            }
+            self.add_to_tips(-volume);
+            // Dispensing all blows out the air as well, once the liquid went out
+            if blow_out {
+                self.air_volume = 0.0;
+            }
             Ok(())
         }
     }
 
-    pub fn eject_tips(&mut self) {
+    pub fn eject_tips(&mut self) -> Result<()> {
+        let volume = self.get_tip_volume();
+        if volume > self.config.max_eject_volume + self.volume_tolerance() {
+            return Err(MachineError::EjectWithVolume(volume));
+        }
         self.tips_loaded = false;
-        self.tip_volume = 0.0;
+        self.tip_volumes.fill(0.0);
+        self.air_volume = 0.0;
+        self.tip_capacity = None;
+        Ok(())
     }
 
     pub fn load_tips(&mut self) -> Result<()> {
@@ -97,8 +399,350 @@ impl ScicloneG3 {
         self.tips_loaded
     }
 
+    /// Volume held in each tip. All channels hold the same volume.
     pub fn get_tip_volume(&self) -> f64 {
-        self.tip_volume
+        self.tip_volumes.first().copied().unwrap_or(0.0)
+    }
+
+    /// Volume held in the tip of one channel, counting from 0
+    pub fn get_tip_volume_channel(&self, idx: usize) -> Option<f64> {
+        self.tip_volumes.get(idx).copied()
+    }
+
+    pub fn get_air_volume(&self) -> f64 {
+        self.air_volume
+    }
+
+    /// Problems that did not stop the machine, in the order they happened
+    pub fn get_warnings(&self) -> &[MachineError] {
+        &self.warnings
+    }
+
+    /// Start tracking the liquid volume in a deck location. Locations that are not tracked hold an
+    /// unlimited volume.
+    pub fn set_location_volume(&mut self, location: &str, volume: f64) {
+        self.location_volumes.insert(location.to_string(), volume);
+    }
+
+    pub fn get_location_volume(&self, location: &str) -> Option<f64> {
+        self.location_volumes.get(location).cloned()
+    }
+
+    /// The liquid in the well of a deck location, with nothing in locations that are not tracked
+    pub fn get_well_volume(&self, location: &str) -> f64 {
+        self.get_location_volume(location).unwrap_or(0.0)
+    }
+
+    /// Set the most a tracked deck location holds. Dispensing more into it fails.
+    pub fn set_location_capacity(&mut self, location: &str, capacity: f64) {
+        self.location_capacities
+            .insert(location.to_string(), capacity);
+    }
+
+    pub fn get_location_capacity(&self, location: &str) -> Option<f64> {
+        self.location_capacities.get(location).cloned()
+    }
+
+    /// Dispense into each location in turn. Nothing is dispensed, and the head does not move, if
+    /// the tips do not hold the total or a tracked location would overflow.
+    pub fn array_dispense(&mut self, destinations: &[(&str, f64)]) -> Result<()> {
+        self.assert_tips()?;
+        let destinations: Vec<(&str, f64)> = destinations
+            .iter()
+            .map(|&(location, volume)| (location, self.round_volume(volume)))
+            .collect();
+        let total: f64 = destinations.iter().map(|&(_, v)| v).sum();
+        if total > self.get_tip_volume() {
+            return Err(MachineError::NotEnoughTipVolume);
+        }
+        // A location may be given more than once, so it is checked with all it received so far
+        let mut received: HashMap<&str, f64> = HashMap::new();
+        for &(location, volume) in &destinations {
+            let added = received.entry(location).or_insert(0.0);
+            *added += volume;
+            let current = self.location_volumes.get(location);
+            let capacity = self.location_capacities.get(location);
+            if let (Some(current), Some(capacity)) = (current, capacity) {
+                if current + *added > *capacity {
+                    return Err(MachineError::DestinationOverflow(location.to_string()));
+                }
+            }
+        }
+        for (location, volume) in destinations {
+            self.move_to(location);
+            self.dispense(Some(volume))?;
+        }
+        Ok(())
+    }
+
+    /// Take hold of the plate at the current location with the gripper
+    pub fn pick(&mut self) -> Result<()> {
+        if self.holding_plate {
+            Err(MachineError::AlreadyHoldingPlate)
+        } else {
+            self.holding_plate = true;
+            Ok(())
+        }
+    }
+
+    /// Let go of the plate held by the gripper at the current location
+    pub fn place(&mut self) -> Result<()> {
+        if self.holding_plate {
+            self.holding_plate = false;
+            Ok(())
+        } else {
+            Err(MachineError::NotHoldingPlate)
+        }
+    }
+
+    pub fn get_holding_plate(&self) -> bool {
+        self.holding_plate
+    }
+
+    /// Set the height of what stands in a deck location, such as a stack of consumables, on the
+    /// same axis as the travel height
+    pub fn set_obstacle_height(&mut self, location: &str, height: f64) {
+        self.obstacle_heights.insert(location.to_string(), height);
+    }
+
+    /// Move the gantry by the distance along each axis. Nothing moves if the gantry would leave the
+    /// deck envelope.
+    pub fn relative_move(&mut self, dx: f64, dy: f64, dz: f64) -> Result<()> {
+        let (x, y, z) = self.xyz;
+        self.move_to_xyz((x + dx, y + dy, z + dz))
+    }
+
+    /// Move the gantry to the coordinates of the axes that are given, the others stay where they
+    /// are. Nothing moves if the gantry would leave the deck envelope.
+    pub fn absolute_move(&mut self, x: Option<f64>, y: Option<f64>, z: Option<f64>) -> Result<()> {
+        let (cx, cy, cz) = self.xyz;
+        self.move_to_xyz((x.unwrap_or(cx), y.unwrap_or(cy), z.unwrap_or(cz)))
+    }
+
+    fn move_to_xyz(&mut self, target: (f64, f64, f64)) -> Result<()> {
+        match self.config.deck_envelope {
+            Some(envelope) if !envelope.contains(target) => {
+                Err(MachineError::PositionOutOfBounds {
+                    x: target.0,
+                    y: target.1,
+                    z: target.2,
+                })
+            }
+            _ => {
+                self.xyz = target;
+                Ok(())
+            }
+        }
+    }
+
+    /// Coordinates of the gantry, in mm from where it started. Only relative and absolute moves
+    /// change them.
+    pub fn get_xyz(&self) -> (f64, f64, f64) {
+        self.xyz
+    }
+
+    /// Whether the shaker of a device is running. Devices are named by their DCC control, like
+    /// "ThermalLocator4".
+    pub fn get_shaker_on(&self, device: &str) -> bool {
+        self.shaker_on.get(device).cloned().unwrap_or(false)
+    }
+
+    /// Whether temperature control of a device is turned on
+    pub fn get_temperature_on(&self, device: &str) -> bool {
+        self.temperature_on.get(device).cloned().unwrap_or(false)
+    }
+
+    /// The temperature a device was last set to, in °C, whether or not its control is on
+    pub fn get_device_temperature(&self, device: &str) -> Option<f64> {
+        self.device_temperature.get(device).cloned()
+    }
+
+    /// The temperature a device has reached, in °C. Devices that have never been set or turned on
+    /// have none.
+    pub fn get_current_temperature(&self, device: &str) -> Option<f64> {
+        self.current_temperature.get(device).cloned()
+    }
+
+    /// Emulated time that timed instructions have taken so far
+    pub fn get_elapsed_seconds(&self) -> f64 {
+        self.elapsed_seconds
+    }
+
+    /// Let emulated time pass. Devices with temperature control on ramp towards the temperature
+    /// they were set to, and the others towards room temperature.
+    pub fn advance_time(&mut self, seconds: f64) {
+        self.elapsed_seconds += seconds;
+        self.ramp_temperatures(seconds);
+    }
+
+    fn ramp_temperatures(&mut self, seconds: f64) {
+        let mut devices: Vec<String> = self
+            .device_temperature
+            .keys()
+            .chain(self.temperature_on.keys())
+            .cloned()
+            .collect();
+        devices.sort();
+        devices.dedup();
+        let rate = self.config.temperature_ramp_rate;
+        for device in devices {
+            let goal = match self.temperature_on.get(&device) {
+                Some(true) => self
+                    .device_temperature
+                    .get(&device)
+                    .cloned()
+                    .unwrap_or(ROOM_TEMPERATURE),
+                _ => ROOM_TEMPERATURE,
+            };
+            let current = self
+                .current_temperature
+                .entry(device)
+                .or_insert(ROOM_TEMPERATURE);
+            *current = if rate > 0.0 {
+                let step = rate * seconds;
+                goal.clamp(*current - step, *current + step)
+            } else {
+                goal
+            };
+        }
+    }
+
+    /// Position of the pipettor plunger, zero when homed
+    pub fn get_p_axis_position(&self) -> f64 {
+        self.p_axis_position
+    }
+
+    pub fn get_travel_height(&self) -> Option<f64> {
+        self.travel_height
+    }
+
+    /// Carry material from one location to another. With a travel height set, every obstacle on
+    /// the way must be below it. The way is crudely taken to be the locations from one corner to
+    /// the other of the deck rectangle spanned by the two locations, or just the two locations if
+    /// they are not named by row and column like "C4".
+    pub fn move_material(&mut self, from: &str, to: &str) -> Result<()> {
+        if let Some(travel_height) = self.travel_height {
+            let mut blocking: Vec<&String> = self
+                .obstacle_heights
+                .iter()
+                .filter(|&(location, &height)| {
+                    height > travel_height && on_path(location, from, to)
+                })
+                .map(|(location, _)| location)
+                .collect();
+            blocking.sort();
+            if let Some(location) = blocking.first() {
+                return Err(MachineError::TravelHeightTooLow(location.to_string()));
+            }
+        }
+        self.move_to(from);
+        self.move_to(to);
+        Ok(())
+    }
+
+    fn operate(&mut self, exe: &Execute) -> Result<()> {
+        match *exe {
+            Execute::AbsoluteMove { x, y, z } => self.absolute_move(x, y, z)?,
+            Execute::ArrayDispense { ref destinations } => self.array_dispense(destinations)?,
+            Execute::Aspirate {
+                volume, air_gap, ..
+            } => {
+                self.aspirate(volume)?;
+                if let Some(air) = air_gap {
+                    self.aspirate_air(air)?;
+                }
+            }
+            Execute::BeginLoop { .. } => {}
+            Execute::Dispense {
+                position,
+                volume,
+                capacity,
+            } => {
+                if let Some(capacity) = capacity {
+                    self.set_location_capacity(position, capacity);
+                }
+                self.dispense(volume)?
+            }
+            Execute::EjectTips { .. } => self.eject_tips()?,
+            Execute::EndIf => {}
+            Execute::EndLoop => {}
+            Execute::EndWhile => {}
+            Execute::HeadPosition { .. } => {}
+            Execute::HomePAxis => self.p_axis_position = 0.0,
+            Execute::IfThen { .. } => {}
+            Execute::LoadTips { tip_size, .. } => {
+                self.load_tips()?;
+                self.tip_capacity = tip_size;
+            }
+            Execute::MathOperation { .. } => {}
+            Execute::Mix { .. } => {}
+            Execute::MoveMaterial { from, to } => self.move_material(from, to)?,
+            Execute::PAxisSetPosition { position } => self.p_axis_position = position,
+            Execute::Pick { .. } => self.pick()?,
+            Execute::Place { .. } => self.place()?,
+            Execute::REM { .. } => {}
+            Execute::RelativeMove { dx, dy, dz } => self.relative_move(dx, dy, dz)?,
+            Execute::RunMethod { .. } => {}
+            Execute::RunShakerForTime { seconds, .. } => self.advance_time(seconds),
+            Execute::SetTemperature {
+                device,
+                temperature,
+            } => {
+                self.device_temperature
+                    .insert(device.to_string(), temperature);
+                self.ramp_temperatures(0.0);
+            }
+            Execute::SetTravelHeight { height } => self.travel_height = Some(height),
+            Execute::ShakerOnOff { device, on } => {
+                self.shaker_on.insert(device.to_string(), on);
+            }
+            Execute::ShowDialog { .. } => {}
+            Execute::StringOperation { .. } => {}
+            Execute::TemperatureOnOff { device, on } => {
+                self.temperature_on.insert(device.to_string(), on);
+                self.ramp_temperatures(0.0);
+            }
+            Execute::Unknown { .. } => {}
+            Execute::WhileLoop { .. } => {}
+        }
+
+        Ok(())
+    }
+
+    // Channels all aspirate and dispense together, so a negative volume is dispensed from each
+    fn add_to_tips(&mut self, volume: f64) {
+        for tip_volume in &mut self.tip_volumes {
+            *tip_volume += volume;
+        }
+    }
+
+    // Volumes closer than this are the same, as far as the machine can tell
+    fn volume_tolerance(&self) -> f64 {
+        let resolution = self.config.volume_resolution;
+        if resolution > 0.0 {
+            resolution
+        } else {
+            VOLUME_EPSILON
+        }
+    }
+
+    fn round_volume(&self, volume: f64) -> f64 {
+        let resolution = self.config.volume_resolution;
+        if resolution > 0.0 {
+            (volume / resolution).round() / resolution.recip()
+        } else {
+            volume
+        }
+    }
+
+    // Tips of a known size cannot hold more than their size
+    fn assert_fits(&self, volume: f64) -> Result<()> {
+        match self.tip_capacity {
+            Some(capacity) if volume > capacity => {
+                Err(MachineError::TipOverflow { capacity, volume })
+            }
+            _ => Ok(()),
+        }
     }
 
     fn assert_tips(&self) -> Result<()> {
@@ -110,32 +754,1014 @@ impl ScicloneG3 {
     }
 }
 
+// The row letter and column number of a location named like "C4"
+fn deck_slot(location: &str) -> Option<(char, u32)> {
+    let mut chars = location.chars();
+    let row = chars.next().filter(|c| c.is_ascii_uppercase())?;
+    let column = chars.as_str().parse().ok()?;
+    Some((row, column))
+}
+
+fn on_path(location: &str, from: &str, to: &str) -> bool {
+    if location == from || location == to {
+        return true;
+    }
+    match (deck_slot(location), deck_slot(from), deck_slot(to)) {
+        (Some((row, column)), Some((row_a, column_a)), Some((row_b, column_b))) => {
+            row_a.min(row_b) <= row
+                && row <= row_a.max(row_b)
+                && column_a.min(column_b) <= column
+                && column <= column_a.max(column_b)
+        }
+        _ => false,
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, serde::Serialize)]
 pub enum Execute<'a> {
-    Aspirate { position: &'a str, volume: f64 },
-    // If None volume, dispense all
-    Dispense { position: &'a str, volume: Option<f64> },
-    EjectTips { position: &'a str },
-    LoadTips { position: &'a str },
-    Mix { position: &'a str },
-    REM { comment: &'a str },
+    // An axis that is not used stays where it is
+    AbsoluteMove {
+        x: Option<f64>,
+        y: Option<f64>,
+        z: Option<f64>,
+    },
+    // Moves to each location and dispenses the volume there
+    ArrayDispense {
+        destinations: Vec<(&'a str, f64)>,
+    },
+    // The air gap is the total air drawn before and after the liquid, if any
+    Aspirate {
+        position: &'a str,
+        volume: f64,
+        air_gap: Option<f64>,
+    },
+    // The bounds are the values at the start of the loop
+    BeginLoop {
+        index: Option<Uuid>,
+        from: f64,
+        to: f64,
+        step: f64,
+    },
+    // If None volume, dispense all. The capacity is the volume of a well at the position, if
+    // known.
+    Dispense {
+        position: &'a str,
+        volume: Option<f64>,
+        capacity: Option<f64>,
+    },
+    EjectTips {
+        position: &'a str,
+    },
+    EndIf,
+    EndLoop,
+    EndWhile,
+    HeadPosition {
+        position: &'a str,
+    },
+    HomePAxis,
+    // Whether the test of the If..Then holds
+    IfThen {
+        condition: bool,
+    },
+    // The size of the tips, if the tip box is of a known size
+    LoadTips {
+        position: &'a str,
+        tip_size: Option<f64>,
+    },
+    // The result to be stored in the variable
+    MathOperation {
+        variable: Uuid,
+        value: f64,
+    },
+    Mix {
+        position: &'a str,
+    },
+    MoveMaterial {
+        from: &'a str,
+        to: &'a str,
+    },
+    PAxisSetPosition {
+        position: f64,
+    },
+    // Grips the plate at the position
+    Pick {
+        position: &'a str,
+    },
+    // Releases the gripped plate at the position
+    Place {
+        position: &'a str,
+    },
+    REM {
+        comment: &'a str,
+    },
+    // An axis that is not used moves by zero
+    RelativeMove {
+        dx: f64,
+        dy: f64,
+        dz: f64,
+    },
+    RunMethod {
+        method: Uuid,
+    },
+    // The shaker stops when the time is up, so it leaves no state behind
+    RunShakerForTime {
+        speed: f64,
+        seconds: f64,
+    },
+    // The devices are named by their DCC control
+    SetTemperature {
+        device: &'a str,
+        temperature: f64,
+    },
+    SetTravelHeight {
+        height: f64,
+    },
+    ShakerOnOff {
+        device: &'a str,
+        on: bool,
+    },
+    ShowDialog {
+        text: &'a str,
+    },
+    // The string to be stored in the variable
+    StringOperation {
+        variable: Uuid,
+        value: String,
+    },
+    TemperatureOnOff {
+        device: &'a str,
+        on: bool,
+    },
+    // An instruction the loader did not know, or a commented out one that could not be lowered,
+    // which does nothing
+    Unknown {
+        designation: &'a str,
+    },
+    // Whether the test of the While Loop holds on this pass
+    WhileLoop {
+        condition: bool,
+    },
 }
 
+impl<'a> Execute<'a> {
+    /// The name of the instruction, without its values
+    pub fn name(&self) -> &'static str {
+        match self {
+            Execute::AbsoluteMove { .. } => "AbsoluteMove",
+            Execute::ArrayDispense { .. } => "ArrayDispense",
+            Execute::Aspirate { .. } => "Aspirate",
+            Execute::BeginLoop { .. } => "BeginLoop",
+            Execute::Dispense { .. } => "Dispense",
+            Execute::EjectTips { .. } => "EjectTips",
+            Execute::EndIf => "EndIf",
+            Execute::EndLoop => "EndLoop",
+            Execute::EndWhile => "EndWhile",
+            Execute::HeadPosition { .. } => "HeadPosition",
+            Execute::HomePAxis => "HomePAxis",
+            Execute::IfThen { .. } => "IfThen",
+            Execute::LoadTips { .. } => "LoadTips",
+            Execute::MathOperation { .. } => "MathOperation",
+            Execute::Mix { .. } => "Mix",
+            Execute::MoveMaterial { .. } => "MoveMaterial",
+            Execute::PAxisSetPosition { .. } => "PAxisSetPosition",
+            Execute::Pick { .. } => "Pick",
+            Execute::Place { .. } => "Place",
+            Execute::REM { .. } => "REM",
+            Execute::RelativeMove { .. } => "RelativeMove",
+            Execute::RunMethod { .. } => "RunMethod",
+            Execute::RunShakerForTime { .. } => "RunShakerForTime",
+            Execute::SetTemperature { .. } => "SetTemperature",
+            Execute::SetTravelHeight { .. } => "SetTravelHeight",
+            Execute::ShakerOnOff { .. } => "ShakerOnOff",
+            Execute::ShowDialog { .. } => "ShowDialog",
+            Execute::StringOperation { .. } => "StringOperation",
+            Execute::TemperatureOnOff { .. } => "TemperatureOnOff",
+            Execute::Unknown { .. } => "Unknown",
+            Execute::WhileLoop { .. } => "WhileLoop",
+        }
+    }
+
+    /// The deck location the instruction moves to, if any
+    pub fn position(&self) -> Option<&'a str> {
+        match *self {
+            Execute::AbsoluteMove { .. } => None,
+            Execute::ArrayDispense { .. } => None,
+            Execute::Aspirate { position, .. } => Some(position),
+            Execute::BeginLoop { .. } => None,
+            Execute::Dispense { position, .. } => Some(position),
+            Execute::EjectTips { position } => Some(position),
+            Execute::EndIf => None,
+            Execute::EndLoop => None,
+            Execute::EndWhile => None,
+            Execute::HeadPosition { position } => Some(position),
+            Execute::HomePAxis => None,
+            Execute::IfThen { .. } => None,
+            Execute::LoadTips { position, .. } => Some(position),
+            Execute::MathOperation { .. } => None,
+            Execute::Mix { position } => Some(position),
+            // Moves to both of its locations
+            Execute::MoveMaterial { .. } => None,
+            Execute::PAxisSetPosition { .. } => None,
+            Execute::Pick { position } => Some(position),
+            Execute::Place { position } => Some(position),
+            Execute::REM { .. } => None,
+            Execute::RelativeMove { .. } => None,
+            Execute::RunMethod { .. } => None,
+            Execute::RunShakerForTime { .. } => None,
+            Execute::SetTemperature { .. } => None,
+            Execute::SetTravelHeight { .. } => None,
+            Execute::ShakerOnOff { .. } => None,
+            Execute::ShowDialog { .. } => None,
+            Execute::StringOperation { .. } => None,
+            Execute::TemperatureOnOff { .. } => None,
+            Execute::Unknown { .. } => None,
+            Execute::WhileLoop { .. } => None,
+        }
+    }
+}
+
+/// Errors of the machine. New variants may be added, so matches outside this crate need a wildcard
+/// arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum MachineError {
+    AlreadyHoldingPlate,
+    DestinationOverflow(String),
+    DispenseFromEmpty,
+    EjectWithVolume(f64),
     NeedTips,
     NotEnoughTipVolume,
+    NotEnoughWellVolume(String),
+    NotHoldingPlate,
+    PositionOutOfBounds { x: f64, y: f64, z: f64 },
+    SourceTooShallow(String),
+    TipOverflow { capacity: f64, volume: f64 },
     TipsAlreadyLoaded,
+    TravelHeightTooLow(String),
 }
 
 impl std::fmt::Display for MachineError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::AlreadyHoldingPlate => write!(f, "picking a plate while holding one"),
+            Self::DestinationOverflow(location) => {
+                write!(f, "dispensing would overflow {}", location)
+            }
+            Self::DispenseFromEmpty => write!(f, "dispensing all from empty tips"),
+            Self::EjectWithVolume(volume) => {
+                write!(f, "ejecting tips that still hold {} uL", volume)
+            }
             Self::NeedTips => write!(f, "need tips on gantry to do this"),
             Self::NotEnoughTipVolume => write!(f, "not enough volume in tips"),
+            Self::NotHoldingPlate => write!(f, "placing a plate without holding one"),
+            Self::PositionOutOfBounds { x, y, z } => {
+                write!(f, "moving to ({}, {}, {}) leaves the deck", x, y, z)
+            }
+            Self::NotEnoughWellVolume(location) => {
+                write!(f, "not enough volume in {} to aspirate", location)
+            }
+            Self::SourceTooShallow(location) => {
+                write!(f, "too little liquid left to aspirate from {}", location)
+            }
+            Self::TipOverflow { capacity, volume } => {
+                write!(f, "{} uL does not fit in tips of {} uL", volume, capacity)
+            }
             Self::TipsAlreadyLoaded => write!(f, "trying to load tips twice"),
+            Self::TravelHeightTooLow(location) => {
+                write!(f, "travel height is too low to pass over {}", location)
+            }
         }
     }
 }
 
 impl std::error::Error for MachineError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipette_and_mix() -> Vec<Execute<'static>> {
+        vec![
+            Execute::LoadTips {
+                position: "C3",
+                tip_size: None,
+            },
+            Execute::HeadPosition { position: "A1" },
+            Execute::HeadPosition { position: "B2" },
+            Execute::Aspirate {
+                position: "C4",
+                volume: 100.0,
+                air_gap: None,
+            },
+            Execute::Aspirate {
+                position: "C4",
+                volume: 20.0,
+                air_gap: None,
+            },
+            Execute::Dispense {
+                position: "B4",
+                volume: Some(50.0),
+                capacity: None,
+            },
+            Execute::Mix { position: "B4" },
+            Execute::REM { comment: "done" },
+            Execute::EjectTips { position: "D5" },
+            Execute::LoadTips {
+                position: "C3",
+                tip_size: None,
+            },
+            Execute::Aspirate {
+                position: "C4",
+                volume: 10.0,
+                air_gap: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn batch_matches_single_execution() {
+        // The tips are ejected with the mixed residual still in them
+        let exes = pipette_and_mix();
+        let config = ScicloneConfig {
+            max_eject_volume: f64::INFINITY,
+            ..ScicloneConfig::default()
+        };
+
+        let mut single = ScicloneG3::with_config(config.clone());
+        for exe in &exes {
+            single.execute(exe).unwrap();
+        }
+
+        let mut batch = ScicloneG3::with_config(config);
+        batch.execute_batch(&exes).unwrap();
+
+        assert_eq!(batch.get_deck_location(), single.get_deck_location());
+        assert_eq!(batch.get_tips_loaded(), single.get_tips_loaded());
+        assert_eq!(batch.get_tip_volume(), single.get_tip_volume());
+        assert_eq!(batch.get_deck_location(), Some(&"C4".to_string()));
+        assert_eq!(batch.get_tip_volume(), 10.0);
+    }
+
+    #[test]
+    fn batch_ends_at_last_head_position() {
+        let exes = vec![
+            Execute::HeadPosition { position: "A1" },
+            Execute::HeadPosition { position: "B2" },
+            Execute::HeadPosition { position: "C3" },
+        ];
+        let mut machine = ScicloneG3::new();
+        machine.execute_batch(&exes).unwrap();
+        assert_eq!(machine.get_deck_location(), Some(&"C3".to_string()));
+    }
+
+    #[test]
+    fn batch_stops_at_first_error() {
+        let exes = vec![
+            Execute::Aspirate {
+                position: "C4",
+                volume: 100.0,
+                air_gap: None,
+            },
+            Execute::LoadTips {
+                position: "C3",
+                tip_size: None,
+            },
+        ];
+        let mut machine = ScicloneG3::new();
+        assert!(matches!(
+            machine.execute_batch(&exes),
+            Err(MachineError::NeedTips)
+        ));
+        assert!(!machine.get_tips_loaded());
+    }
+
+    #[test]
+    fn volume_resolution_rounds_volumes() {
+        let config = ScicloneConfig {
+            volume_resolution: 0.1,
+            ..ScicloneConfig::default()
+        };
+        let mut machine = ScicloneG3::with_config(config);
+        machine.load_tips().unwrap();
+        machine.aspirate(10.04).unwrap();
+        assert_eq!(machine.get_tip_volume(), 10.0);
+
+        let mut machine = ScicloneG3::new();
+        machine.load_tips().unwrap();
+        machine.aspirate(10.04).unwrap();
+        assert_eq!(machine.get_tip_volume(), 10.04);
+    }
+
+    #[test]
+    fn array_dispense() {
+        let mut machine = ScicloneG3::new();
+        machine.set_location_volume("B4", 0.0);
+        machine.load_tips().unwrap();
+        machine.aspirate(100.0).unwrap();
+
+        let too_much = Execute::ArrayDispense {
+            destinations: vec![("B4", 50.0), ("B5", 60.0)],
+        };
+        assert!(matches!(
+            machine.execute(&too_much),
+            Err(MachineError::NotEnoughTipVolume)
+        ));
+        assert_eq!(machine.get_tip_volume(), 100.0);
+
+        let three_way = Execute::ArrayDispense {
+            destinations: vec![("B4", 20.0), ("B5", 30.0), ("B6", 25.0)],
+        };
+        machine.execute(&three_way).unwrap();
+        assert_eq!(machine.get_tip_volume(), 25.0);
+        assert_eq!(machine.get_location_volume("B4"), Some(20.0));
+        assert_eq!(machine.get_deck_location(), Some(&"B6".to_string()));
+    }
+
+    #[test]
+    fn array_dispense_past_well_capacity() {
+        let mut machine = ScicloneG3::new();
+        machine.set_location_volume("B4", 0.0);
+        machine.set_location_volume("B5", 0.0);
+        machine.set_location_capacity("B5", 40.0);
+        machine.load_tips().unwrap();
+        machine.move_to("C4");
+        machine.aspirate(100.0).unwrap();
+
+        // B4 takes its share before B5 would overflow, and B5 overflows only with both of its
+        let overflow = Execute::ArrayDispense {
+            destinations: vec![("B4", 20.0), ("B5", 30.0), ("B5", 20.0)],
+        };
+        assert!(matches!(
+            machine.execute(&overflow),
+            Err(MachineError::DestinationOverflow(location)) if location == "B5"
+        ));
+        assert_eq!(machine.get_tip_volume(), 100.0);
+        assert_eq!(machine.get_location_volume("B4"), Some(0.0));
+        assert_eq!(machine.get_location_volume("B5"), Some(0.0));
+        assert_eq!(machine.get_deck_location(), Some(&"C4".to_string()));
+    }
+
+    #[test]
+    fn dispense_past_well_capacity() {
+        let mut machine = ScicloneG3::new();
+        machine.set_location_volume("B4", 20.0);
+        machine
+            .execute(&Execute::LoadTips {
+                position: "C3",
+                tip_size: None,
+            })
+            .unwrap();
+        machine.move_to("C4");
+        machine.aspirate(50.0).unwrap();
+
+        let dispense = |volume| Execute::Dispense {
+            position: "B4",
+            volume: Some(volume),
+            capacity: Some(50.0),
+        };
+        machine.execute(&dispense(30.0)).unwrap();
+        assert_eq!(machine.get_location_capacity("B4"), Some(50.0));
+        assert!(matches!(
+            machine.execute(&dispense(10.0)),
+            Err(MachineError::DestinationOverflow(location)) if location == "B4"
+        ));
+        // Nothing is dispensed into a full well
+        assert_eq!(machine.get_location_volume("B4"), Some(50.0));
+        assert_eq!(machine.get_tip_volume(), 20.0);
+    }
+
+    #[test]
+    fn travel_height_too_low() {
+        let mut machine = ScicloneG3::new();
+        machine.set_obstacle_height("C4", 80.0);
+        machine.set_obstacle_height("E4", 120.0);
+        let move_c3_to_d5 = Execute::MoveMaterial {
+            from: "C3",
+            to: "D5",
+        };
+
+        // Without a travel height there is nothing to check
+        machine.execute(&move_c3_to_d5).unwrap();
+
+        machine
+            .execute(&Execute::SetTravelHeight { height: 10.0 })
+            .unwrap();
+        assert!(matches!(
+            machine.execute(&move_c3_to_d5),
+            Err(MachineError::TravelHeightTooLow(location)) if location == "C4"
+        ));
+
+        machine
+            .execute(&Execute::SetTravelHeight { height: 100.0 })
+            .unwrap();
+        machine.execute(&move_c3_to_d5).unwrap();
+        assert_eq!(machine.get_deck_location(), Some(&"D5".to_string()));
+        assert_eq!(machine.get_travel_height(), Some(100.0));
+    }
+
+    #[test]
+    fn overfill_small_tips() {
+        let mut machine = ScicloneG3::new();
+        machine
+            .execute(&Execute::LoadTips {
+                position: "C3",
+                tip_size: Some(20.0),
+            })
+            .unwrap();
+        machine
+            .execute(&Execute::Aspirate {
+                position: "C4",
+                volume: 15.0,
+                air_gap: None,
+            })
+            .unwrap();
+        assert!(matches!(
+            machine.execute(&Execute::Aspirate {
+                position: "C4",
+                volume: 10.0,
+                air_gap: None,
+            }),
+            Err(MachineError::TipOverflow { capacity, volume })
+                if capacity == 20.0 && volume == 25.0
+        ));
+        assert_eq!(machine.get_tip_volume(), 15.0);
+
+        // Tips of unknown size take any volume
+        machine.dispense(None).unwrap();
+        machine.eject_tips().unwrap();
+        machine
+            .execute(&Execute::LoadTips {
+                position: "C3",
+                tip_size: None,
+            })
+            .unwrap();
+        machine.aspirate(500.0).unwrap();
+    }
+
+    #[test]
+    fn air_gap_apart_from_liquid() {
+        let mut machine = ScicloneG3::new();
+        machine
+            .execute(&Execute::LoadTips {
+                position: "C3",
+                tip_size: None,
+            })
+            .unwrap();
+        machine
+            .execute(&Execute::Aspirate {
+                position: "C4",
+                volume: 50.0,
+                air_gap: Some(5.0),
+            })
+            .unwrap();
+        assert_eq!(machine.get_tip_volume(), 50.0);
+        assert_eq!(machine.get_air_volume(), 5.0);
+
+        machine
+            .execute(&Execute::Dispense {
+                position: "B4",
+                volume: Some(50.0),
+                capacity: None,
+            })
+            .unwrap();
+        assert_eq!(machine.get_tip_volume(), 0.0);
+        assert_eq!(machine.get_air_volume(), 5.0);
+        assert!(matches!(
+            machine.dispense(Some(1.0)),
+            Err(MachineError::NotEnoughTipVolume)
+        ));
+
+        machine.dispense(None).unwrap();
+        assert_eq!(machine.get_air_volume(), 0.0);
+    }
+
+    #[test]
+    fn failed_dispense_all_keeps_air() {
+        let mut machine = ScicloneG3::new();
+        machine.set_location_volume("B4", 45.0);
+        machine
+            .execute(&Execute::LoadTips {
+                position: "C3",
+                tip_size: None,
+            })
+            .unwrap();
+        machine
+            .execute(&Execute::Aspirate {
+                position: "C4",
+                volume: 10.0,
+                air_gap: Some(5.0),
+            })
+            .unwrap();
+
+        let dispense_all = Execute::Dispense {
+            position: "B4",
+            volume: None,
+            capacity: Some(50.0),
+        };
+        assert!(matches!(
+            machine.execute(&dispense_all),
+            Err(MachineError::DestinationOverflow(location)) if location == "B4"
+        ));
+        assert_eq!(machine.get_tip_volume(), 10.0);
+        assert_eq!(machine.get_air_volume(), 5.0);
+    }
+
+    #[test]
+    fn p_axis_set_and_home() {
+        let mut machine = ScicloneG3::new();
+        assert_eq!(machine.get_p_axis_position(), 0.0);
+        machine
+            .execute(&Execute::PAxisSetPosition { position: 12.5 })
+            .unwrap();
+        assert_eq!(machine.get_p_axis_position(), 12.5);
+        machine.execute(&Execute::HomePAxis).unwrap();
+        assert_eq!(machine.get_p_axis_position(), 0.0);
+    }
+
+    #[test]
+    fn restore_before_aspirate() {
+        let mut machine = ScicloneG3::new();
+        machine.set_location_volume("C4", 200.0);
+        machine
+            .execute(&Execute::LoadTips {
+                position: "C3",
+                tip_size: None,
+            })
+            .unwrap();
+        let snap = machine.snapshot();
+        machine
+            .execute(&Execute::Aspirate {
+                position: "C4",
+                volume: 50.0,
+                air_gap: Some(5.0),
+            })
+            .unwrap();
+        assert_eq!(machine.get_tip_volume(), 50.0);
+
+        machine.restore(&snap);
+        assert_eq!(machine.get_tip_volume(), 0.0);
+        assert_eq!(machine.get_air_volume(), 0.0);
+        assert!(machine.get_tips_loaded());
+        assert_eq!(machine.get_deck_location().unwrap(), "C3");
+        assert_eq!(machine.get_location_volume("C4"), Some(200.0));
+        assert_eq!(machine.snapshot(), snap);
+    }
+
+    #[test]
+    fn eight_channel_aspirate() {
+        let mut machine = ScicloneG3::with_channels(8);
+        machine.set_location_volume("C4", 200.0);
+        machine
+            .execute(&Execute::LoadTips {
+                position: "C3",
+                tip_size: None,
+            })
+            .unwrap();
+        machine
+            .execute(&Execute::Aspirate {
+                position: "C4",
+                volume: 50.0,
+                air_gap: None,
+            })
+            .unwrap();
+        for channel in 0..8 {
+            assert_eq!(machine.get_tip_volume_channel(channel), Some(50.0));
+        }
+        assert_eq!(machine.get_tip_volume_channel(8), None);
+        assert_eq!(machine.get_location_volume("C4"), Some(150.0));
+
+        machine
+            .execute(&Execute::Dispense {
+                position: "D5",
+                volume: Some(20.0),
+                capacity: None,
+            })
+            .unwrap();
+        for channel in 0..8 {
+            assert_eq!(machine.get_tip_volume_channel(channel), Some(30.0));
+        }
+    }
+
+    #[test]
+    fn eject_with_volume_over_threshold() {
+        let config = ScicloneConfig {
+            max_eject_volume: 5.0,
+            ..ScicloneConfig::default()
+        };
+        let mut machine = ScicloneG3::with_config(config);
+        machine.load_tips().unwrap();
+        machine.aspirate(90.0).unwrap();
+
+        let eject = Execute::EjectTips { position: "D5" };
+        assert!(matches!(
+            machine.execute(&eject),
+            Err(MachineError::EjectWithVolume(volume)) if volume == 90.0
+        ));
+        assert!(machine.get_tips_loaded());
+
+        // A small residual is ejected
+        machine.dispense(Some(88.0)).unwrap();
+        machine.execute(&eject).unwrap();
+        assert!(!machine.get_tips_loaded());
+    }
+
+    #[test]
+    fn eject_within_volume_tolerance() {
+        // 0.1 + 0.2 - 0.3 leaves a rounding error in the tips, which still count as empty
+        let mut machine = ScicloneG3::new();
+        machine.load_tips().unwrap();
+        machine.aspirate(0.1).unwrap();
+        machine.aspirate(0.2).unwrap();
+        machine.dispense(Some(0.3)).unwrap();
+        assert!(machine.get_tip_volume() > 0.0);
+        machine.eject_tips().unwrap();
+
+        machine.load_tips().unwrap();
+        machine.aspirate(0.5).unwrap();
+        assert!(matches!(
+            machine.eject_tips(),
+            Err(MachineError::EjectWithVolume(volume)) if volume == 0.5
+        ));
+
+        // Within the resolution of the machine
+        let mut machine = ScicloneG3::with_config(ScicloneConfig {
+            volume_resolution: 1.0,
+            ..ScicloneConfig::default()
+        });
+        machine.load_tips().unwrap();
+        machine.aspirate(3.0).unwrap();
+        machine.dispense(Some(2.0)).unwrap();
+        machine.eject_tips().unwrap();
+    }
+
+    #[test]
+    fn aspirate_from_seeded_well() {
+        let mut machine = ScicloneG3::new();
+        machine.set_location_volume("C4", 100.0);
+        machine.load_tips().unwrap();
+        let aspirate = |volume| Execute::Aspirate {
+            position: "C4",
+            volume,
+            air_gap: None,
+        };
+        machine.execute(&aspirate(60.0)).unwrap();
+        assert_eq!(machine.get_tip_volume(), 60.0);
+        assert_eq!(machine.get_location_volume("C4"), Some(40.0));
+        assert_eq!(machine.state().location_volumes["C4"], 40.0);
+
+        assert!(matches!(
+            machine.execute(&aspirate(50.0)),
+            Err(MachineError::NotEnoughWellVolume(location)) if location == "C4"
+        ));
+        assert_eq!(machine.get_tip_volume(), 60.0);
+        assert_eq!(machine.get_well_volume("C4"), 40.0);
+        assert_eq!(machine.get_well_volume("D5"), 0.0);
+    }
+
+    #[test]
+    fn dispense_all_from_empty_tips() {
+        let dispense_all = Execute::Dispense {
+            position: "B4",
+            volume: None,
+            capacity: None,
+        };
+        let with_policy = |dispense_empty| {
+            let mut machine = ScicloneG3::with_config(ScicloneConfig {
+                dispense_empty,
+                ..ScicloneConfig::default()
+            });
+            machine.load_tips().unwrap();
+            machine
+        };
+
+        let mut machine = with_policy(DispenseEmptyPolicy::Error);
+        assert!(matches!(
+            machine.execute(&dispense_all),
+            Err(MachineError::DispenseFromEmpty)
+        ));
+        machine.aspirate(10.0).unwrap();
+        machine.execute(&dispense_all).unwrap();
+
+        let mut machine = with_policy(DispenseEmptyPolicy::Warn);
+        machine.execute(&dispense_all).unwrap();
+        assert!(matches!(
+            machine.get_warnings(),
+            [MachineError::DispenseFromEmpty]
+        ));
+
+        let mut machine = with_policy(DispenseEmptyPolicy::Allow);
+        machine.execute(&dispense_all).unwrap();
+        assert!(machine.get_warnings().is_empty());
+    }
+
+    #[test]
+    fn pick_then_place() {
+        let mut machine = ScicloneG3::new();
+        machine.execute(&Execute::Pick { position: "C4" }).unwrap();
+        assert!(machine.get_holding_plate());
+        assert!(matches!(
+            machine.execute(&Execute::Pick { position: "B4" }),
+            Err(MachineError::AlreadyHoldingPlate)
+        ));
+
+        machine.execute(&Execute::Place { position: "B4" }).unwrap();
+        assert!(!machine.get_holding_plate());
+        assert_eq!(machine.get_deck_location().unwrap(), "B4");
+        assert!(matches!(
+            machine.execute(&Execute::Place { position: "B4" }),
+            Err(MachineError::NotHoldingPlate)
+        ));
+    }
+
+    #[test]
+    fn heat_device_to_37() {
+        let mut machine = ScicloneG3::new();
+        assert_eq!(machine.get_device_temperature("ThermalLocator1"), None);
+        machine
+            .execute(&Execute::SetTemperature {
+                device: "ThermalLocator1",
+                temperature: 37.0,
+            })
+            .unwrap();
+        assert!(!machine.get_temperature_on("ThermalLocator1"));
+        machine
+            .execute(&Execute::TemperatureOnOff {
+                device: "ThermalLocator1",
+                on: true,
+            })
+            .unwrap();
+
+        assert_eq!(
+            machine.get_device_temperature("ThermalLocator1"),
+            Some(37.0)
+        );
+        assert!(machine.get_temperature_on("ThermalLocator1"));
+        assert!(!machine.get_shaker_on("ThermalLocator1"));
+        assert!(!machine.get_temperature_on("ThermalLocator4"));
+
+        let state = machine.state();
+        assert_eq!(state.device_temperature["ThermalLocator1"], 37.0);
+        assert!(state.temperature_on["ThermalLocator1"]);
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(json["device_temperature"]["ThermalLocator1"], 37.0);
+        assert!(json.get("shaker_on").is_none());
+    }
+
+    #[test]
+    fn ramp_to_37() {
+        let mut machine = ScicloneG3::with_config(ScicloneConfig {
+            temperature_ramp_rate: 0.5,
+            ..ScicloneConfig::default()
+        });
+        let shake = |seconds| Execute::RunShakerForTime {
+            speed: 500.0,
+            seconds,
+        };
+        machine
+            .execute(&Execute::SetTemperature {
+                device: "ThermalLocator1",
+                temperature: 37.0,
+            })
+            .unwrap();
+        machine
+            .execute(&Execute::TemperatureOnOff {
+                device: "ThermalLocator1",
+                on: true,
+            })
+            .unwrap();
+        assert_eq!(
+            machine.get_current_temperature("ThermalLocator1"),
+            Some(22.0)
+        );
+
+        machine.execute(&shake(10.0)).unwrap();
+        assert_eq!(
+            machine.get_current_temperature("ThermalLocator1"),
+            Some(27.0)
+        );
+        let state = machine.state();
+        assert_eq!(state.current_temperature["ThermalLocator1"], 27.0);
+        assert_eq!(state.elapsed_seconds, 10.0);
+        machine.execute(&shake(60.0)).unwrap();
+        assert_eq!(
+            machine.get_current_temperature("ThermalLocator1"),
+            Some(37.0)
+        );
+        assert_eq!(machine.get_elapsed_seconds(), 70.0);
+
+        machine
+            .execute(&Execute::TemperatureOnOff {
+                device: "ThermalLocator1",
+                on: false,
+            })
+            .unwrap();
+        machine.advance_time(4.0);
+        assert_eq!(
+            machine.get_current_temperature("ThermalLocator1"),
+            Some(35.0)
+        );
+        assert_eq!(machine.get_current_temperature("ThermalLocator4"), None);
+    }
+
+    #[test]
+    fn relative_move_out_of_bounds() {
+        let config = ScicloneConfig {
+            deck_envelope: Some(DeckEnvelope {
+                min: (0.0, 0.0, -150.0),
+                max: (600.0, 400.0, 0.0),
+            }),
+            ..ScicloneConfig::default()
+        };
+        let mut machine = ScicloneG3::with_config(config);
+        let step = |dx, dz| Execute::RelativeMove { dx, dy: 0.0, dz };
+        machine.execute(&step(250.0, -100.0)).unwrap();
+        machine.execute(&step(250.0, 0.0)).unwrap();
+        assert_eq!(machine.get_xyz(), (500.0, 0.0, -100.0));
+
+        assert!(matches!(
+            machine.execute(&step(250.0, 0.0)),
+            Err(MachineError::PositionOutOfBounds { x, .. }) if x == 750.0
+        ));
+        assert!(matches!(
+            machine.execute(&step(0.0, -60.0)),
+            Err(MachineError::PositionOutOfBounds { z, .. }) if z == -160.0
+        ));
+        assert_eq!(machine.get_xyz(), (500.0, 0.0, -100.0));
+    }
+
+    #[test]
+    fn absolute_move_keeps_unused_axes() {
+        let config = ScicloneConfig {
+            deck_envelope: Some(DeckEnvelope {
+                min: (0.0, 0.0, -150.0),
+                max: (600.0, 400.0, 0.0),
+            }),
+            ..ScicloneConfig::default()
+        };
+        let mut machine = ScicloneG3::with_config(config);
+        machine
+            .execute(&Execute::RelativeMove {
+                dx: 100.0,
+                dy: 50.0,
+                dz: -20.0,
+            })
+            .unwrap();
+        machine
+            .execute(&Execute::AbsoluteMove {
+                x: Some(300.0),
+                y: None,
+                z: Some(0.0),
+            })
+            .unwrap();
+        assert_eq!(machine.get_xyz(), (300.0, 50.0, 0.0));
+
+        assert!(matches!(
+            machine.execute(&Execute::AbsoluteMove {
+                x: None,
+                y: Some(500.0),
+                z: None,
+            }),
+            Err(MachineError::PositionOutOfBounds { y, .. }) if y == 500.0
+        ));
+        assert_eq!(machine.get_xyz(), (300.0, 50.0, 0.0));
+    }
+
+    #[test]
+    fn drain_source_below_threshold() {
+        let config = ScicloneConfig {
+            min_source_volume: 20.0,
+            ..ScicloneConfig::default()
+        };
+        let mut machine = ScicloneG3::with_config(config);
+        machine.set_location_volume("C4", 200.0);
+        machine
+            .execute(&Execute::LoadTips {
+                position: "C3",
+                tip_size: None,
+            })
+            .unwrap();
+
+        let aspirate = Execute::Aspirate {
+            position: "C4",
+            volume: 30.0,
+            air_gap: None,
+        };
+        let dispense = Execute::Dispense {
+            position: "B4",
+            volume: None,
+            capacity: None,
+        };
+        for _ in 0..6 {
+            machine.execute(&aspirate).unwrap();
+            machine.execute(&dispense).unwrap();
+        }
+        assert_eq!(machine.get_location_volume("C4"), Some(20.0));
+        assert!(matches!(
+            machine.execute(&aspirate),
+            Err(MachineError::SourceTooShallow(location)) if location == "C4"
+        ));
+        assert_eq!(machine.get_location_volume("C4"), Some(20.0));
+        assert_eq!(machine.get_location_volume("B4"), None);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::machine::{Execute, Machine, MachineError, MachineSnapshot, MachineState};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// A machine that only records the instructions it receives, for testing code that drives the
+/// emulator. It never changes state, so it reports no location, no tips and no volume.
+#[derive(Debug, Default)]
+pub struct RecordingMachine {
+    pub executed: Vec<OwnedExecute>,
+}
+
+impl Machine for RecordingMachine {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn execute(&mut self, exe: &Execute) -> Result<(), MachineError> {
+        self.executed.push(exe.into());
+        Ok(())
+    }
+
+    fn tip_volume(&self) -> f64 {
+        0.0
+    }
+
+    fn state(&self) -> MachineState {
+        MachineState {
+            deck_location: None,
+            tips_loaded: false,
+            tip_volume: 0.0,
+            location_volumes: BTreeMap::new(),
+            shaker_on: BTreeMap::new(),
+            temperature_on: BTreeMap::new(),
+            device_temperature: BTreeMap::new(),
+            current_temperature: BTreeMap::new(),
+            elapsed_seconds: 0.0,
+        }
+    }
+
+    fn snapshot(&self) -> MachineSnapshot {
+        MachineSnapshot::default()
+    }
+
+    // What was executed is a record, not state, so it is kept
+    fn restore(&mut self, _: &MachineSnapshot) {}
+}
+
+/// An `Execute` that owns its text, so it can outlive the application it was lowered from
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum OwnedExecute {
+    AbsoluteMove {
+        x: Option<f64>,
+        y: Option<f64>,
+        z: Option<f64>,
+    },
+    ArrayDispense {
+        destinations: Vec<(String, f64)>,
+    },
+    Aspirate {
+        position: String,
+        volume: f64,
+        air_gap: Option<f64>,
+    },
+    BeginLoop {
+        index: Option<Uuid>,
+        from: f64,
+        to: f64,
+        step: f64,
+    },
+    Dispense {
+        position: String,
+        volume: Option<f64>,
+        capacity: Option<f64>,
+    },
+    EjectTips {
+        position: String,
+    },
+    EndIf,
+    EndLoop,
+    EndWhile,
+    HeadPosition {
+        position: String,
+    },
+    HomePAxis,
+    IfThen {
+        condition: bool,
+    },
+    LoadTips {
+        position: String,
+        tip_size: Option<f64>,
+    },
+    MathOperation {
+        variable: Uuid,
+        value: f64,
+    },
+    Mix {
+        position: String,
+    },
+    MoveMaterial {
+        from: String,
+        to: String,
+    },
+    PAxisSetPosition {
+        position: f64,
+    },
+    Pick {
+        position: String,
+    },
+    Place {
+        position: String,
+    },
+    REM {
+        comment: String,
+    },
+    RelativeMove {
+        dx: f64,
+        dy: f64,
+        dz: f64,
+    },
+    RunMethod {
+        method: Uuid,
+    },
+    RunShakerForTime {
+        speed: f64,
+        seconds: f64,
+    },
+    SetTemperature {
+        device: String,
+        temperature: f64,
+    },
+    SetTravelHeight {
+        height: f64,
+    },
+    ShakerOnOff {
+        device: String,
+        on: bool,
+    },
+    ShowDialog {
+        text: String,
+    },
+    StringOperation {
+        variable: Uuid,
+        value: String,
+    },
+    TemperatureOnOff {
+        device: String,
+        on: bool,
+    },
+    Unknown {
+        designation: String,
+    },
+    WhileLoop {
+        condition: bool,
+    },
+}
+
+impl From<&Execute<'_>> for OwnedExecute {
+    fn from(exe: &Execute) -> Self {
+        match *exe {
+            Execute::AbsoluteMove { x, y, z } => OwnedExecute::AbsoluteMove { x, y, z },
+            Execute::ArrayDispense { ref destinations } => OwnedExecute::ArrayDispense {
+                destinations: destinations
+                    .iter()
+                    .map(|&(position, volume)| (position.to_string(), volume))
+                    .collect(),
+            },
+            Execute::Aspirate {
+                position,
+                volume,
+                air_gap,
+            } => OwnedExecute::Aspirate {
+                position: position.to_string(),
+                volume,
+                air_gap,
+            },
+            Execute::BeginLoop {
+                index,
+                from,
+                to,
+                step,
+            } => OwnedExecute::BeginLoop {
+                index,
+                from,
+                to,
+                step,
+            },
+            Execute::Dispense {
+                position,
+                volume,
+                capacity,
+            } => OwnedExecute::Dispense {
+                position: position.to_string(),
+                volume,
+                capacity,
+            },
+            Execute::EjectTips { position } => OwnedExecute::EjectTips {
+                position: position.to_string(),
+            },
+            Execute::EndIf => OwnedExecute::EndIf,
+            Execute::EndLoop => OwnedExecute::EndLoop,
+            Execute::EndWhile => OwnedExecute::EndWhile,
+            Execute::HeadPosition { position } => OwnedExecute::HeadPosition {
+                position: position.to_string(),
+            },
+            Execute::HomePAxis => OwnedExecute::HomePAxis,
+            Execute::IfThen { condition } => OwnedExecute::IfThen { condition },
+            Execute::LoadTips { position, tip_size } => OwnedExecute::LoadTips {
+                position: position.to_string(),
+                tip_size,
+            },
+            Execute::MathOperation { variable, value } => {
+                OwnedExecute::MathOperation { variable, value }
+            }
+            Execute::Mix { position } => OwnedExecute::Mix {
+                position: position.to_string(),
+            },
+            Execute::MoveMaterial { from, to } => OwnedExecute::MoveMaterial {
+                from: from.to_string(),
+                to: to.to_string(),
+            },
+            Execute::PAxisSetPosition { position } => OwnedExecute::PAxisSetPosition { position },
+            Execute::Pick { position } => OwnedExecute::Pick {
+                position: position.to_string(),
+            },
+            Execute::Place { position } => OwnedExecute::Place {
+                position: position.to_string(),
+            },
+            Execute::REM { comment } => OwnedExecute::REM {
+                comment: comment.to_string(),
+            },
+            Execute::RelativeMove { dx, dy, dz } => OwnedExecute::RelativeMove { dx, dy, dz },
+            Execute::RunMethod { method } => OwnedExecute::RunMethod { method },
+            Execute::RunShakerForTime { speed, seconds } => {
+                OwnedExecute::RunShakerForTime { speed, seconds }
+            }
+            Execute::SetTemperature {
+                device,
+                temperature,
+            } => OwnedExecute::SetTemperature {
+                device: device.to_string(),
+                temperature,
+            },
+            Execute::SetTravelHeight { height } => OwnedExecute::SetTravelHeight { height },
+            Execute::ShakerOnOff { device, on } => OwnedExecute::ShakerOnOff {
+                device: device.to_string(),
+                on,
+            },
+            Execute::ShowDialog { text } => OwnedExecute::ShowDialog {
+                text: text.to_string(),
+            },
+            Execute::StringOperation {
+                variable,
+                ref value,
+            } => OwnedExecute::StringOperation {
+                variable,
+                value: value.clone(),
+            },
+            Execute::TemperatureOnOff { device, on } => OwnedExecute::TemperatureOnOff {
+                device: device.to_string(),
+                on,
+            },
+            Execute::Unknown { designation } => OwnedExecute::Unknown {
+                designation: designation.to_string(),
+            },
+            Execute::WhileLoop { condition } => OwnedExecute::WhileLoop { condition },
+        }
+    }
+}
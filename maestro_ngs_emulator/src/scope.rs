@@ -0,0 +1,49 @@
+use crate::{EmulatorError, Result};
+use maestro_ngs_application::{InstructionValue, Variable, VariableValue};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// The variables visible to an instruction. A variable is looked up in the method's local
+/// variables first, then the method's parameters and finally the global variables.
+pub struct Scope<'s> {
+    locals: &'s HashMap<Uuid, Variable>,
+    params: &'s HashMap<Uuid, Variable>,
+    globals: &'s HashMap<Uuid, Variable>,
+}
+
+impl<'s> Scope<'s> {
+    pub fn new(
+        locals: &'s HashMap<Uuid, Variable>,
+        params: &'s HashMap<Uuid, Variable>,
+        globals: &'s HashMap<Uuid, Variable>,
+    ) -> Self {
+        Scope {
+            locals,
+            params,
+            globals,
+        }
+    }
+
+    /// The variable visible under this id
+    pub fn variable(&self, uuid: Uuid) -> Option<&'s Variable> {
+        self.locals
+            .get(&uuid)
+            .or_else(|| self.params.get(&uuid))
+            .or_else(|| self.globals.get(&uuid))
+    }
+
+    /// The value of an instruction value. If the instruction value references a variable, the
+    /// variable's value is used instead of the direct value.
+    pub fn resolve<'v>(&self, inst: &'v InstructionValue) -> Result<&'v VariableValue>
+    where
+        's: 'v,
+    {
+        match inst.variable {
+            Some(uuid) => self
+                .variable(uuid)
+                .map(|v| v.value())
+                .ok_or(EmulatorError::UnknownVariable(uuid)),
+            None => Ok(&inst.direct),
+        }
+    }
+}
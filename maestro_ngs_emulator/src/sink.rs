@@ -0,0 +1,108 @@
+use crate::{csv_field, Action, MachineState};
+use std::io::Write;
+
+/// Receives every step of a run from `Emulator::run_with_sink`, with the state of the machine
+/// after the step
+pub trait TraceSink {
+    fn emit(&mut self, action: &Action, state: &MachineState) -> std::io::Result<()>;
+}
+
+/// One JSON object per line, with the action and the machine state
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLinesSink { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TraceSink for JsonLinesSink<W> {
+    fn emit(&mut self, action: &Action, state: &MachineState) -> std::io::Result<()> {
+        let line = serde_json::json!({ "action": action, "state": state });
+        writeln!(self.writer, "{}", line)
+    }
+}
+
+/// Comma separated values, with a header before the first step
+pub struct CsvSink<W: Write> {
+    writer: W,
+    header_written: bool,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W) -> Self {
+        CsvSink {
+            writer,
+            header_written: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TraceSink for CsvSink<W> {
+    fn emit(&mut self, action: &Action, state: &MachineState) -> std::io::Result<()> {
+        if !self.header_written {
+            writeln!(
+                self.writer,
+                "method,line,skip,instruction,deck_location,tips_loaded,tip_volume"
+            )?;
+            self.header_written = true;
+        }
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{}",
+            action.method,
+            action.line,
+            action.skip,
+            action.execute.name(),
+            csv_field(state.deck_location.as_deref().unwrap_or("")),
+            state.tips_loaded,
+            state.tip_volume
+        )
+    }
+}
+
+/// A line of plain text per step, for reading in a terminal
+pub struct TextSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> TextSink<W> {
+    pub fn new(writer: W) -> Self {
+        TextSink { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TraceSink for TextSink<W> {
+    fn emit(&mut self, action: &Action, state: &MachineState) -> std::io::Result<()> {
+        let skipped = if action.skip { " (skipped)" } else { "" };
+        let tips = if state.tips_loaded {
+            format!("tips hold {} uL", state.tip_volume)
+        } else {
+            "no tips".to_string()
+        };
+        writeln!(
+            self.writer,
+            "{} line {}: {:?}{}, at {}, {}",
+            action.method,
+            action.line,
+            action.execute,
+            skipped,
+            state.deck_location.as_deref().unwrap_or("no location"),
+            tips
+        )
+    }
+}
@@ -1,47 +1,450 @@
-use lazy_static;
 use maestro_ngs_application::{self, SavedApplication};
-use maestro_ngs_emulator;
-use rocket;
+use maestro_ngs_emulator::ScicloneG3Emulator;
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::ContentType;
+use rocket::response::content::RawJson;
+use rocket::response::status::BadRequest;
 
-use std::sync::Mutex;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, RwLock};
+use std::thread;
 
-lazy_static::lazy_static! {
-    static ref ARRAY: Mutex<Option<SavedApplication>> = Mutex::new(None);
+// Request handlers and the stepper take the current application from under the lock, so a new
+// application can be loaded while others are still in use
+type SharedApp = RwLock<Arc<SavedApplication>>;
+
+fn current_app(app: &rocket::State<SharedApp>) -> Arc<SavedApplication> {
+    app.read().unwrap().clone()
+}
+
+// How far the step-by-step emulation of an application has come. The emulator borrows the
+// application, so it runs on a thread of its own that holds the application and keeps the
+// emulator between requests, answering the requests sent to it.
+struct Stepper {
+    app: Arc<SavedApplication>,
+    requests: mpsc::Sender<StepRequest>,
+}
+
+// What the emulation thread is asked for, with where to send the answer
+enum StepRequest {
+    Step(mpsc::Sender<Result<Option<serde_json::Value>, String>>),
+    Trace(mpsc::Sender<Result<serde_json::Value, String>>),
+    State(mpsc::Sender<Result<serde_json::Value, String>>),
+}
+
+impl StepRequest {
+    fn fail(self, message: String) {
+        match self {
+            StepRequest::Step(reply) => reply.send(Err(message)).ok(),
+            StepRequest::Trace(reply) | StepRequest::State(reply) => reply.send(Err(message)).ok(),
+        };
+    }
+}
+
+impl Stepper {
+    fn start(app: Arc<SavedApplication>) -> Self {
+        let (requests, received) = mpsc::channel();
+        let emulated = app.clone();
+        thread::spawn(move || emulate_on_request(&emulated, received));
+        Stepper { app, requests }
+    }
+
+    fn ask<T>(&self, request: impl FnOnce(mpsc::Sender<T>) -> StepRequest) -> Result<T, String> {
+        let (reply, answer) = mpsc::channel();
+        self.requests
+            .send(request(reply))
+            .map_err(|e| e.to_string())?;
+        answer.recv().map_err(|e| e.to_string())
+    }
+}
+
+// Emulate the application as far as the requests ask, until the stepper is dropped. An answer that
+// cannot be sent is dropped, as the one asking waits for it and is only gone if it has panicked.
+fn emulate_on_request(app: &SavedApplication, requests: mpsc::Receiver<StepRequest>) {
+    let mut emu = match ScicloneG3Emulator::new(app) {
+        Ok(emu) => emu,
+        Err(e) => {
+            for request in requests {
+                request.fail(e.to_string());
+            }
+            return;
+        }
+    };
+    let mut actions = Vec::new();
+    for request in requests {
+        match request {
+            StepRequest::Step(reply) => {
+                let action = emu
+                    .next()
+                    .map(|action| action.map(|a| serde_json::to_value(a).unwrap()))
+                    .map_err(|e| e.to_string());
+                if let Ok(Some(action)) = &action {
+                    actions.push(action.clone());
+                }
+                reply.send(action).ok()
+            }
+            StepRequest::Trace(reply) => reply.send(Ok(actions.clone().into())).ok(),
+            StepRequest::State(reply) => {
+                let state = serde_json::from_str(&emu.final_state_json()).unwrap();
+                reply.send(Ok(state)).ok()
+            }
+        };
+    }
+}
+
+#[derive(rocket::Responder)]
+enum StepResponse {
+    Action(RawJson<String>),
+    #[response(status = 204)]
+    Done(()),
 }
 
 #[rocket::get("/count")]
-fn count(hit_count: &rocket::State<&ARRAY>) -> String {
-    format!(
-        "This is request #{}.",
-        hit_count.lock().unwrap().as_ref().unwrap().start_method()
-    )
+fn count(app: &rocket::State<SharedApp>) -> String {
+    let app = current_app(app);
+    let name = app.start_method_name().unwrap_or("unknown");
+    format!("The application starts with method {}.", name)
+}
+
+// The id and name of each method, in the order of the export
+#[rocket::get("/methods")]
+fn methods(app: &rocket::State<SharedApp>) -> (ContentType, String) {
+    (ContentType::JSON, method_list(app).to_string())
+}
+
+fn method_list(app: &SharedApp) -> serde_json::Value {
+    let app = app.read().unwrap().clone();
+    let methods: Vec<serde_json::Value> = app
+        .methods_in_order()
+        .into_iter()
+        .map(|id| serde_json::json!({ "id": id.to_string(), "name": app.name_method(id) }))
+        .collect();
+    methods.into()
+}
+
+// Emulate the next instruction and give the action as JSON, starting over if another application
+// has been loaded
+#[rocket::post("/step")]
+fn step(
+    app: &rocket::State<SharedApp>,
+    stepper: &rocket::State<Mutex<Option<Stepper>>>,
+) -> Result<StepResponse, BadRequest<String>> {
+    match step_once(app, stepper).map_err(BadRequest)? {
+        Some(action) => Ok(StepResponse::Action(RawJson(action.to_string()))),
+        None => Ok(StepResponse::Done(())),
+    }
+}
+
+// The actions of the steps taken so far
+#[rocket::get("/trace")]
+fn trace(
+    app: &rocket::State<SharedApp>,
+    stepper: &rocket::State<Mutex<Option<Stepper>>>,
+) -> Result<RawJson<String>, BadRequest<String>> {
+    let actions = stepped_trace(app, stepper).map_err(BadRequest)?;
+    Ok(RawJson(actions.to_string()))
+}
+
+// The state of the machine after the steps taken so far
+#[rocket::get("/state")]
+fn state(
+    app: &rocket::State<SharedApp>,
+    stepper: &rocket::State<Mutex<Option<Stepper>>>,
+) -> Result<RawJson<String>, BadRequest<String>> {
+    let state = stepped_state(app, stepper).map_err(BadRequest)?;
+    Ok(RawJson(state.to_string()))
+}
+
+// The stepper of the current application, which starts over if the application has changed
+fn synced_stepper<'s>(
+    app: &SharedApp,
+    stepper: &'s Mutex<Option<Stepper>>,
+) -> MutexGuard<'s, Option<Stepper>> {
+    let app = app.read().unwrap().clone();
+    let mut stepper = stepper.lock().unwrap();
+    if !matches!(&*stepper, Some(stepped) if Arc::ptr_eq(&stepped.app, &app)) {
+        *stepper = Some(Stepper::start(app));
+    }
+    stepper
+}
+
+// The next action, or None once the emulation is done
+fn step_once(
+    app: &SharedApp,
+    stepper: &Mutex<Option<Stepper>>,
+) -> Result<Option<serde_json::Value>, String> {
+    let stepper = synced_stepper(app, stepper);
+    stepper.as_ref().unwrap().ask(StepRequest::Step)?
+}
+
+fn stepped_trace(
+    app: &SharedApp,
+    stepper: &Mutex<Option<Stepper>>,
+) -> Result<serde_json::Value, String> {
+    let stepper = synced_stepper(app, stepper);
+    stepper.as_ref().unwrap().ask(StepRequest::Trace)?
+}
+
+fn stepped_state(
+    app: &SharedApp,
+    stepper: &Mutex<Option<Stepper>>,
+) -> Result<serde_json::Value, String> {
+    let stepper = synced_stepper(app, stepper);
+    stepper.as_ref().unwrap().ask(StepRequest::State)?
+}
+
+// Start the step-by-step emulation over
+#[rocket::post("/reset")]
+fn reset(stepper: &rocket::State<Mutex<Option<Stepper>>>) {
+    *stepper.lock().unwrap() = None;
+}
+
+// Replace the application with the one exported in the body
+#[rocket::post("/load", data = "<body>")]
+async fn load(
+    body: Data<'_>,
+    app: &rocket::State<SharedApp>,
+) -> Result<String, BadRequest<String>> {
+    let xml = read_body(body).await.map_err(BadRequest)?;
+    load_xml(&xml, app).map_err(BadRequest)
+}
+
+async fn read_body(body: Data<'_>) -> Result<String, String> {
+    let limit = 64.mebibytes();
+    let text = body
+        .open(limit)
+        .into_string()
+        .await
+        .map_err(|e| e.to_string())?;
+    if text.is_complete() {
+        Ok(text.into_inner())
+    } else {
+        Err(format!("body is larger than {}", limit))
+    }
+}
+
+fn load_xml(xml: &str, app: &SharedApp) -> Result<String, String> {
+    let loaded = maestro_ngs_application::Loader::new(xml)
+        .and_then(|loader| loader.build_application())
+        .map_err(|e| e.to_string())?;
+    let message = format!(
+        "Loaded the application starting with method {}.",
+        loaded.start_method_name().unwrap_or("unknown")
+    );
+    *app.write().unwrap() = Arc::new(loaded);
+    Ok(message)
+}
+
+// Run a JSON array of operations like {"op": "load", "xml": "..."}, {"op": "step"} in order, and
+// give an array with {"ok": result} or {"error": message} for each. An operation that fails does
+// not stop the ones after it. A step past the end has a null result.
+#[rocket::post("/rpc", data = "<body>")]
+async fn rpc(
+    body: Data<'_>,
+    app: &rocket::State<SharedApp>,
+    stepper: &rocket::State<Mutex<Option<Stepper>>>,
+) -> Result<RawJson<String>, BadRequest<String>> {
+    let body = read_body(body).await.map_err(BadRequest)?;
+    let operations: Vec<serde_json::Value> =
+        serde_json::from_str(&body).map_err(|e| BadRequest(e.to_string()))?;
+    let results: Vec<serde_json::Value> = operations
+        .iter()
+        .map(|operation| match run_operation(operation, app, stepper) {
+            Ok(result) => serde_json::json!({ "ok": result }),
+            Err(message) => serde_json::json!({ "error": message }),
+        })
+        .collect();
+    Ok(RawJson(serde_json::Value::from(results).to_string()))
 }
 
-fn load_app() -> Result<(), std::io::Error> {
+fn run_operation(
+    operation: &serde_json::Value,
+    app: &SharedApp,
+    stepper: &Mutex<Option<Stepper>>,
+) -> Result<serde_json::Value, String> {
+    match operation["op"].as_str() {
+        Some("load") => {
+            let xml = operation["xml"]
+                .as_str()
+                .ok_or("load needs the application in \"xml\"")?;
+            load_xml(xml, app).map(Into::into)
+        }
+        Some("list_methods") => Ok(method_list(app)),
+        Some("step") => step_once(app, stepper).map(Into::into),
+        Some("trace") => stepped_trace(app, stepper),
+        Some("reset") => {
+            *stepper.lock().unwrap() = None;
+            Ok(serde_json::Value::Null)
+        }
+        Some("state") => stepped_state(app, stepper),
+        Some(op) => Err(format!("unknown operation {}", op)),
+        None => Err("operation has no \"op\"".to_string()),
+    }
+}
+
+fn load_app() -> Result<SavedApplication, Box<dyn std::error::Error>> {
     let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
     d.push("resources/test/Pipette_and_Mix.eap");
     let empty_app = std::fs::read_to_string(d)?;
 
-    let app = maestro_ngs_application::Loader::new(&empty_app).build_application();
-    let mut a = ARRAY.lock().unwrap();
-    *a = Some(app);
-    Ok(())
+    Ok(maestro_ngs_application::Loader::new(&empty_app)?.build_application()?)
+}
+
+fn rocket(app: SavedApplication) -> rocket::Rocket<rocket::Build> {
+    rocket::build()
+        .mount(
+            "/",
+            rocket::routes![count, load, methods, reset, rpc, state, step, trace],
+        )
+        .manage(SharedApp::new(Arc::new(app)))
+        .manage(Mutex::new(None::<Stepper>))
 }
 
 #[rocket::main]
 async fn main() {
-    match load_app() {
-        Ok(_) => {}
+    let app = match load_app() {
+        Ok(app) => app,
         Err(e) => {
             eprintln!("error: {:?}", e);
             std::process::exit(1);
         }
     };
 
-    rocket::build()
-        .mount("/", rocket::routes![count])
-        .manage(&ARRAY)
-        .launch()
-        .await
-        .unwrap();
+    rocket(app).launch().await.unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+
+    fn load_empty_app() -> String {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Application_Empty.eap");
+
+        std::fs::read_to_string(d).unwrap()
+    }
+
+    #[test]
+    fn load_empty_app_by_post() {
+        let client = Client::tracked(rocket(load_app().unwrap())).unwrap();
+
+        let response = client.post("/load").body(load_empty_app()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.into_string().unwrap(),
+            "Loaded the application starting with method Main."
+        );
+
+        let response = client.post("/load").body("<Application>").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn list_methods() {
+        let client = Client::tracked(rocket(load_app().unwrap())).unwrap();
+        client.post("/load").body(load_empty_app()).dispatch();
+
+        let response = client.get("/methods").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let methods: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(
+            methods,
+            serde_json::json!([
+                { "id": "3ac47c04-dcce-4036-8f9f-6ad7d530e220", "name": "Main" }
+            ])
+        );
+    }
+
+    #[test]
+    fn step_pipette_and_mix() {
+        let client = Client::tracked(rocket(load_app().unwrap())).unwrap();
+        let first_step = |client: &Client| {
+            let response = client.post("/step").dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let action: serde_json::Value =
+                serde_json::from_str(&response.into_string().unwrap()).unwrap();
+            action
+        };
+
+        let action = first_step(&client);
+        assert_eq!(action["line"], 0);
+        assert!(action["execute"].get("LoadTips").is_some());
+        for line in 1..5 {
+            let response = client.post("/step").dispatch();
+            let action: serde_json::Value =
+                serde_json::from_str(&response.into_string().unwrap()).unwrap();
+            assert_eq!(action["line"], line);
+        }
+        assert_eq!(client.post("/step").dispatch().status(), Status::NoContent);
+
+        client.post("/reset").dispatch();
+        assert!(first_step(&client)["execute"].get("LoadTips").is_some());
+    }
+
+    #[test]
+    fn trace_keeps_up_with_steps() {
+        let client = Client::tracked(rocket(load_app().unwrap())).unwrap();
+        let trace = |client: &Client| {
+            let response = client.get("/trace").dispatch();
+            let trace: serde_json::Value =
+                serde_json::from_str(&response.into_string().unwrap()).unwrap();
+            trace
+        };
+
+        for _ in 0..3 {
+            client.post("/step").dispatch();
+        }
+        let lines: Vec<_> = trace(&client)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|action| action["line"].clone())
+            .collect();
+        assert_eq!(lines, vec![0, 1, 2]);
+        let response = client.get("/state").dispatch();
+        let state: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(state["tips_loaded"], true);
+
+        client.post("/load").body(load_empty_app()).dispatch();
+        assert_eq!(trace(&client), serde_json::json!([]));
+    }
+
+    #[test]
+    fn batch_of_load_step_state() {
+        let client = Client::tracked(rocket(load_app().unwrap())).unwrap();
+        client.post("/load").body(load_empty_app()).dispatch();
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("resources/test/Pipette_and_Mix.eap");
+        let xml = std::fs::read_to_string(d).unwrap();
+        let batch = serde_json::json!([
+            { "op": "load", "xml": xml },
+            { "op": "step" },
+            { "op": "fly" },
+            { "op": "state" },
+        ]);
+
+        let response = client.post("/rpc").body(batch.to_string()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let results: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(
+            results[0]["ok"],
+            "Loaded the application starting with method Main."
+        );
+        assert!(results[1]["ok"]["execute"].get("LoadTips").is_some());
+        assert_eq!(results[2]["error"], "unknown operation fly");
+        assert_eq!(results[3]["ok"]["tips_loaded"], true);
+
+        let response = client.get("/trace").dispatch();
+        let trace: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(trace.as_array().unwrap().len(), 1);
+
+        let response = client.post("/rpc").body("{}").dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
 }